@@ -0,0 +1,53 @@
+use crate::board::*;
+use crate::search::{Limits, Search, SearchOptions};
+use std::time::{Duration, Instant};
+
+/// Middlegame, tactical and endgame positions searched by the bench
+pub const BENCH_FENS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+    "8/8/4k3/8/2p5/8/B2K4/8 w - - 0 1",
+];
+
+pub struct BenchResult {
+    /// Nodes searched in each of the bench positions
+    pub nodes: Vec<u64>,
+    pub time: Duration,
+}
+
+impl BenchResult {
+    pub fn total(&self) -> u64 {
+        self.nodes.iter().sum()
+    }
+
+    pub fn nps(&self) -> u64 {
+        (self.total() as f64 / self.time.as_secs_f64().max(1e-9)) as u64
+    }
+}
+
+/// Searches every bench position to `depth` with a fresh search, so the node
+/// counts only change when the search itself does
+pub fn bench(depth: u8, options: SearchOptions) -> BenchResult {
+    let start = Instant::now();
+    let nodes = BENCH_FENS
+        .iter()
+        .map(|fen| {
+            let game = GameState::from(fen.to_string());
+            let mut search = Search::new(Limits {
+                depth: Some(depth),
+                ..Default::default()
+            });
+            search.options = options;
+            search.run(&game, &[]).nodes
+        })
+        .collect();
+    BenchResult {
+        nodes,
+        time: start.elapsed(),
+    }
+}
@@ -0,0 +1,35 @@
+use rustle::bench::{bench, BENCH_FENS};
+use rustle::search::SearchOptions;
+use std::process::exit;
+
+const USAGE: &str = "usage: bench [depth] [--no-pvs] [--no-null-move] [--no-lmr] [--no-rfp] \
+                     [--no-futility] [--no-aspiration] [--no-check-extensions]";
+
+fn main() {
+    let mut depth = 6;
+    let mut options = SearchOptions::default();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--no-pvs" => options.pvs = false,
+            "--no-null-move" => options.null_move = false,
+            "--no-lmr" => options.lmr = false,
+            "--no-rfp" => options.reverse_futility = false,
+            "--no-futility" => options.futility = false,
+            "--no-aspiration" => options.aspiration = false,
+            "--no-check-extensions" => options.check_extensions = false,
+            _ => match arg.parse() {
+                Ok(d) => depth = d,
+                Err(_) => {
+                    eprintln!("{}", USAGE);
+                    exit(1);
+                }
+            },
+        }
+    }
+
+    let result = bench(depth, options);
+    for (fen, nodes) in BENCH_FENS.iter().zip(&result.nodes) {
+        println!("{:>10}  {}", nodes, fen);
+    }
+    println!("{} nodes {} nps", result.total(), result.nps());
+}
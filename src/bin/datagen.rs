@@ -0,0 +1,55 @@
+use rustle::datagen::{generate, DatagenConfig};
+use rustle::search::{Limits, MAX_PLY};
+use std::fs::File;
+use std::io::BufWriter;
+use std::process::exit;
+
+const USAGE: &str = "usage: datagen [--games N] [--threads N] [--nodes N | --depth N] \
+                     [--random-plies N] [--max-plies N] [--seed N] [--out FILE]";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{msg}");
+    exit(1);
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut config = DatagenConfig::default();
+    let mut out = String::from("data.txt");
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| fail(USAGE));
+        let number = || value.parse().unwrap_or_else(|_| fail(USAGE));
+        match arg.as_str() {
+            "--games" => config.games = number() as usize,
+            "--threads" => config.threads = number() as usize,
+            "--nodes" => {
+                config.limits = Limits {
+                    nodes: Some(number()),
+                    ..Default::default()
+                }
+            }
+            "--depth" => {
+                let depth = u8::try_from(number())
+                    .ok()
+                    .filter(|&depth| (depth as usize) < MAX_PLY)
+                    .unwrap_or_else(|| fail(&format!("--depth must be below {MAX_PLY}")));
+                config.limits = Limits {
+                    depth: Some(depth),
+                    ..Default::default()
+                }
+            }
+            "--random-plies" => config.random_plies = number() as usize,
+            "--max-plies" => config.max_plies = number() as usize,
+            "--seed" => config.seed = number(),
+            "--out" => out = value,
+            _ => fail(USAGE),
+        }
+    }
+
+    let file = File::create(&out).unwrap_or_else(|e| fail(&format!("{out}: {e}")));
+    let stats = generate(&config, BufWriter::new(file)).unwrap_or_else(|e| fail(&e.to_string()));
+    println!(
+        "{} games, {} positions, {} duplicates skipped",
+        stats.games, stats.positions, stats.duplicates
+    );
+}
@@ -0,0 +1,84 @@
+use rustle::fen::read_epd;
+use rustle::mate::solve;
+use std::fs::File;
+use std::io::BufReader;
+use std::process::exit;
+
+const USAGE: &str = "usage: mate <puzzles.epd> [--max N]";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{msg}");
+    exit(1);
+}
+
+/// Solves every puzzle of an EPD file, up to its `dm` moves or `--max`,
+/// reporting puzzles without the stated mate, with a different key than `bm`
+/// or with more than one key
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut path = None;
+    let mut max = 3;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max" => {
+                max = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| fail(USAGE))
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => fail(USAGE),
+        }
+    }
+    let path = path.unwrap_or_else(|| fail(USAGE));
+    let file = File::open(&path).unwrap_or_else(|e| fail(&format!("{path}: {e}")));
+
+    let mut failures = 0;
+    for (i, epd) in read_epd(BufReader::new(file)).enumerate() {
+        let epd = epd.unwrap_or_else(|e| fail(&format!("{path}:{}: {e}", i + 1)));
+        let name = epd
+            .id()
+            .map_or_else(|| format!("#{}", i + 1), str::to_string);
+        let expected = epd.direct_mate();
+        let Some(solution) = solve(&epd.game, expected.unwrap_or(max)) else {
+            println!("{name}: no mate found");
+            failures += 1;
+            continue;
+        };
+
+        let mut game = epd.game.clone();
+        let mut line = vec![];
+        for mov in &solution.line {
+            line.push(game.san(mov));
+            game = game.apply(mov.clone());
+        }
+        let mut problems = vec![];
+        if expected.is_some_and(|n| n != solution.moves) {
+            problems.push(format!("shorter than dm {}", expected.unwrap()));
+        }
+        let best = epd.best_moves();
+        if !best.is_empty() && !solution.keys.iter().all(|k| best.contains(k)) {
+            problems.push(String::from("key not in bm"));
+        }
+        if !solution.is_unique() {
+            let keys: Vec<_> = solution.keys.iter().map(|k| epd.game.san(k)).collect();
+            problems.push(format!("{} keys: {}", keys.len(), keys.join(" ")));
+        }
+        if !problems.is_empty() {
+            failures += 1;
+        }
+        println!(
+            "{name}: mate in {}: {} ({} nodes){}",
+            solution.moves,
+            line.join(" "),
+            solution.nodes,
+            problems
+                .iter()
+                .map(|p| format!(", {p}"))
+                .collect::<String>()
+        );
+    }
+    if failures > 0 {
+        exit(1);
+    }
+}
@@ -0,0 +1,64 @@
+use rustle::eval::DEFAULT_WEIGHTS;
+use rustle::tune::{load_weights, read_dataset, rust_source, save_weights, Tuner};
+use std::fs::File;
+use std::io::BufReader;
+use std::process::exit;
+
+const USAGE: &str = "usage: tune <dataset> [--epochs N] [--rate R] [--weights FILE] \
+                     [--out FILE] [--rust FILE]";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{msg}");
+    exit(1);
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut dataset = None;
+    let mut epochs = 1000;
+    let mut rate = 1.0;
+    let mut weights_in = None;
+    let mut out = String::from("weights.txt");
+    let mut rust = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+        match arg.as_str() {
+            "--epochs" => epochs = value().parse().unwrap_or_else(|_| fail(USAGE)),
+            "--rate" => rate = value().parse().unwrap_or_else(|_| fail(USAGE)),
+            "--weights" => weights_in = Some(value()),
+            "--out" => out = value(),
+            "--rust" => rust = Some(value()),
+            _ if dataset.is_none() => dataset = Some(arg),
+            _ => fail(USAGE),
+        }
+    }
+    let dataset = dataset.unwrap_or_else(|| fail(USAGE));
+
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .unwrap_or_else(|e| fail(&format!("{path}: {e}")))
+    };
+    let samples = read_dataset(open(&dataset)).unwrap_or_else(|e| fail(&e.to_string()));
+    let weights = match &weights_in {
+        Some(path) => load_weights(open(path)).unwrap_or_else(|e| fail(&e.to_string())),
+        None => DEFAULT_WEIGHTS,
+    };
+    println!("{} positions", samples.len());
+
+    let mut tuner = Tuner::new(samples, &weights);
+    tuner.find_k();
+    println!("K = {:.6}, error {:.6}", tuner.k(), tuner.error());
+    for epoch in 1..=epochs {
+        let error = tuner.epoch(rate);
+        if epoch % 50 == 0 || epoch == epochs {
+            println!("epoch {epoch} error {error:.6}");
+            let file = File::create(&out).unwrap_or_else(|e| fail(&format!("{out}: {e}")));
+            save_weights(&tuner.weights(), file).unwrap_or_else(|e| fail(&e.to_string()));
+        }
+    }
+    if let Some(path) = rust {
+        std::fs::write(&path, rust_source(&tuner.weights()))
+            .unwrap_or_else(|e| fail(&format!("{path}: {e}")));
+    }
+}
@@ -0,0 +1,16 @@
+use rustle::uci::Uci;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+
+fn main() {
+    let mut uci = Uci::new(Arc::new(Mutex::new(std::io::stdout())));
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if !uci.handle(&line) {
+            return;
+        }
+    }
+    uci.handle("quit");
+}
@@ -0,0 +1,16 @@
+use rustle::xboard::XBoard;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+
+fn main() {
+    let mut xboard = XBoard::new(Arc::new(Mutex::new(std::io::stdout())));
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if !xboard.handle(&line) {
+            return;
+        }
+    }
+    xboard.handle("quit");
+}
@@ -1,14 +1,26 @@
+use crate::variant::Variant;
+
 // TODO: Clean up and document the entire codebase because this is terrible
 // TODO: Write Tests
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct GameState {
     pub state: [[BitBoard; 6]; 2],
     pub turn: Sides, // TODO: turn to 1 bit bool?
     pub castle_rights: u8,
+    /// Rook square each castling right is tied to, indexed by `Castle::index`
+    pub castle_rooks: [Square; 4],
+    pub chess960: bool,
     pub en_passant: Option<Square>,
-    // halfmoves: usize,
-    // fullmoves: usize,
+    pub halfmoves: u32,
+    pub fullmoves: u32,
+    pub variant: Variant,
+    /// Crazyhouse pieces in hand, indexed by side and piece
+    pub pockets: [[u8; 6]; 2],
+    /// Crazyhouse squares holding promoted pieces, which are captured as Pawns
+    pub promoted: BitBoard,
+    /// Three-check checks given by each side
+    pub checks: [u8; 2],
 }
 
 impl GameState {
@@ -16,12 +28,16 @@ impl GameState {
         &self.state[side as usize][piece as usize]
     }
 
+    pub fn castle_rook(&self, castle: Castle) -> Square {
+        self.castle_rooks[castle.index()]
+    }
+
     pub fn board_mut(&mut self, side: Sides, piece: Piece) -> &mut BitBoard {
         &mut self.state[side as usize][piece as usize]
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BitBoard(pub u64);
 
 impl BitBoard {
@@ -47,7 +63,7 @@ impl From<Vec<u8>> for BitBoard {
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Castle {
     WhiteQueen = 1u8 << 0,
     WhiteKing = 1u8 << 1,
@@ -55,8 +71,39 @@ pub enum Castle {
     BlackKing = 1u8 << 3,
 }
 
+pub const CASTLES: [Castle; 4] = [
+    Castle::WhiteQueen,
+    Castle::WhiteKing,
+    Castle::BlackQueen,
+    Castle::BlackKing,
+];
+
+impl Castle {
+    pub fn index(self) -> usize {
+        (self as u8).trailing_zeros() as usize
+    }
+
+    pub fn side(self) -> Sides {
+        match self {
+            Castle::WhiteQueen | Castle::WhiteKing => Sides::White,
+            Castle::BlackQueen | Castle::BlackKing => Sides::Black,
+        }
+    }
+
+    /// Destination squares of the King and Rook, which are the same in Chess960
+    pub fn targets(self) -> (Square, Square) {
+        use Square::*;
+        match self {
+            Castle::WhiteQueen => (C1, D1),
+            Castle::WhiteKing => (G1, F1),
+            Castle::BlackQueen => (C8, D8),
+            Castle::BlackKing => (G8, F8),
+        }
+    }
+}
+
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Sides {
     White,
     Black,
@@ -82,13 +129,13 @@ pub const PIECES: [Piece; 6] = [
 
 #[rustfmt::skip]
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Piece { Pawn, Rook, Knight, Bishop, Queen, King }
 // }
 
 #[rustfmt::skip]
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd)]
 pub enum Square {
     A8, B8, C8, D8, E8, F8, G8, H8,
     A7, B7, C7, D7, E7, F7, G7, H7,
@@ -134,7 +181,40 @@ impl From<Square> for String {
 #[derive(Clone, Copy, Debug)]
 pub enum Direction { N, E, S, W, NE, SE, NW, SW }
 
+impl TryFrom<&str> for Square {
+    type Error = ();
+
+    /// Parses lowercase or uppercase algebraic coordinates such as "e4"
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        let mut chars = name.chars();
+        let file = chars.next().ok_or(())?.to_ascii_lowercase();
+        let rank = chars.next().ok_or(())?;
+        if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+            return Err(());
+        }
+        Ok(Square::at(file as u8 - b'a', rank as u8 - b'1'))
+    }
+}
+
 impl Square {
+    /// Square on `file` (0 = A) and `rank` (0 = first rank)
+    pub fn at(file: u8, rank: u8) -> Square {
+        Square::from((7 - rank) * 8 + file)
+    }
+
+    pub fn file(&self) -> u8 {
+        *self as u8 % 8
+    }
+
+    pub fn rank(&self) -> u8 {
+        7 - *self as u8 / 8
+    }
+
+    /// Lowercase algebraic name, as used by UCI, SAN and FEN
+    pub fn name(&self) -> String {
+        String::from(*self).to_lowercase()
+    }
+
     pub fn step(&self, dir: Direction) -> Option<Square> {
         use Square::*;
         let sq = *self as u8;
@@ -143,7 +223,7 @@ impl Square {
             Direction::N => Some(Square::from(sq - 8)),
             Direction::S if sq >= A1 as u8 => None,
             Direction::S => Some(Square::from(sq + 8)),
-            Direction::W if sq % 8 == 0 => None,
+            Direction::W if sq.is_multiple_of(8) => None,
             Direction::W => Some(Square::from(sq - 1)),
             Direction::E if sq % 8 == 7 => None,
             Direction::E => Some(Square::from(sq + 1)),
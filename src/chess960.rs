@@ -0,0 +1,119 @@
+use crate::board::*;
+
+/// Knight placements on the five squares left after the Bishops and Queen
+const KNIGHTS: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+/// Back rank of Chess960 start position `index` by Scharnagl numbering,
+/// where 518 is the standard setup
+pub fn back_rank(index: u16) -> [Piece; 8] {
+    assert!(index < 960, "Invalid Chess960 position index");
+    let mut n = index as usize;
+    let mut rank = [None; 8];
+
+    rank[(n % 4) * 2 + 1] = Some(Piece::Bishop);
+    n /= 4;
+    rank[(n % 4) * 2] = Some(Piece::Bishop);
+    n /= 4;
+
+    let mut place = |skip: usize, piece: Piece| {
+        let file = (0..8).filter(|&f| rank[f].is_none()).nth(skip).unwrap();
+        rank[file] = Some(piece);
+    };
+    place(n % 6, Piece::Queen);
+    n /= 6;
+
+    let (first, second) = KNIGHTS[n];
+    place(second, Piece::Knight);
+    place(first, Piece::Knight);
+
+    for piece in [Piece::Rook, Piece::King, Piece::Rook] {
+        place(0, piece);
+    }
+
+    rank.map(Option::unwrap)
+}
+
+impl GameState {
+    pub fn chess960_start(index: u16) -> GameState {
+        let rank: String = back_rank(index).iter().map(Piece::symbol).collect();
+        let fen = format!(
+            "{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1",
+            rank.to_lowercase(),
+            rank
+        );
+        let mut game = GameState::from(fen);
+        game.chess960 = true;
+        game
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moves::Move;
+
+    fn perft(game: &GameState, depth: u8) -> usize {
+        if depth == 0 {
+            return 1;
+        }
+        game.moves()
+            .into_iter()
+            .map(|m: Move| perft(&game.apply(m), depth - 1))
+            .sum()
+    }
+
+    #[test]
+    fn start_positions() {
+        let symbols = |index| -> String { back_rank(index).iter().map(Piece::symbol).collect() };
+        assert_eq!(symbols(518), "RNBQKBNR");
+        assert_eq!(symbols(0), "BBQNNRKR");
+        assert_eq!(symbols(959), "RKRNNQBB");
+        assert_eq!(
+            GameState::chess960_start(0).fen(),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1"
+        );
+    }
+
+    // https://www.chessprogramming.org/Chess960_Perft_Results
+    #[test]
+    fn shredder_perft() {
+        for (fen, nodes) in [
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                528,
+            ),
+            (
+                "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+                807,
+            ),
+            (
+                "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+                479,
+            ),
+        ] {
+            let game = GameState::from(fen.to_string());
+            assert_eq!(game.shredder_fen(), fen);
+            assert_eq!(perft(&game, 2), nodes);
+        }
+    }
+
+    #[test]
+    fn king_takes_rook() {
+        let game = GameState::from("rk2r3/8/8/8/8/8/8/RK2R3 w AEae - 0 1".to_string());
+        let castle = game.parse_uci("b1a1").unwrap();
+        assert!(castle.castle.is_some());
+        let next = game.apply(castle);
+        assert_eq!(next.fen(), "rk2r3/8/8/8/8/8/8/2KRR3 b kq - 1 1");
+    }
+}
@@ -0,0 +1,99 @@
+use crate::board::*;
+use crate::moves::Move;
+
+/// Pieces that can be held in a pocket, in the order they are written in FEN
+pub const POCKET_PIECES: [Piece; 5] = [
+    Piece::Queen,
+    Piece::Rook,
+    Piece::Bishop,
+    Piece::Knight,
+    Piece::Pawn,
+];
+
+impl GameState {
+    pub fn pocket(&self, side: Sides, piece: Piece) -> u8 {
+        self.pockets[side as usize][piece as usize]
+    }
+
+    /// Drops of every pocketed piece onto empty squares, with Pawns kept off
+    /// the first and last ranks
+    pub(crate) fn pseudo_drops(&self, block_board: &BitBoard) -> Vec<Move> {
+        let mut moves = vec![];
+        for piece in POCKET_PIECES {
+            if self.pocket(self.turn, piece) == 0 {
+                continue;
+            }
+            for square in SQUARES {
+                if block_board.get(square) {
+                    continue;
+                }
+                if piece == Piece::Pawn && (square.rank() == 0 || square.rank() == 7) {
+                    continue;
+                }
+                moves.push(Move {
+                    piece,
+                    from: square,
+                    to: square,
+                    capture: None,
+                    castle: None,
+                    promotion: None,
+                    drop: true,
+                });
+            }
+        }
+        moves
+    }
+
+    /// Pocket contents in FEN bracket notation, e.g. "QNpp"
+    pub(crate) fn pocket_field(&self) -> String {
+        let mut field = String::new();
+        for side in [Sides::White, Sides::Black] {
+            for piece in POCKET_PIECES {
+                for _ in 0..self.pocket(side, piece) {
+                    field.push(match side {
+                        Sides::White => piece.symbol(),
+                        Sides::Black => piece.symbol().to_ascii_lowercase(),
+                    });
+                }
+            }
+        }
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    #[test]
+    fn captures_fill_pockets() {
+        let fen = "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR[] w KQkq - 0 2";
+        let game = GameState::from(fen.to_string());
+        assert_eq!(game.variant, Variant::Crazyhouse);
+
+        let game = game.apply(game.parse_san("exd5").unwrap());
+        assert_eq!(game.pocket(Sides::White, Piece::Pawn), 1);
+        let game = game.apply(game.parse_san("Qxd5").unwrap());
+        let drop = game.parse_san("P@e6").unwrap();
+        assert_eq!(game.uci(&drop), "P@e6");
+        assert_eq!(game.san(&drop), "P@e6");
+        assert_eq!(
+            game.apply(drop).fen(),
+            "rnb1kbnr/ppp1pppp/4P3/3q4/8/8/PPPP1PPP/RNBQKBNR[p] b KQkq - 0 3"
+        );
+    }
+
+    #[test]
+    fn promoted_pieces_demote() {
+        let fen = "4k3/1P6/8/8/8/8/1r6/4K3[] w - - 0 1";
+        let game = GameState::from(fen.to_string());
+        let game = game.apply(game.parse_san("b8=Q+").unwrap());
+        assert_eq!(game.fen(), "1Q~2k3/8/8/8/8/8/1r6/4K3[] b - - 0 1");
+
+        let game = game.apply(game.parse_san("Rxb8").unwrap());
+        assert_eq!(game.pocket(Sides::Black, Piece::Queen), 0);
+        assert_eq!(game.pocket(Sides::Black, Piece::Pawn), 1);
+        assert_eq!(game.fen(), "1r2k3/8/8/8/8/8/8/4K3[p] w - - 0 2");
+    }
+}
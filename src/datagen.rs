@@ -0,0 +1,203 @@
+use crate::board::*;
+use crate::fen::START_FEN;
+use crate::search::{Limits, Search, MATE_BOUND};
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+#[derive(Clone, Debug)]
+pub struct DatagenConfig {
+    pub games: usize,
+    pub threads: usize,
+    /// Search limits for every move, usually a fixed node count or depth
+    pub limits: Limits,
+    /// Random moves played from the start before searching, so games differ
+    pub random_plies: usize,
+    /// Games still going after this many plies are scored as draws
+    pub max_plies: usize,
+    pub seed: u64,
+}
+
+impl Default for DatagenConfig {
+    fn default() -> Self {
+        DatagenConfig {
+            games: 100,
+            threads: 1,
+            limits: Limits {
+                nodes: Some(5000),
+                ..Default::default()
+            },
+            random_plies: 8,
+            max_plies: 400,
+            seed: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DatagenStats {
+    pub games: usize,
+    pub positions: usize,
+    pub duplicates: usize,
+}
+
+/// A quiet position from a game with its search score from White's side
+struct Record {
+    fen: String,
+    hash: u64,
+    score: i32,
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Plays one self-play game, returning its quiet positions and White's
+/// result, or none when the random opening already ended the game
+fn play_game(
+    config: &DatagenConfig,
+    search: &mut Search,
+    rng: &mut Rng,
+) -> Option<(Vec<Record>, f64)> {
+    let mut game = GameState::from(START_FEN.to_string());
+    let mut history = vec![];
+    for _ in 0..config.random_plies {
+        let moves = game.moves();
+        if moves.is_empty() {
+            return None;
+        }
+        let mov = moves[(rng.next() % moves.len() as u64) as usize].clone();
+        history.push(game.clone());
+        game = game.apply(mov);
+    }
+    if game.outcome().is_some() {
+        return None;
+    }
+
+    let mut records = vec![];
+    let result = loop {
+        if let Some(outcome) = game.outcome() {
+            break match outcome.winner() {
+                Some(Sides::White) => 1.0,
+                Some(Sides::Black) => 0.0,
+                None => 0.5,
+            };
+        }
+        let repetitions = history
+            .iter()
+            .filter(|prev| prev.hash() == game.hash())
+            .count();
+        if repetitions >= 2 || history.len() >= config.max_plies + config.random_plies {
+            break 0.5;
+        }
+
+        let result = search.run(&game, &history);
+        let best = result.best?;
+        let quiet = best.capture.is_none() && best.promotion.is_none() && !game.in_check();
+        if quiet && result.score.abs() < MATE_BOUND {
+            let score = match game.turn {
+                Sides::White => result.score,
+                Sides::Black => -result.score,
+            };
+            records.push(Record {
+                fen: game.fen(),
+                hash: game.hash(),
+                score,
+            });
+        }
+        history.push(game.clone());
+        game = game.apply(best);
+    };
+    Some((records, result))
+}
+
+/// Plays self-play games on `config.threads` threads and writes each quiet
+/// position once as a `fen | score | result` line, with the score in
+/// centipawns and the result as 1.0, 0.5 or 0.0, both from White's side
+pub fn generate<W: Write>(config: &DatagenConfig, mut writer: W) -> std::io::Result<DatagenStats> {
+    let next_game = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let mut stats = DatagenStats::default();
+    let mut seen = HashSet::new();
+
+    std::thread::scope(|scope| {
+        for _ in 0..config.threads.max(1) {
+            let sender = sender.clone();
+            let next_game = &next_game;
+            scope.spawn(move || {
+                let mut search = Search::new(config.limits.clone());
+                loop {
+                    let index = next_game.fetch_add(1, Ordering::Relaxed);
+                    if index >= config.games {
+                        break;
+                    }
+                    let seed = config.seed ^ (index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                    let mut rng = Rng(seed | 1);
+                    // Openings that end the game at once are replayed with new moves
+                    let game = loop {
+                        if let Some(game) = play_game(config, &mut search, &mut rng) {
+                            break game;
+                        }
+                    };
+                    if sender.send(game).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (records, result) in receiver {
+            stats.games += 1;
+            for record in records {
+                if !seen.insert(record.hash) {
+                    stats.duplicates += 1;
+                    continue;
+                }
+                writeln!(writer, "{} | {} | {:.1}", record.fen, record.score, result)?;
+                stats.positions += 1;
+            }
+        }
+        writer.flush()
+    })?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tune::read_dataset;
+
+    #[test]
+    fn self_play() {
+        let config = DatagenConfig {
+            games: 4,
+            threads: 2,
+            limits: Limits {
+                depth: Some(1),
+                ..Default::default()
+            },
+            max_plies: 12,
+            ..Default::default()
+        };
+        let mut out = vec![];
+        let stats = generate(&config, &mut out).unwrap();
+        assert_eq!(stats.games, 4);
+        assert!(stats.positions > 0);
+
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), stats.positions);
+        assert_eq!(
+            read_dataset(text.as_bytes()).unwrap().len(),
+            stats.positions
+        );
+    }
+}
@@ -0,0 +1,465 @@
+use crate::board::*;
+use crate::eval::PIECE_VALUES;
+use crate::variant::Variant;
+use std::sync::OnceLock;
+
+/// Scale factors are out of this, which leaves the evaluation as it is
+pub const SCALE_NORMAL: i32 = 64;
+
+/// Added to the score of endgames known to be won, which stays below
+/// tablebase and mate scores
+pub const KNOWN_WIN: i32 = 10000;
+
+/// Opposite coloured bishops with only pawns besides, and with one more
+/// Rook or minor piece each
+const OCB_SCALE: i32 = 24;
+const OCB_PIECES_SCALE: i32 = 48;
+
+/// What the material on the board says beyond the usual evaluation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Knowledge {
+    /// Its score, from the side to move's perspective
+    Score(i32),
+    /// The evaluation is scaled by this out of `SCALE_NORMAL`, as the
+    /// position is drawish
+    Scale(i32),
+}
+
+/// Applies what is known about the endgame to the evaluation `eval`, which
+/// isn't computed when the endgame has its own score
+pub fn evaluate(game: &GameState, eval: impl FnOnce() -> i32) -> i32 {
+    match knowledge(game) {
+        Some(Knowledge::Score(score)) => score,
+        Some(Knowledge::Scale(scale)) => eval() * scale / SCALE_NORMAL,
+        None => eval(),
+    }
+}
+
+fn count(game: &GameState, side: Sides, piece: Piece) -> u32 {
+    game.board(side, piece).0.count_ones()
+}
+
+/// Pieces other than the King and pawns
+fn pieces(game: &GameState, side: Sides) -> u32 {
+    [Piece::Rook, Piece::Knight, Piece::Bishop, Piece::Queen]
+        .into_iter()
+        .map(|piece| count(game, side, piece))
+        .sum()
+}
+
+fn square(game: &GameState, side: Sides, piece: Piece) -> Square {
+    Square::from(game.board(side, piece).0.trailing_zeros() as u8)
+}
+
+fn distance(a: Square, b: Square) -> i32 {
+    (a.file() as i32 - b.file() as i32)
+        .abs()
+        .max((a.rank() as i32 - b.rank() as i32).abs())
+}
+
+fn is_dark(sq: Square) -> bool {
+    (sq.file() + sq.rank()).is_multiple_of(2)
+}
+
+/// Higher the closer a King is to the edge
+fn push_to_edge(sq: Square) -> i32 {
+    let edge = |x: u8| x.min(7 - x) as i32;
+    90 - (7 * edge(sq.file()).pow(2) / 2 + 7 * edge(sq.rank()).pow(2) / 2)
+}
+
+/// Higher the closer two Kings are
+fn push_close(a: Square, b: Square) -> i32 {
+    140 - 20 * distance(a, b)
+}
+
+/// Higher the closer a King is to the a1 or h8 corner
+fn push_to_corner(sq: Square) -> i32 {
+    (7 - sq.rank() as i32 - sq.file() as i32).abs()
+}
+
+/// Knowledge of standard chess endgames, found from the material alone
+pub fn knowledge(game: &GameState) -> Option<Knowledge> {
+    if game.variant != Variant::Standard {
+        return None;
+    }
+    for strong in [Sides::White, Sides::Black] {
+        let weak = strong.switch();
+        if pieces(game, weak) + count(game, weak, Piece::Pawn) > 0 {
+            continue;
+        }
+        let sign = if game.turn == strong { 1 } else { -1 };
+        let pawns = count(game, strong, Piece::Pawn);
+        let (knights, bishops) = (
+            count(game, strong, Piece::Knight),
+            count(game, strong, Piece::Bishop),
+        );
+        let heavy = count(game, strong, Piece::Rook) + count(game, strong, Piece::Queen);
+        if pieces(game, strong) == 0 && pawns == 1 {
+            return Some(Knowledge::Score(sign * kpk(game, strong)));
+        }
+        // Minor pieces that can't force mate on their own
+        if pawns == 0 && heavy == 0 && matches!((knights, bishops), (1, 0) | (0, 1) | (2, 0)) {
+            return Some(Knowledge::Score(0));
+        }
+        let (king, weak_king) = (
+            square(game, strong, Piece::King),
+            square(game, weak, Piece::King),
+        );
+        if pawns == 0 && heavy == 0 && knights == 1 && bishops == 1 {
+            // Mate only happens in a corner the Bishop covers
+            let bishop = square(game, strong, Piece::Bishop);
+            let target = if is_dark(bishop) {
+                weak_king
+            } else {
+                Square::at(7 - weak_king.file(), weak_king.rank())
+            };
+            let score =
+                KNOWN_WIN + 3520 + push_close(king, weak_king) + 420 * push_to_corner(target);
+            return Some(Knowledge::Score(sign * score));
+        }
+        let bishops_board = game.board(strong, Piece::Bishop).0;
+        let both_colours = bishops_board & DARK_SQUARES != 0 && bishops_board & !DARK_SQUARES != 0;
+        if heavy > 0 || both_colours {
+            let material: i32 = [
+                Piece::Pawn,
+                Piece::Rook,
+                Piece::Knight,
+                Piece::Bishop,
+                Piece::Queen,
+            ]
+            .into_iter()
+            .map(|piece| count(game, strong, piece) as i32 * PIECE_VALUES[piece as usize])
+            .sum();
+            let score =
+                KNOWN_WIN + material + push_to_edge(weak_king) + push_close(king, weak_king);
+            return Some(Knowledge::Score(sign * score));
+        }
+    }
+    scale(game).map(Knowledge::Scale)
+}
+
+/// Squares the a1 Bishop moves on
+const DARK_SQUARES: u64 = 0x55AA_55AA_55AA_55AA;
+
+/// Scale factor for drawish material
+fn scale(game: &GameState) -> Option<i32> {
+    for strong in [Sides::White, Sides::Black] {
+        let weak = strong.switch();
+        // A Bishop that can't cover the promotion square of rook pawns, with
+        // the defending King there in time
+        let pawns = game.board(strong, Piece::Pawn).0;
+        let on_file = |file: u64| pawns != 0 && pawns & !(FILE_A << file) == 0;
+        if pieces(game, strong) == 1
+            && count(game, strong, Piece::Bishop) == 1
+            && pieces(game, weak) == 0
+        {
+            for file in [0, 7] {
+                if !on_file(file) {
+                    continue;
+                }
+                let promotion = match strong {
+                    Sides::White => Square::at(file as u8, 7),
+                    Sides::Black => Square::at(file as u8, 0),
+                };
+                let bishop = square(game, strong, Piece::Bishop);
+                let king = square(game, weak, Piece::King);
+                if is_dark(bishop) != is_dark(promotion) && distance(king, promotion) <= 1 {
+                    return Some(0);
+                }
+            }
+        }
+    }
+    let (white, black) = (
+        game.board(Sides::White, Piece::Bishop).0,
+        game.board(Sides::Black, Piece::Bishop).0,
+    );
+    if white.count_ones() == 1
+        && black.count_ones() == 1
+        && (white & DARK_SQUARES != 0) != (black & DARK_SQUARES != 0)
+    {
+        // With more on the board there is still play enough to win
+        let at_most = |n| {
+            [Sides::White, Sides::Black]
+                .into_iter()
+                .all(|side| pieces(game, side) <= n && count(game, side, Piece::Queen) == 0)
+        };
+        if at_most(1) {
+            return Some(OCB_SCALE);
+        }
+        if at_most(2) {
+            return Some(OCB_PIECES_SCALE);
+        }
+    }
+    None
+}
+
+const FILE_A: u64 = 0x0101_0101_0101_0101;
+
+// The KPK bitbase records which positions of King and Pawn against King
+// are won, for White with the Pawn on files a to d. Squares are numbered
+// from a1 here, rank by rank. Positions are found by retrograde analysis,
+// starting from the ones decided by the rules and the promotion race.
+
+/// King and Pawn against King positions by side to move, the Pawn on ranks 2
+/// to 7 and files a to d, and the two Kings
+const KPK_SIZE: usize = 2 * 24 * 64 * 64;
+
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+/// `stm` is 0 for White, the strong side
+fn kpk_index(stm: usize, black_king: usize, white_king: usize, pawn: usize) -> usize {
+    white_king | black_king << 6 | stm << 12 | (pawn % 8) << 13 | (6 - pawn / 8) << 15
+}
+
+fn kpk_distance(a: usize, b: usize) -> usize {
+    (a % 8).abs_diff(b % 8).max((a / 8).abs_diff(b / 8))
+}
+
+fn king_moves(sq: usize) -> impl Iterator<Item = usize> {
+    (0..64).filter(move |&to| kpk_distance(sq, to) == 1)
+}
+
+fn pawn_attacks(pawn: usize, sq: usize) -> bool {
+    sq / 8 == pawn / 8 + 1 && (sq % 8).abs_diff(pawn % 8) == 1
+}
+
+fn kpk_decode(idx: usize) -> (usize, usize, usize, usize) {
+    let pawn = (idx >> 13 & 3) + 8 * (6 - (idx >> 15 & 7));
+    (idx >> 12 & 1, idx >> 6 & 63, idx & 63, pawn)
+}
+
+/// The result known without looking at any moves
+fn kpk_initial(idx: usize) -> u8 {
+    let (stm, bk, wk, pawn) = kpk_decode(idx);
+    if kpk_distance(wk, bk) <= 1 || wk == pawn || bk == pawn || (stm == 0 && pawn_attacks(pawn, bk))
+    {
+        INVALID
+    } else if stm == 0
+        && pawn / 8 == 6
+        && wk != pawn + 8
+        && (kpk_distance(bk, pawn + 8) > 1 || kpk_distance(wk, pawn + 8) == 1)
+    {
+        // Promotes without the new Queen being taken
+        WIN
+    } else if stm == 1
+        && (king_moves(bk).all(|to| kpk_distance(wk, to) == 1 || pawn_attacks(pawn, to))
+            || (kpk_distance(bk, pawn) == 1 && kpk_distance(wk, pawn) > 1))
+    {
+        // Stalemate, or the Pawn is taken
+        DRAW
+    } else {
+        UNKNOWN
+    }
+}
+
+/// The result found from the positions after each move: White wins if a
+/// move wins and Black draws if a move draws
+fn kpk_classify(db: &[u8], idx: usize) -> u8 {
+    let (stm, bk, wk, pawn) = kpk_decode(idx);
+    let mut results = INVALID;
+    if stm == 0 {
+        for to in king_moves(wk) {
+            results |= db[kpk_index(1, bk, to, pawn)];
+        }
+        if pawn / 8 < 6 {
+            results |= db[kpk_index(1, bk, wk, pawn + 8)];
+        }
+        if pawn / 8 == 1 && pawn + 8 != wk && pawn + 8 != bk {
+            results |= db[kpk_index(1, bk, wk, pawn + 16)];
+        }
+    } else {
+        for to in king_moves(bk) {
+            results |= db[kpk_index(0, to, wk, pawn)];
+        }
+    }
+    let (good, bad) = if stm == 0 { (WIN, DRAW) } else { (DRAW, WIN) };
+    if results & good != 0 {
+        good
+    } else if results & UNKNOWN != 0 {
+        UNKNOWN
+    } else {
+        bad
+    }
+}
+
+/// One bit for each position, set when White wins
+fn kpk_bitbase() -> &'static [u64] {
+    static BITBASE: OnceLock<Vec<u64>> = OnceLock::new();
+    BITBASE.get_or_init(|| {
+        let mut db: Vec<u8> = (0..KPK_SIZE).map(kpk_initial).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for idx in 0..KPK_SIZE {
+                if db[idx] == UNKNOWN {
+                    db[idx] = kpk_classify(&db, idx);
+                    changed |= db[idx] != UNKNOWN;
+                }
+            }
+        }
+        let mut bits = vec![0; KPK_SIZE / 64];
+        for (idx, &result) in db.iter().enumerate() {
+            if result == WIN {
+                bits[idx / 64] |= 1 << (idx % 64);
+            }
+        }
+        bits
+    })
+}
+
+/// Builds the KPK bitbase, which takes a moment, so that searches don't
+/// stop to build it when they first reach the endgame
+pub fn init() {
+    kpk_bitbase();
+}
+
+/// Whether `strong`, with a King and Pawn against a bare King, wins
+pub fn kpk_wins(game: &GameState, strong: Sides) -> bool {
+    // Squares from a1, turned around so that the strong side plays up
+    let flip = match strong {
+        Sides::White => 56,
+        Sides::Black => 0,
+    };
+    let at = |side, piece| square(game, side, piece) as usize ^ flip;
+    let (mut wk, mut bk, mut pawn) = (
+        at(strong, Piece::King),
+        at(strong.switch(), Piece::King),
+        at(strong, Piece::Pawn),
+    );
+    if pawn % 8 > 3 {
+        (wk, bk, pawn) = (wk ^ 7, bk ^ 7, pawn ^ 7);
+    }
+    let stm = (game.turn != strong) as usize;
+    let idx = kpk_index(stm, bk, wk, pawn);
+    kpk_bitbase()[idx / 64] >> (idx % 64) & 1 != 0
+}
+
+/// Score of King and Pawn against King for `strong`, a known win that is
+/// higher the further the Pawn has gone, or a draw
+fn kpk(game: &GameState, strong: Sides) -> i32 {
+    if !kpk_wins(game, strong) {
+        return 0;
+    }
+    let pawn = square(game, strong, Piece::Pawn);
+    let rank = match strong {
+        Sides::White => pawn.rank(),
+        Sides::Black => 7 - pawn.rank(),
+    };
+    KNOWN_WIN + PIECE_VALUES[Piece::Pawn as usize] + rank as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(fen: &str) -> GameState {
+        GameState::from(fen.to_string())
+    }
+
+    fn known(fen: &str) -> Option<Knowledge> {
+        knowledge(&game(fen))
+    }
+
+    #[test]
+    fn kpk() {
+        // The King in front of its Pawn on the sixth rank wins either way
+        let win = "4k3/8/4K3/4P3/8/8/8/8 w - - 0 1";
+        assert!(kpk_wins(&game(win), Sides::White));
+        assert!(kpk_wins(&game(&win.replace(" w ", " b ")), Sides::White));
+        // Without the opposition it is a draw
+        assert!(!kpk_wins(
+            &game("8/8/8/8/8/4k3/4P3/4K3 w - - 0 1"),
+            Sides::White
+        ));
+        assert!(kpk_wins(
+            &game("4k3/8/8/8/8/4K3/4P3/8 w - - 0 1"),
+            Sides::White
+        ));
+        // Rook pawns with the defending King in the corner are drawn
+        assert!(!kpk_wins(
+            &game("k7/8/8/8/8/8/P7/7K w - - 0 1"),
+            Sides::White
+        ));
+        // Mirrored for Black, and to the other side of the board
+        assert!(kpk_wins(
+            &game("8/8/8/8/3p4/3k4/8/3K4 b - - 0 1"),
+            Sides::Black
+        ));
+        assert!(!kpk_wins(
+            &game("7k/8/8/8/8/8/7P/K7 w - - 0 1"),
+            Sides::White
+        ));
+
+        let Some(Knowledge::Score(score)) = known("8/8/8/8/3p4/3k4/8/3K4 w - - 0 1") else {
+            panic!("KPK not recognized");
+        };
+        assert!(score < -KNOWN_WIN);
+        assert_eq!(
+            known("8/8/8/8/8/4k3/4P3/4K3 w - - 0 1"),
+            Some(Knowledge::Score(0))
+        );
+    }
+
+    #[test]
+    fn recognizers() {
+        for fen in [
+            "8/8/4k3/8/8/3NK3/8/8 w - - 0 1",
+            "8/8/4k3/8/8/3bK3/8/8 w - - 0 1",
+            "8/8/4k3/8/8/2NNK3/8/8 b - - 0 1",
+        ] {
+            assert_eq!(known(fen), Some(Knowledge::Score(0)));
+        }
+        assert_eq!(known(crate::fen::START_FEN), None);
+        assert_eq!(known("8/8/4k3/8/8/1PNNK3/8/8 w - - 0 1"), None);
+    }
+
+    #[test]
+    fn mating() {
+        let score = |fen| match known(fen) {
+            Some(Knowledge::Score(score)) => score,
+            other => panic!("{fen}: {other:?}"),
+        };
+        // The defending King is better off in the centre and away from ours
+        let edge = score("k7/8/2K5/8/8/8/8/7R w - - 0 1");
+        let centre = score("8/8/8/3k4/8/8/8/K6R w - - 0 1");
+        assert!(edge > centre && centre > KNOWN_WIN);
+        assert!(score("8/8/8/3k4/8/8/8/K6q w - - 0 1") < -KNOWN_WIN);
+        assert!(score("8/8/8/8/8/2kbb3/8/K7 b - - 0 1") > KNOWN_WIN);
+
+        // A dark squared Bishop mates in the a1 and h8 corners
+        let right = score("8/8/8/8/8/2KN4/8/k1B5 w - - 0 1");
+        let wrong = score("k2B4/8/2KN4/8/8/8/8/8 w - - 0 1");
+        assert!(right > wrong && wrong > KNOWN_WIN);
+    }
+
+    #[test]
+    fn scaling() {
+        // The Bishop can't drive the King from the a8 corner
+        assert_eq!(
+            known("k7/8/8/8/8/8/P7/K1B5 w - - 0 1"),
+            Some(Knowledge::Scale(0))
+        );
+        assert_eq!(known("k7/8/8/8/8/8/P7/KB6 w - - 0 1"), None);
+
+        let ocb = game("4k3/5p2/8/3b4/8/2B5/5PP1/4K3 w - - 0 1");
+        assert_eq!(knowledge(&ocb), Some(Knowledge::Scale(OCB_SCALE)));
+        let score = crate::eval::evaluate(&ocb);
+        assert_eq!(
+            score,
+            crate::eval::evaluate_with(&ocb, &crate::eval::DEFAULT_WEIGHTS) * OCB_SCALE
+                / SCALE_NORMAL
+        );
+        assert_eq!(
+            known("3rk3/5p2/8/3b4/8/2B5/5PP1/3RK3 w - - 0 1"),
+            Some(Knowledge::Scale(OCB_PIECES_SCALE))
+        );
+        assert_eq!(known("4k3/5p2/8/4b3/8/2B5/5PP1/4K3 w - - 0 1"), None);
+        // Nor in the middlegame
+        let middlegame = "r2qk2r/ppp2ppp/2n1bn2/3p4/3P4/2N1BN2/PPP2PPP/R2QK2R w KQkq - 0 8";
+        assert_eq!(known(middlegame), None);
+        assert_eq!(known("2q1k3/5p2/8/3b4/8/2B5/5PP1/2Q1K3 w - - 0 1"), None);
+    }
+}
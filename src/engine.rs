@@ -0,0 +1,240 @@
+use crate::board::*;
+use crate::fen::START_FEN;
+use crate::nnue::{Evaluator, NnueError};
+use crate::search::{IterationCallback, Limits, Search, SearchResult};
+use crate::syzygy::Tablebases;
+use crate::tt::TranspositionTable;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Largest transposition table the protocols accept, in megabytes
+pub const MAX_HASH_MB: usize = 65536;
+pub const MAX_THREADS: usize = 256;
+pub const MAX_MULTIPV: usize = 256;
+
+/// Where a front-end's replies go, shared with the search thread
+pub type Output = Arc<Mutex<dyn Write + Send>>;
+
+/// Writes one line of a reply
+pub fn send(out: &Output, msg: &str) {
+    let mut out = out.lock().unwrap();
+    // The GUI going away is noticed when reading its next command
+    let _ = writeln!(out, "{msg}").and_then(|_| out.flush());
+}
+
+/// How a search started by `go` ends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GoMode {
+    /// Once a limit is hit
+    Normal,
+    /// Only when stopped, even when it has nothing left to search
+    Infinite,
+    /// As an infinite search until the ponder hit, then as a normal one with
+    /// the clock starting at the hit
+    Ponder,
+}
+
+/// The game being played and a search that runs on its own thread, shared by
+/// the protocol front-ends so commands keep being read while it thinks
+pub struct Engine {
+    pub game: GameState,
+    /// Positions before `game`, for repetition detection
+    pub history: Vec<GameState>,
+    /// The search, while it isn't running
+    search: Option<Search>,
+    worker: Option<JoinHandle<Search>>,
+    stop: Arc<AtomicBool>,
+    ponder: Arc<AtomicBool>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        let search = Search::new(Limits::default());
+        Engine {
+            game: GameState::from(START_FEN.to_string()),
+            history: vec![],
+            stop: search.stop_handle(),
+            ponder: search.ponder_handle(),
+            search: Some(search),
+            worker: None,
+        }
+    }
+
+    /// The search, once any running search has finished
+    pub fn search(&mut self) -> &mut Search {
+        self.wait();
+        self.search.as_mut().unwrap()
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.worker.as_ref().is_some_and(|w| !w.is_finished())
+    }
+
+    /// Forgets everything learnt from earlier games
+    pub fn new_game(&mut self) {
+        self.search().tt.clear();
+        self.set_position(GameState::from(START_FEN.to_string()));
+    }
+
+    pub fn set_position(&mut self, game: GameState) {
+        self.game = game;
+        self.history.clear();
+    }
+
+    /// Plays a legal move, returning false if it isn't one
+    pub fn play(&mut self, mov: &str) -> bool {
+        match self.game.parse_uci(mov) {
+            Some(mov) => {
+                let next = self.game.apply(mov);
+                self.history.push(std::mem::replace(&mut self.game, next));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Takes back the last move, returning false at the start of the game
+    pub fn undo(&mut self) -> bool {
+        match self.history.pop() {
+            Some(game) => {
+                self.game = game;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_hash(&mut self, mb: usize) {
+        self.search().tt = Arc::new(TranspositionTable::new(mb.clamp(1, MAX_HASH_MB)));
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.search().threads = threads.clamp(1, MAX_THREADS);
+    }
+
+    pub fn set_multipv(&mut self, lines: usize) {
+        self.search().multipv = lines.clamp(1, MAX_MULTIPV);
+    }
+
+    /// Evaluates with the network in the file at `path`, or the classical
+    /// evaluation when it is empty
+    pub fn set_eval_file(&mut self, path: &str) -> Result<(), NnueError> {
+        self.search().evaluator = Evaluator::from_file(path)?;
+        Ok(())
+    }
+
+    /// Probes the endgame tablebases in `paths`, or none when it is empty,
+    /// returning how many tables were found
+    pub fn set_tablebases(&mut self, paths: &str) -> std::io::Result<usize> {
+        let tablebases = match paths {
+            "" | "<empty>" => None,
+            paths => Some(Arc::new(Tablebases::open(paths)?)),
+        };
+        let found = tablebases.as_ref().map_or(0, |tb| tb.len());
+        self.search().tablebases = tablebases;
+        Ok(found)
+    }
+
+    /// Starts searching the current position on another thread, calling
+    /// `on_iteration` as each depth finishes and `on_done` with the result,
+    /// after ending any search still running
+    pub fn go<F>(
+        &mut self,
+        limits: Limits,
+        mode: GoMode,
+        on_iteration: IterationCallback,
+        on_done: F,
+    ) where
+        F: FnOnce(SearchResult) + Send + 'static,
+    {
+        self.stop();
+        let mut search = self.search.take().unwrap();
+        search.limits = limits;
+        search.on_iteration = Some(on_iteration);
+        let (game, history) = (self.game.clone(), self.history.clone());
+        let (stop, ponder) = (self.stop.clone(), self.ponder.clone());
+        ponder.store(mode == GoMode::Ponder, Ordering::Relaxed);
+        self.worker = Some(std::thread::spawn(move || {
+            let result = search.run(&game, &history);
+            // The result waits for the GUI, which doesn't expect it before
+            // a stop or ponder hit
+            let waiting = || match mode {
+                GoMode::Normal => false,
+                GoMode::Infinite => true,
+                GoMode::Ponder => ponder.load(Ordering::Relaxed),
+            };
+            while waiting() && !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            search.on_iteration = None;
+            on_done(result);
+            search
+        }));
+    }
+
+    /// Ends the running search, which still reports its result
+    pub fn stop(&mut self) {
+        if self.worker.is_some() {
+            self.stop.store(true, Ordering::Relaxed);
+            self.wait();
+        }
+        self.ponder.store(false, Ordering::Relaxed);
+    }
+
+    /// The opponent played the move being pondered on, so the search goes on
+    /// as a normal one on the engine's own time
+    pub fn ponderhit(&mut self) {
+        self.ponder.store(false, Ordering::Relaxed);
+    }
+
+    /// Blocks until the running search finishes by itself
+    pub fn wait(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            self.search = Some(worker.join().expect("search thread panicked"));
+            // Also clears a stop sent after the search had finished by itself
+            self.stop.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Output of the front-ends' tests, collected for them to read back
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::Output;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    pub struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        /// An output writing into this buffer
+        pub fn output(&self) -> Output {
+            Arc::new(Mutex::new(self.clone()))
+        }
+
+        /// Everything written since the last call
+        pub fn take(&self) -> String {
+            String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+        }
+    }
+}
@@ -0,0 +1,548 @@
+use crate::board::*;
+use crate::endgame;
+use crate::moves::piece_attacks;
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+pub const PIECE_VALUES: [i32; 6] = [100, 500, 320, 330, 900, 0];
+
+/// Middlegame and endgame halves of a score, blended by the game phase
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+pub const fn s(mg: i32, eg: i32) -> Score {
+    Score { mg, eg }
+}
+
+impl Add for Score {
+    type Output = Score;
+    fn add(self, other: Score) -> Score {
+        s(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+    fn sub(self, other: Score) -> Score {
+        s(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+    fn mul(self, n: i32) -> Score {
+        s(self.mg * n, self.eg * n)
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+    fn neg(self) -> Score {
+        s(-self.mg, -self.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, other: Score) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, other: Score) {
+        *self = *self - other;
+    }
+}
+
+// The evaluation is linear in its parameters: each term counts how often a
+// feature occurs for a side, and the score is the sum of counts times weights.
+// Offsets of each group of parameters in the weights:
+pub const MATERIAL: usize = 0;
+/// 64 squares per piece, from White's side, with A8 first
+pub const PSQT: usize = MATERIAL + 6;
+/// Per safe square attacked, by piece
+pub const MOBILITY: usize = PSQT + 6 * 64;
+pub const DOUBLED: usize = MOBILITY + 6;
+pub const ISOLATED: usize = DOUBLED + 1;
+pub const BACKWARD: usize = ISOLATED + 1;
+/// By rank from the side's own first rank
+pub const PASSED: usize = BACKWARD + 1;
+/// Per Pawn on the two ranks in front of the King
+pub const SHIELD: usize = PASSED + 8;
+/// Per piece attacking the enemy King's surroundings, by piece
+pub const KING_ATTACK: usize = SHIELD + 1;
+pub const BISHOP_PAIR: usize = KING_ATTACK + 6;
+pub const ROOK_OPEN: usize = BISHOP_PAIR + 1;
+pub const ROOK_SEMI_OPEN: usize = ROOK_OPEN + 1;
+pub const PARAM_COUNT: usize = ROOK_SEMI_OPEN + 1;
+
+/// Phase of the starting material, counting minor pieces 1, Rooks 2 and Queens 4
+pub const MAX_PHASE: i32 = 24;
+const PHASE_WEIGHTS: [i32; 6] = [0, 2, 1, 1, 4, 0];
+
+pub type Weights = [Score; PARAM_COUNT];
+
+#[rustfmt::skip]
+const PSQT_MG: [[i32; 64]; 6] = [
+    [
+         0,   0,   0,   0,   0,   0,   0,   0,
+        50,  50,  50,  50,  50,  50,  50,  50,
+        10,  10,  20,  30,  30,  20,  10,  10,
+         5,   5,  10,  25,  25,  10,   5,   5,
+         0,   0,   0,  20,  20,   0,   0,   0,
+         5,  -5, -10,   0,   0, -10,  -5,   5,
+         5,  10,  10, -20, -20,  10,  10,   5,
+         0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    [
+         0,   0,   0,   0,   0,   0,   0,   0,
+         5,  10,  10,  10,  10,  10,  10,   5,
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+        -5,   0,   0,   0,   0,   0,   0,  -5,
+         0,   0,   0,   5,   5,   0,   0,   0,
+    ],
+    [
+       -50, -40, -30, -30, -30, -30, -40, -50,
+       -40, -20,   0,   0,   0,   0, -20, -40,
+       -30,   0,  10,  15,  15,  10,   0, -30,
+       -30,   5,  15,  20,  20,  15,   5, -30,
+       -30,   0,  15,  20,  20,  15,   0, -30,
+       -30,   5,  10,  15,  15,  10,   5, -30,
+       -40, -20,   0,   5,   5,   0, -20, -40,
+       -50, -40, -30, -30, -30, -30, -40, -50,
+    ],
+    [
+       -20, -10, -10, -10, -10, -10, -10, -20,
+       -10,   0,   0,   0,   0,   0,   0, -10,
+       -10,   0,   5,  10,  10,   5,   0, -10,
+       -10,   5,   5,  10,  10,   5,   5, -10,
+       -10,   0,  10,  10,  10,  10,   0, -10,
+       -10,  10,  10,  10,  10,  10,  10, -10,
+       -10,   5,   0,   0,   0,   0,   5, -10,
+       -20, -10, -10, -10, -10, -10, -10, -20,
+    ],
+    [
+       -20, -10, -10,  -5,  -5, -10, -10, -20,
+       -10,   0,   0,   0,   0,   0,   0, -10,
+       -10,   0,   5,   5,   5,   5,   0, -10,
+        -5,   0,   5,   5,   5,   5,   0,  -5,
+         0,   0,   5,   5,   5,   5,   0,  -5,
+       -10,   5,   5,   5,   5,   5,   0, -10,
+       -10,   0,   5,   0,   0,   0,   0, -10,
+       -20, -10, -10,  -5,  -5, -10, -10, -20,
+    ],
+    [
+       -30, -40, -40, -50, -50, -40, -40, -30,
+       -30, -40, -40, -50, -50, -40, -40, -30,
+       -30, -40, -40, -50, -50, -40, -40, -30,
+       -30, -40, -40, -50, -50, -40, -40, -30,
+       -20, -30, -30, -40, -40, -30, -30, -20,
+       -10, -20, -20, -20, -20, -20, -20, -10,
+        20,  20,   0,   0,   0,   0,  20,  20,
+        20,  30,  10,   0,   0,  10,  30,  20,
+    ],
+];
+
+/// Only Pawns and the King change their preferred squares in the endgame
+#[rustfmt::skip]
+const PSQT_EG: [[i32; 64]; 2] = [
+    [
+         0,   0,   0,   0,   0,   0,   0,   0,
+        80,  80,  80,  80,  80,  80,  80,  80,
+        50,  50,  50,  50,  50,  50,  50,  50,
+        30,  30,  30,  30,  30,  30,  30,  30,
+        15,  15,  15,  15,  15,  15,  15,  15,
+         5,   5,   5,   5,   5,   5,   5,   5,
+         0,   0,   0,   0,   0,   0,   0,   0,
+         0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    [
+       -50, -40, -30, -20, -20, -30, -40, -50,
+       -30, -20, -10,   0,   0, -10, -20, -30,
+       -30, -10,  20,  30,  30,  20, -10, -30,
+       -30, -10,  30,  40,  40,  30, -10, -30,
+       -30, -10,  30,  40,  40,  30, -10, -30,
+       -30, -10,  20,  30,  30,  20, -10, -30,
+       -30, -30,   0,   0,   0,   0, -30, -30,
+       -50, -30, -30, -30, -30, -30, -30, -50,
+    ],
+];
+
+pub const DEFAULT_WEIGHTS: Weights = {
+    let mut w = [s(0, 0); PARAM_COUNT];
+    w[MATERIAL] = s(100, 120);
+    w[MATERIAL + 1] = s(480, 520);
+    w[MATERIAL + 2] = s(320, 300);
+    w[MATERIAL + 3] = s(330, 320);
+    w[MATERIAL + 4] = s(950, 950);
+
+    let mut piece = 0;
+    while piece < 6 {
+        let mut sq = 0;
+        while sq < 64 {
+            let eg = match piece {
+                0 => PSQT_EG[0][sq],
+                5 => PSQT_EG[1][sq],
+                _ => PSQT_MG[piece][sq],
+            };
+            w[PSQT + piece * 64 + sq] = s(PSQT_MG[piece][sq], eg);
+            sq += 1;
+        }
+        piece += 1;
+    }
+
+    w[MOBILITY + 1] = s(2, 4);
+    w[MOBILITY + 2] = s(4, 4);
+    w[MOBILITY + 3] = s(5, 5);
+    w[MOBILITY + 4] = s(1, 2);
+
+    w[DOUBLED] = s(-10, -20);
+    w[ISOLATED] = s(-10, -15);
+    w[BACKWARD] = s(-8, -10);
+    let passed = [
+        (0, 0),
+        (5, 10),
+        (10, 20),
+        (15, 35),
+        (25, 60),
+        (40, 100),
+        (60, 150),
+        (0, 0),
+    ];
+    let mut rank = 0;
+    while rank < 8 {
+        w[PASSED + rank] = s(passed[rank].0, passed[rank].1);
+        rank += 1;
+    }
+
+    w[SHIELD] = s(15, 0);
+    w[KING_ATTACK + 1] = s(12, 0);
+    w[KING_ATTACK + 2] = s(10, 0);
+    w[KING_ATTACK + 3] = s(8, 0);
+    w[KING_ATTACK + 4] = s(20, 0);
+    w[BISHOP_PAIR] = s(30, 50);
+    w[ROOK_OPEN] = s(25, 10);
+    w[ROOK_SEMI_OPEN] = s(12, 6);
+    w
+};
+
+/// Receives each evaluation term as it is found, `count` times for `side`
+pub trait Terms {
+    fn add(&mut self, side: Sides, param: usize, count: i32);
+}
+
+/// Sums the weighted terms from White's side
+struct Scorer<'a> {
+    weights: &'a Weights,
+    score: Score,
+}
+
+impl Terms for Scorer<'_> {
+    fn add(&mut self, side: Sides, param: usize, count: i32) {
+        let score = self.weights[param] * count;
+        match side {
+            Sides::White => self.score += score,
+            Sides::Black => self.score -= score,
+        }
+    }
+}
+
+/// Blends middlegame and endgame scores, from 0 with bare Kings up to `MAX_PHASE`
+pub fn taper(score: Score, phase: i32) -> i32 {
+    (score.mg * phase + score.eg * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+pub fn phase(game: &GameState) -> i32 {
+    let mut phase = 0;
+    for side in [Sides::White, Sides::Black] {
+        for piece in PIECES {
+            let count =
+                game.board(side, piece).0.count_ones() as i32 + game.pocket(side, piece) as i32;
+            phase += PHASE_WEIGHTS[piece as usize] * count;
+        }
+    }
+    phase.min(MAX_PHASE)
+}
+
+/// Static evaluation in centipawns from the side to move's perspective, with
+/// known endgames scored by their own rules
+pub fn evaluate(game: &GameState) -> i32 {
+    endgame::evaluate(game, || evaluate_with(game, &DEFAULT_WEIGHTS))
+}
+
+pub fn evaluate_with(game: &GameState, weights: &Weights) -> i32 {
+    let mut scorer = Scorer {
+        weights,
+        score: Score::default(),
+    };
+    eval_terms(game, &mut scorer);
+    let score = taper(scorer.score, phase(game));
+    match game.turn {
+        Sides::White => score,
+        Sides::Black => -score,
+    }
+}
+
+const FILE_A: u64 = 0x0101_0101_0101_0101;
+
+fn squares(mut board: u64) -> impl Iterator<Item = Square> {
+    std::iter::from_fn(move || {
+        if board == 0 {
+            return None;
+        }
+        let sq = board.trailing_zeros() as u8;
+        board &= board - 1;
+        Some(Square::from(sq))
+    })
+}
+
+fn adjacent_files(file: u8) -> u64 {
+    let mut mask = 0;
+    if file > 0 {
+        mask |= FILE_A << (file - 1);
+    }
+    if file < 7 {
+        mask |= FILE_A << (file + 1);
+    }
+    mask
+}
+
+/// Rows of the board ahead of `square` for `side`, with A8 in the first row
+fn ahead(side: Sides, square: Square) -> u64 {
+    let row = square as u32 / 8;
+    match side {
+        Sides::White => (1u64 << (row * 8)) - 1,
+        Sides::Black if row == 7 => 0,
+        Sides::Black => !0u64 << ((row + 1) * 8),
+    }
+}
+
+fn eval_terms<T: Terms>(game: &GameState, terms: &mut T) {
+    let occupied = game.occupied();
+    for side in [Sides::White, Sides::Black] {
+        let them = side.switch();
+        let own = PIECES
+            .iter()
+            .fold(0, |acc, &piece| acc | game.board(side, piece).0);
+        let own_pawns = game.board(side, Piece::Pawn).0;
+        let their_pawns = game.board(them, Piece::Pawn).0;
+        let pawn_attacks = squares(their_pawns).fold(0, |acc, sq| {
+            acc | piece_attacks(Piece::Pawn, them, sq, &occupied).0
+        });
+        let king_zone = game.king_square(them).map_or(0, |king| {
+            piece_attacks(Piece::King, them, king, &occupied).0 | 1u64 << king as u64
+        });
+
+        for piece in PIECES {
+            let board = game.board(side, piece).0;
+            let count = board.count_ones() as i32 + game.pocket(side, piece) as i32;
+            terms.add(side, MATERIAL + piece as usize, count);
+
+            for sq in squares(board) {
+                let relative = match side {
+                    Sides::White => sq as usize,
+                    Sides::Black => sq as usize ^ 56,
+                };
+                terms.add(side, PSQT + piece as usize * 64 + relative, 1);
+
+                if matches!(piece, Piece::Pawn | Piece::King) {
+                    continue;
+                }
+                let attacks = piece_attacks(piece, side, sq, &occupied).0;
+                let safe = attacks & !own & !pawn_attacks;
+                terms.add(side, MOBILITY + piece as usize, safe.count_ones() as i32);
+                if attacks & king_zone != 0 {
+                    terms.add(side, KING_ATTACK + piece as usize, 1);
+                }
+
+                if piece == Piece::Rook {
+                    let file = FILE_A << sq.file();
+                    if file & (own_pawns | their_pawns) == 0 {
+                        terms.add(side, ROOK_OPEN, 1);
+                    } else if file & own_pawns == 0 {
+                        terms.add(side, ROOK_SEMI_OPEN, 1);
+                    }
+                }
+            }
+        }
+
+        for sq in squares(own_pawns) {
+            let file = FILE_A << sq.file();
+            let adjacent = adjacent_files(sq.file());
+            let front = ahead(side, sq);
+            if own_pawns & file & front != 0 {
+                terms.add(side, DOUBLED, 1);
+            }
+            if their_pawns & (file | adjacent) & front == 0 {
+                let rank = match side {
+                    Sides::White => sq.rank(),
+                    Sides::Black => 7 - sq.rank(),
+                };
+                terms.add(side, PASSED + rank as usize, 1);
+            }
+            if own_pawns & adjacent == 0 {
+                terms.add(side, ISOLATED, 1);
+            } else {
+                // No neighbour level with or behind it can ever defend its
+                // next square, and an enemy Pawn already guards that square
+                let stop = match side {
+                    Sides::White => sq.step(Direction::N),
+                    Sides::Black => sq.step(Direction::S),
+                };
+                let supported = own_pawns & adjacent & !front != 0;
+                let stop_attacked =
+                    stop.is_some_and(|stop| pawn_attacks & 1u64 << stop as u64 != 0);
+                if !supported && stop_attacked {
+                    terms.add(side, BACKWARD, 1);
+                }
+            }
+        }
+
+        if game.board(side, Piece::Bishop).0.count_ones() >= 2 {
+            terms.add(side, BISHOP_PAIR, 1);
+        }
+
+        if let Some(king) = game.king_square(side) {
+            let forward = match side {
+                Sides::White => Direction::N,
+                Sides::Black => Direction::S,
+            };
+            let mut shield = 0;
+            let mut row = king.step(forward);
+            for _ in 0..2 {
+                let Some(center) = row else {
+                    break;
+                };
+                let files = FILE_A << center.file() | adjacent_files(center.file());
+                let rank = 0xFFu64 << (center as u32 / 8 * 8);
+                shield += (own_pawns & files & rank).count_ones() as i32;
+                row = center.step(forward);
+            }
+            terms.add(side, SHIELD, shield);
+        }
+    }
+}
+
+/// Names of the parameter groups, for the trace
+const GROUPS: [(&str, usize, usize); 12] = [
+    ("Material", MATERIAL, PSQT),
+    ("Piece squares", PSQT, MOBILITY),
+    ("Mobility", MOBILITY, DOUBLED),
+    ("Doubled pawns", DOUBLED, ISOLATED),
+    ("Isolated pawns", ISOLATED, BACKWARD),
+    ("Backward pawns", BACKWARD, PASSED),
+    ("Passed pawns", PASSED, SHIELD),
+    ("Pawn shield", SHIELD, KING_ATTACK),
+    ("King attackers", KING_ATTACK, BISHOP_PAIR),
+    ("Bishop pair", BISHOP_PAIR, ROOK_OPEN),
+    ("Rook open file", ROOK_OPEN, ROOK_SEMI_OPEN),
+    ("Rook semi-open", ROOK_SEMI_OPEN, PARAM_COUNT),
+];
+
+/// How often each parameter applies to each side in a position, for printing
+/// an evaluation term by term and for tuning the weights
+pub struct Trace {
+    pub counts: [[i32; PARAM_COUNT]; 2],
+    pub phase: i32,
+    pub turn: Sides,
+}
+
+impl Terms for Trace {
+    fn add(&mut self, side: Sides, param: usize, count: i32) {
+        self.counts[side as usize][param] += count;
+    }
+}
+
+impl Trace {
+    pub fn new(game: &GameState) -> Self {
+        let mut trace = Trace {
+            counts: [[0; PARAM_COUNT]; 2],
+            phase: phase(game),
+            turn: game.turn,
+        };
+        eval_terms(game, &mut trace);
+        trace
+    }
+
+    fn side_score(&self, side: Sides, from: usize, to: usize, weights: &Weights) -> Score {
+        (from..to).fold(Score::default(), |acc, param| {
+            acc + weights[param] * self.counts[side as usize][param]
+        })
+    }
+
+    /// Score from White's side with `weights`, before tapering
+    pub fn score(&self, weights: &Weights) -> Score {
+        self.side_score(Sides::White, 0, PARAM_COUNT, weights)
+            - self.side_score(Sides::Black, 0, PARAM_COUNT, weights)
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let weights = &DEFAULT_WEIGHTS;
+        writeln!(
+            f,
+            "{:<16}{:>14}{:>14}{:>14}",
+            "Term", "White", "Black", "Total"
+        )?;
+        for (name, from, to) in GROUPS {
+            let white = self.side_score(Sides::White, from, to, weights);
+            let black = self.side_score(Sides::Black, from, to, weights);
+            let total = white - black;
+            writeln!(
+                f,
+                "{:<16}{:>7}{:>7}{:>7}{:>7}{:>7}{:>7}",
+                name, white.mg, white.eg, black.mg, black.eg, total.mg, total.eg
+            )?;
+        }
+        let white = taper(self.score(weights), self.phase);
+        writeln!(f, "Phase {}/{}", self.phase, MAX_PHASE)?;
+        write!(f, "Evaluation {} from White's side", white)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::START_FEN;
+
+    #[test]
+    fn symmetric() {
+        let start = GameState::from(START_FEN.to_string());
+        assert_eq!(evaluate(&start), 0);
+
+        let white = GameState::from(
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3".to_string(),
+        );
+        let black = GameState::from(
+            "rnbqkb1r/pppp1ppp/5n2/4p3/4P3/2N5/PPPP1PPP/R1BQKBNR b KQkq - 2 3".to_string(),
+        );
+        assert_eq!(evaluate(&white), evaluate(&black));
+    }
+
+    #[test]
+    fn pawn_structure() {
+        // Every White Pawn is isolated, the a-Pawns are doubled and d5 is
+        // passed, while g7 can't advance past f5 without h5 behind it
+        let game = GameState::from("4k3/6p1/8/3P1P1p/8/P7/P7/4K3 w - - 0 1".to_string());
+        let trace = Trace::new(&game);
+        let [white, black] = trace.counts;
+        assert_eq!(white[DOUBLED], 1);
+        assert_eq!(white[ISOLATED], 4);
+        assert_eq!(white[PASSED + 4], 1);
+        assert_eq!(white[PASSED + 5], 0);
+        assert_eq!(black[ISOLATED], 0);
+        assert_eq!(black[BACKWARD], 1);
+        assert_eq!(black[PASSED + 3], 1);
+
+        let score = taper(trace.score(&DEFAULT_WEIGHTS), trace.phase);
+        assert_eq!(score, evaluate(&game));
+        assert!(trace.to_string().contains("Passed pawns"));
+    }
+}
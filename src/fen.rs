@@ -1,19 +1,112 @@
 use crate::board::*;
+use crate::moves::Move;
+use crate::variant::Variant;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FenError {
+    Missing,
+    Position,
+    Side,
+    CastleRights,
+    EnPassant,
+    Counters,
+}
+
+impl std::fmt::Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            FenError::Missing => "Missing FEN Field",
+            FenError::Position => "Invalid FEN Position",
+            FenError::Side => "Invalid FEN Side",
+            FenError::CastleRights => "Invalid FEN Castle Rights",
+            FenError::EnPassant => "Invalid FEN En Passant",
+            FenError::Counters => "Invalid FEN Move Counters",
+        };
+        write!(f, "{msg}")
+    }
+}
+
+impl std::error::Error for FenError {}
 
 impl From<String> for GameState {
     fn from(fen: String) -> Self {
+        GameState::from_fen(&fen).unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+impl GameState {
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
         let mut game = GameState {
             state: std::array::from_fn(|_| std::array::from_fn(|_| BitBoard(0))),
             turn: Sides::White,
             castle_rights: 0,
+            castle_rooks: [Square::A1, Square::H1, Square::A8, Square::H8],
+            chess960: false,
             en_passant: None,
+            halfmoves: 0,
+            fullmoves: 1,
+            variant: Variant::Standard,
+            pockets: [[0; 6]; 2],
+            promoted: BitBoard(0),
+            checks: [0; 2],
         };
 
         let fen: Vec<&str> = fen.split_whitespace().collect();
+        if fen.len() < 4 {
+            return Err(FenError::Missing);
+        }
+
+        // Crazyhouse pockets are written either in brackets or as a ninth rank
+        let (position, pocket) = match fen[0].split_once('[') {
+            Some((position, pocket)) => {
+                let pocket = pocket.strip_suffix(']').ok_or(FenError::Position)?;
+                (position, Some(pocket))
+            }
+            None if fen[0].matches('/').count() == 8 => {
+                let (position, pocket) = fen[0].rsplit_once('/').unwrap();
+                (position, Some(pocket))
+            }
+            None => (fen[0], None),
+        };
+        if let Some(pocket) = pocket {
+            game.variant = Variant::Crazyhouse;
+            for c in pocket.chars() {
+                let piece = Piece::from_symbol(c)
+                    .filter(|&p| p != Piece::King)
+                    .ok_or(FenError::Position)?;
+                let side = match c.is_ascii_uppercase() {
+                    true => Sides::White,
+                    false => Sides::Black,
+                };
+                game.pockets[side as usize][piece as usize] += 1;
+            }
+        }
 
         let mut x: u8 = 0;
         let mut y: u8 = 0;
-        for c in fen[0].to_string().chars() {
+        for c in position.chars() {
+            if c == '~' {
+                if x == 0 {
+                    return Err(FenError::Position);
+                }
+                game.promoted.flip(Square::from(x - 1 + y * 8));
+                continue;
+            }
+            if c == '/' {
+                if x != 8 {
+                    return Err(FenError::Position);
+                }
+                x = 0;
+                y += 1;
+                continue;
+            }
+            if x >= 8 || y >= 8 {
+                return Err(FenError::Position);
+            }
             let sq = Square::from(x + y * 8);
             x += 1;
             match c {
@@ -29,44 +122,557 @@ impl From<String> for GameState {
                 'b' => game.board_mut(Sides::Black, Piece::Bishop).flip(sq),
                 'q' => game.board_mut(Sides::Black, Piece::Queen).flip(sq),
                 'k' => game.board_mut(Sides::Black, Piece::King).flip(sq),
-                '1'..='9' => x += c as u8 - b'1',
-                '/' => {
-                    x = 0;
-                    y += 1;
-                }
-                _ => panic!("Invalid FEN Position"),
+                '1'..='8' => x += c as u8 - b'1',
+                _ => return Err(FenError::Position),
             }
         }
+        if x != 8 || y != 7 {
+            return Err(FenError::Position);
+        }
 
         game.turn = match fen[1] {
             "w" => Sides::White,
             "b" => Sides::Black,
-            _ => panic!("Invalid FEN Side"),
+            _ => return Err(FenError::Side),
         };
 
+        // Accepts standard KQkq, X-FEN and Shredder-FEN rook files
         if fen[2] != "-" {
-            for c in fen[2].to_string().chars() {
-                let right = match c {
-                    'K' => Castle::WhiteKing,
-                    'Q' => Castle::WhiteQueen,
-                    'k' => Castle::BlackKing,
-                    'q' => Castle::BlackQueen,
-                    _ => panic!("Invalid FEN Castle Rights"),
+            for c in fen[2].chars() {
+                let side = match c.is_ascii_uppercase() {
+                    true => Sides::White,
+                    false => Sides::Black,
+                };
+                let rank = match side {
+                    Sides::White => 0,
+                    Sides::Black => 7,
+                };
+                let king = game.king_square(side).ok_or(FenError::CastleRights)?;
+                if king.rank() != rank {
+                    return Err(FenError::CastleRights);
+                }
+                let rooks = game.board(side, Piece::Rook);
+                let file = match c.to_ascii_lowercase() {
+                    'k' => (king.file() + 1..8)
+                        .rev()
+                        .find(|&f| rooks.get(Square::at(f, rank))),
+                    'q' => (0..king.file()).find(|&f| rooks.get(Square::at(f, rank))),
+                    f @ 'a'..='h' => {
+                        let file = f as u8 - b'a';
+                        if file == king.file() || !rooks.get(Square::at(file, rank)) {
+                            return Err(FenError::CastleRights);
+                        }
+                        game.chess960 = true;
+                        Some(file)
+                    }
+                    _ => return Err(FenError::CastleRights),
+                };
+                let Some(file) = file else {
+                    continue;
+                };
+                let right = match (side, file > king.file()) {
+                    (Sides::White, true) => Castle::WhiteKing,
+                    (Sides::White, false) => Castle::WhiteQueen,
+                    (Sides::Black, true) => Castle::BlackKing,
+                    (Sides::Black, false) => Castle::BlackQueen,
                 };
                 game.castle_rights |= right as u8;
+                game.castle_rooks[right.index()] = Square::at(file, rank);
+                if king.file() != 4 || (file != 0 && file != 7) {
+                    game.chess960 = true;
+                }
             }
         }
 
         if fen[3] != "-" {
-            let mut chars = fen[3].chars();
-            let file = chars.next().unwrap() as u8 - b'a';
-            let row = b'8' - chars.next().unwrap() as u8;
-            let square = Square::from(row * 8 + file);
+            let square = Square::try_from(fen[3]).map_err(|_| FenError::EnPassant)?;
             game.en_passant = Some(square);
         }
 
-        // TODO: Halfmove and Fullmove
+        // Three-check counters, either remaining checks as "3+3" before the move
+        // counters or checks given as "+0+0" after them
+        let mut counters = &fen[4..];
+        if let Some((white, black)) = counters.first().and_then(|c| c.split_once('+')) {
+            if !white.is_empty() {
+                let remaining = |n: &str| n.parse::<u8>().ok().filter(|&n| n <= 3);
+                let white = remaining(white).ok_or(FenError::Counters)?;
+                let black = remaining(black).ok_or(FenError::Counters)?;
+                game.checks = [3 - white, 3 - black];
+                game.variant = Variant::ThreeCheck;
+                counters = &counters[1..];
+            }
+        }
+        if let Some(given) = counters.get(2).and_then(|c| c.strip_prefix('+')) {
+            let (white, black) = given.split_once('+').ok_or(FenError::Counters)?;
+            let white = white.parse().map_err(|_| FenError::Counters)?;
+            let black = black.parse().map_err(|_| FenError::Counters)?;
+            game.checks = [white, black];
+            game.variant = Variant::ThreeCheck;
+        }
+
+        if let Some(halfmoves) = counters.first() {
+            game.halfmoves = halfmoves.parse().map_err(|_| FenError::Counters)?;
+        }
+        if let Some(fullmoves) = counters.get(1) {
+            game.fullmoves = fullmoves.parse().map_err(|_| FenError::Counters)?;
+        }
+
+        Ok(game)
+    }
+}
+
+impl GameState {
+    pub fn fen(&self) -> String {
+        let mut fen = self.epd();
+        if self.variant == Variant::ThreeCheck {
+            let [white, black] = self.checks.map(|n| 3u8.saturating_sub(n));
+            fen.push_str(&format!(" {white}+{black}"));
+        }
+        format!("{fen} {} {}", self.halfmoves, self.fullmoves)
+    }
+
+    /// FEN with castling rights written as rook files, as used for Chess960
+    pub fn shredder_fen(&self) -> String {
+        let fields: Vec<String> = self.fen().split(' ').map(str::to_string).collect();
+        let castle = self.castle_field(true);
+        format!(
+            "{} {} {castle} {}",
+            fields[0],
+            fields[1],
+            fields[3..].join(" ")
+        )
+    }
+
+    /// Castling rights as KQkq, falling back to X-FEN rook files in Chess960
+    /// when the right's Rook is not the outermost one, or always in Shredder-FEN
+    fn castle_field(&self, shredder: bool) -> String {
+        let order = [
+            Castle::WhiteKing,
+            Castle::WhiteQueen,
+            Castle::BlackKing,
+            Castle::BlackQueen,
+        ];
+        let mut field = String::new();
+        for castle in order {
+            if self.castle_rights & castle as u8 == 0 {
+                continue;
+            }
+            let rook = self.castle_rook(castle);
+            let rooks = self.board(castle.side(), Piece::Rook);
+            let outermost = match castle {
+                Castle::WhiteKing | Castle::BlackKing => rook.file() + 1..8,
+                Castle::WhiteQueen | Castle::BlackQueen => 0..rook.file(),
+            }
+            .all(|f| !rooks.get(Square::at(f, rook.rank())));
+
+            let c = if shredder || (self.chess960 && !outermost) {
+                (b'a' + rook.file()) as char
+            } else if matches!(castle, Castle::WhiteKing | Castle::BlackKing) {
+                'k'
+            } else {
+                'q'
+            };
+            field.push(match castle.side() {
+                Sides::White => c.to_ascii_uppercase(),
+                Sides::Black => c,
+            });
+        }
+        if field.is_empty() {
+            field.push('-');
+        }
+        field
+    }
+
+    /// The first four FEN fields, shared by FEN and EPD
+    pub fn epd(&self) -> String {
+        let mut position = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.piece_at(Square::at(file, rank)) {
+                    Some((side, piece)) => {
+                        if empty > 0 {
+                            position.push((b'0' + empty) as char);
+                            empty = 0;
+                        }
+                        position.push(match side {
+                            Sides::White => piece.symbol(),
+                            Sides::Black => piece.symbol().to_ascii_lowercase(),
+                        });
+                        if self.promoted.get(Square::at(file, rank)) {
+                            position.push('~');
+                        }
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                position.push((b'0' + empty) as char);
+            }
+            if rank > 0 {
+                position.push('/');
+            }
+        }
+        if self.variant == Variant::Crazyhouse {
+            position.push_str(&format!("[{}]", self.pocket_field()));
+        }
+
+        let turn = match self.turn {
+            Sides::White => "w",
+            Sides::Black => "b",
+        };
+
+        let castle = self.castle_field(false);
+
+        let en_passant = self.en_passant.map_or("-".to_string(), |sq| sq.name());
+
+        format!("{position} {turn} {castle} {en_passant}")
+    }
+}
+
+/// Operand of an EPD opcode, typed by what the opcode is known to hold
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Move(Move),
+    Integer(i64),
+    Float(f64),
+    Str(String),
+    Token(String),
+}
+
+#[derive(Debug)]
+pub enum EpdError {
+    Io(std::io::Error),
+    Fen(FenError),
+    Operation(String),
+    Move { opcode: String, san: String },
+}
+
+impl std::fmt::Display for EpdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EpdError::Io(e) => write!(f, "{e}"),
+            EpdError::Fen(e) => write!(f, "{e}"),
+            EpdError::Operation(op) => write!(f, "Invalid EPD Operation {op}"),
+            EpdError::Move { opcode, san } => {
+                write!(f, "Illegal move {san} in EPD opcode {opcode}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EpdError {}
+
+/// Opcodes whose operands are moves from the record's position
+const MOVE_OPCODES: [&str; 4] = ["bm", "am", "pm", "sm"];
+
+/// A position from an EPD test suite along with its opcodes,
+/// such as `bm`, `am`, `id` and the perft counts `D1`..`D6`
+#[derive(Clone, Debug)]
+pub struct Epd {
+    pub game: GameState,
+    pub ops: BTreeMap<String, Vec<Operand>>,
+}
+
+impl Epd {
+    pub fn new(game: GameState) -> Self {
+        Epd {
+            game,
+            ops: BTreeMap::new(),
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, EpdError> {
+        // Fields may be separated by any run of whitespace, which the
+        // operations keep inside their quoted strings
+        let mut rest = line;
+        let mut position = vec![];
+        for _ in 0..4 {
+            let field;
+            (field, rest) = next_field(rest);
+            position.push(field);
+        }
+        let mut game = GameState::from_fen(&position.join(" ")).map_err(EpdError::Fen)?;
+
+        let mut ops = BTreeMap::new();
+        for op in split_operations(rest) {
+            let (opcode, operands) = match op.split_first() {
+                Some((opcode, operands)) => (opcode.clone(), operands),
+                None => continue,
+            };
+            if !opcode.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                return Err(EpdError::Operation(opcode));
+            }
+            let operands = parse_operands(&game, &opcode, operands)?;
+            // Variants can't be told apart by the position, so they travel as
+            // an opcode written ahead of any moves
+            if opcode == "variant" {
+                let variant = match operands.as_slice() {
+                    [Operand::Str(name) | Operand::Token(name)] => Variant::from_name(name),
+                    _ => None,
+                };
+                game.variant = variant.ok_or(EpdError::Operation(opcode))?;
+                continue;
+            }
+            ops.insert(opcode, operands);
+        }
+
+        let counter = |opcode: &str| match ops.get(opcode).map(Vec::as_slice) {
+            Some([Operand::Integer(n)]) => u32::try_from(*n)
+                .map(Some)
+                .map_err(|_| EpdError::Operation(opcode.to_string())),
+            _ => Ok(None),
+        };
+        if let Some(n) = counter("hmvc")? {
+            game.halfmoves = n;
+        }
+        if let Some(n) = counter("fmvn")? {
+            game.fullmoves = n;
+        }
+
+        Ok(Epd { game, ops })
+    }
+
+    pub fn get(&self, opcode: &str) -> Option<&[Operand]> {
+        self.ops.get(opcode).map(Vec::as_slice)
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        match self.get("id")? {
+            [Operand::Str(id) | Operand::Token(id)] => Some(id),
+            _ => None,
+        }
+    }
+
+    pub fn best_moves(&self) -> Vec<Move> {
+        self.moves("bm")
+    }
+
+    pub fn avoid_moves(&self) -> Vec<Move> {
+        self.moves("am")
+    }
+
+    /// Moves listed under a move opcode such as `bm`, `am` or `pv`
+    pub fn moves(&self, opcode: &str) -> Vec<Move> {
+        self.get(opcode)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|op| match op {
+                Operand::Move(m) => Some(m.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Moves to mate from a `dm` opcode, for mate puzzles
+    pub fn direct_mate(&self) -> Option<u32> {
+        match self.get("dm")? {
+            [Operand::Integer(n)] => u32::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+
+    /// Expected perft node count from a `D<depth>` opcode
+    pub fn perft(&self, depth: u8) -> Option<u64> {
+        match self.get(&format!("D{depth}"))? {
+            [Operand::Integer(n)] => u64::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Epd {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.game.epd())?;
+        if self.game.variant != Variant::Standard {
+            write!(f, " variant \"{}\";", self.game.variant.name())?;
+        }
+        for (opcode, operands) in &self.ops {
+            write!(f, " {opcode}")?;
+            let mut game = self.game.clone();
+            for operand in operands {
+                match operand {
+                    Operand::Move(m) => {
+                        write!(f, " {}", game.san(m))?;
+                        if opcode == "pv" {
+                            game = game.apply(m.clone());
+                        }
+                    }
+                    Operand::Integer(n) => write!(f, " {n}")?,
+                    Operand::Float(x) => write!(f, " {x}")?,
+                    Operand::Str(s) => write!(f, " \"{s}\"")?,
+                    Operand::Token(t) => write!(f, " {t}")?,
+                }
+            }
+            write!(f, ";")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads every record of an EPD file, skipping blank lines and `#` comments.
+/// A read error is the last item.
+pub fn read_epd<R: BufRead>(reader: R) -> impl Iterator<Item = Result<Epd, EpdError>> {
+    let mut lines = reader.lines();
+    let mut failed = false;
+    std::iter::from_fn(move || loop {
+        if failed {
+            return None;
+        }
+        let line = match lines.next()? {
+            Ok(line) => line,
+            Err(e) => {
+                failed = true;
+                return Some(Err(EpdError::Io(e)));
+            }
+        };
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            return Some(Epd::parse(line));
+        }
+    })
+}
+
+/// Splits off the first whitespace separated field of `s`
+fn next_field(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    s.split_once(char::is_whitespace).unwrap_or((s, ""))
+}
+
+/// Splits EPD operations on `;`, keeping quoted strings intact
+fn split_operations(ops: &str) -> Vec<Vec<String>> {
+    let mut result = vec![];
+    let mut op = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+
+    for c in ops.chars() {
+        match c {
+            '"' if quoted => {
+                op.push(format!("\"{token}\""));
+                token.clear();
+                quoted = false;
+            }
+            _ if quoted => token.push(c),
+            '"' => quoted = true,
+            ';' => {
+                if !token.is_empty() {
+                    op.push(std::mem::take(&mut token));
+                }
+                result.push(std::mem::take(&mut op));
+            }
+            c if c.is_whitespace() => {
+                if !token.is_empty() {
+                    op.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        op.push(token);
+    }
+    result.push(op);
+    result
+}
+
+fn parse_operands(
+    game: &GameState,
+    opcode: &str,
+    operands: &[String],
+) -> Result<Vec<Operand>, EpdError> {
+    let mut game = game.clone();
+    let mut result = vec![];
+    for operand in operands {
+        if let Some(s) = operand.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            result.push(Operand::Str(s.to_string()));
+        } else if MOVE_OPCODES.contains(&opcode) || opcode == "pv" {
+            let mov = game.parse_san(operand).ok_or_else(|| EpdError::Move {
+                opcode: opcode.to_string(),
+                san: operand.clone(),
+            })?;
+            if opcode == "pv" {
+                game = game.apply(mov.clone());
+            }
+            result.push(Operand::Move(mov));
+        } else if let Ok(n) = operand.parse() {
+            result.push(Operand::Integer(n));
+        } else if let Ok(x) = operand.parse() {
+            result.push(Operand::Float(x));
+        } else {
+            result.push(Operand::Token(operand.clone()));
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [
+            START_FEN,
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "r3k2r/ppp1pppp/8/2PpP3/4PP2/8/PPPPPPPP/R3K2R w Kq d6 0 12",
+        ] {
+            assert_eq!(GameState::from(fen.to_string()).fen(), fen);
+        }
+    }
+
+    #[test]
+    fn castle_rook_files() {
+        let shredder = "rk5r/8/8/8/8/8/8/RK5R w HAha - 0 1";
+        assert_eq!(
+            GameState::from_fen(shredder).unwrap().shredder_fen(),
+            shredder
+        );
+        // Files without a rook of that side, or the King's own
+        for fen in [
+            "4k3/8/8/8/8/8/8/4K3 w H - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w E - 0 1",
+            "4k3/8/8/8/8/8/8/R3K3 w B - 0 1",
+        ] {
+            assert!(
+                matches!(GameState::from_fen(fen), Err(FenError::CastleRights)),
+                "{fen}"
+            );
+        }
+    }
+
+    #[test]
+    fn epd_opcodes() {
+        let line =
+            r#"2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001";"#;
+        let epd = Epd::parse(line).unwrap();
+        assert_eq!(epd.id(), Some("WAC.001"));
+        assert_eq!(epd.best_moves()[0].uci(), "g3g6");
+        assert_eq!(epd.to_string(), line);
+
+        let start = GameState::from(START_FEN.to_string());
+        let perft = Epd::parse(&format!("{} ;D1 20 ;D2 400", start.epd())).unwrap();
+        assert_eq!(perft.perft(1), Some(20));
+        assert_eq!(perft.perft(2), Some(400));
+        assert_eq!(perft.perft(3), None);
+
+        let atomic = r#"4k3/4p3/8/8/8/8/8/4QK2 w - - variant "Atomic"; bm Qxe7#;"#;
+        let epd = Epd::parse(atomic).unwrap();
+        assert_eq!(epd.game.variant, Variant::Atomic);
+        assert_eq!(epd.to_string(), atomic);
+
+        let spaced = "4k3/8/8/8/8/8/8/4K3 \t b  -   -  hmvc 12;\tfmvn 40;";
+        let epd = Epd::parse(spaced).unwrap();
+        assert_eq!((epd.game.halfmoves, epd.game.fullmoves), (12, 40));
+        assert!(Epd::parse("4k3/8/8/8/8/8/8/4K3 b - - hmvc -1;").is_err());
+    }
 
-        game
+    #[test]
+    fn epd_read_errors() {
+        let file: &[u8] = b"# suite\n\n4k3/8/8/8/8/8/8/4K3 w - - id \"a\";\n\xff\n8/8 w - -\n";
+        let records: Vec<_> = read_epd(file).collect();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].is_ok());
+        assert!(matches!(records[1], Err(EpdError::Io(_))));
     }
 }
@@ -0,0 +1,26 @@
+pub mod bench;
+pub mod board;
+pub mod chess960;
+pub mod crazyhouse;
+pub mod datagen;
+pub mod endgame;
+pub mod engine;
+pub mod eval;
+pub mod fen;
+pub mod mate;
+pub mod movepick;
+pub mod moves;
+pub mod nnue;
+pub mod notation;
+pub mod outcome;
+pub mod pgn;
+pub mod search;
+pub mod see;
+pub mod syzygy;
+pub mod timeman;
+pub mod tt;
+pub mod tune;
+pub mod uci;
+pub mod variant;
+pub mod xboard;
+pub mod zobrist;
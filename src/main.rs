@@ -1,9 +1,13 @@
-mod board;
-mod fen;
-mod moves;
-
-use board::{GameState, Piece, Sides, Square};
 use macroquad::prelude::*;
+use rustle::board::{GameState, Piece, Sides, Square};
+use rustle::nnue::Evaluator;
+use rustle::search::{Limits, Search};
+use rustle::timeman::TimeControl;
+use std::time::{Duration, Instant};
+
+/// The engine's clock in the GUI, five minutes with two seconds a move
+const ENGINE_TIME: Duration = Duration::from_secs(300);
+const ENGINE_INCREMENT: Duration = Duration::from_secs(2);
 
 #[macroquad::main("rustle")]
 async fn main() {
@@ -19,6 +23,16 @@ async fn main() {
     let mut curr = game.clone();
     let mut moves = game.moves();
     let mut index = 0;
+    let mut history = vec![];
+    let mut search = Search::new(Limits::default());
+    // A network file to evaluate with can be given as the only argument
+    if let Some(path) = std::env::args().nth(1) {
+        match Evaluator::from_file(&path) {
+            Ok(evaluator) => search.evaluator = evaluator,
+            Err(e) => eprintln!("{path}: {e}"),
+        }
+    }
+    let mut engine_clock = ENGINE_TIME;
 
     loop {
         let game_size = screen_width().min(screen_height());
@@ -83,16 +97,35 @@ async fn main() {
             curr = game.apply(moves[index].clone());
         }
         if is_key_pressed(KeyCode::Up) {
+            history.push(game.clone());
             game = game.apply(moves[index].clone());
             curr = game.clone();
             moves = game.moves();
             index = 0;
         }
         if is_key_pressed(KeyCode::Space) {
-            game = game.apply(moves[rand::gen_range(0, moves.len())].clone());
-            curr = game.clone();
-            moves = game.moves();
-            index = 0;
+            search.limits.clock = Some(TimeControl::Clock {
+                remaining: engine_clock,
+                increment: ENGINE_INCREMENT,
+                moves_to_go: None,
+            });
+            let start = Instant::now();
+            let result = search.run(&game, &history);
+            engine_clock = engine_clock.saturating_sub(start.elapsed()) + ENGINE_INCREMENT;
+            if let Some(best) = result.best {
+                println!(
+                    "{} (depth {}, score {}, {:.1}s left)",
+                    best,
+                    result.depth,
+                    result.score,
+                    engine_clock.as_secs_f64()
+                );
+                history.push(game.clone());
+                game = game.apply(best);
+                curr = game.clone();
+                moves = game.moves();
+                index = 0;
+            }
         }
         next_frame().await;
     }
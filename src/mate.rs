@@ -0,0 +1,170 @@
+use crate::board::*;
+use crate::moves::Move;
+use std::collections::HashMap;
+
+/// A forced mate found by `solve`
+#[derive(Clone, Debug)]
+pub struct MateSolution {
+    /// Moves by the side to move until mate
+    pub moves: u32,
+    /// The mate with the defender's most stubborn replies
+    pub line: Vec<Move>,
+    /// Every first move that mates as quickly, of which a sound puzzle has one
+    pub keys: Vec<Move>,
+    /// Positions visited
+    pub nodes: u64,
+}
+
+impl MateSolution {
+    pub fn is_unique(&self) -> bool {
+        self.keys.len() == 1
+    }
+}
+
+/// Proves mates by a full width search of every attacking move and every
+/// defence, which needs no evaluation. Positions already decided are
+/// remembered by how many moves they were searched for.
+struct MateSearch {
+    attacker: Sides,
+    proven: HashMap<(u64, u32), bool>,
+    nodes: u64,
+}
+
+impl MateSearch {
+    /// Whether the attacker, to move in `game`, mates within `n` moves
+    fn attack(&mut self, game: &GameState, n: u32) -> bool {
+        if n == 0 {
+            return false;
+        }
+        let key = (game.hash(), n);
+        if let Some(&proven) = self.proven.get(&key) {
+            return proven;
+        }
+        // Checks first, as they are the likeliest to mate
+        let mut children: Vec<GameState> =
+            game.moves().into_iter().map(|m| game.apply(m)).collect();
+        children.sort_by_key(|child| !child.in_check());
+        let proven = children.iter().any(|child| self.defend(child, n));
+        self.proven.insert(key, proven);
+        proven
+    }
+
+    /// Whether the defender, to move in `game`, is mated by the attacker's
+    /// `n`th move at the latest, counting the one just played
+    fn defend(&mut self, game: &GameState, n: u32) -> bool {
+        self.nodes += 1;
+        if let Some(outcome) = game.outcome() {
+            return outcome.winner() == Some(self.attacker);
+        }
+        if n <= 1 {
+            return false;
+        }
+        let key = (game.hash(), n);
+        if let Some(&proven) = self.proven.get(&key) {
+            return proven;
+        }
+        let proven = game
+            .moves()
+            .into_iter()
+            .all(|m| self.attack(&game.apply(m), n - 1));
+        self.proven.insert(key, proven);
+        proven
+    }
+
+    /// Fewest moves the attacker, to move in `game`, needs to mate, knowing
+    /// it mates within `n`
+    fn distance(&mut self, game: &GameState, n: u32) -> u32 {
+        (1..n).find(|&k| self.attack(game, k)).unwrap_or(n)
+    }
+
+    /// The mate in `n` starting with `key`, where the defender always picks
+    /// the reply that delays it the longest
+    fn line(&mut self, game: &GameState, mut n: u32, key: Move) -> Vec<Move> {
+        let mut line = vec![key.clone()];
+        let mut game = game.apply(key);
+        while game.outcome().is_none() {
+            let (reply, next, k) = game
+                .moves()
+                .into_iter()
+                .map(|m| {
+                    let next = game.apply(m.clone());
+                    let k = self.distance(&next, n - 1);
+                    (m, next, k)
+                })
+                .max_by_key(|(_, _, k)| *k)
+                .unwrap();
+            line.push(reply);
+            n = k;
+            let mov = next
+                .moves()
+                .into_iter()
+                .find(|m| self.defend(&next.apply(m.clone()), n))
+                .unwrap();
+            game = next.apply(mov.clone());
+            line.push(mov);
+        }
+        line
+    }
+}
+
+/// Finds the shortest forced mate for the side to move in at most
+/// `max_moves` moves, for checking puzzles such as "White mates in 3"
+pub fn solve(game: &GameState, max_moves: u32) -> Option<MateSolution> {
+    let mut search = MateSearch {
+        attacker: game.turn,
+        proven: HashMap::new(),
+        nodes: 0,
+    };
+    for n in 1..=max_moves {
+        let keys: Vec<Move> = game
+            .moves()
+            .into_iter()
+            .filter(|m| search.defend(&game.apply(m.clone()), n))
+            .collect();
+        if let Some(key) = keys.first() {
+            let line = search.line(game, n, key.clone());
+            return Some(MateSolution {
+                moves: n,
+                line,
+                keys,
+                nodes: search.nodes,
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sans(game: &GameState, line: &[Move]) -> Vec<String> {
+        let mut game = game.clone();
+        let mut sans = vec![];
+        for mov in line {
+            sans.push(game.san(mov));
+            game = game.apply(mov.clone());
+        }
+        sans
+    }
+
+    #[test]
+    fn mates() {
+        let back_rank = GameState::from("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1".to_string());
+        let solution = solve(&back_rank, 3).unwrap();
+        assert_eq!(solution.moves, 1);
+        assert_eq!(sans(&back_rank, &solution.line), ["Ra8#"]);
+        assert!(solution.is_unique());
+
+        // The King has to come closer before the Rook can mate
+        let rook = GameState::from("k7/8/2K5/8/8/8/8/7R w - - 0 1".to_string());
+        let solution = solve(&rook, 2).unwrap();
+        assert_eq!(solution.moves, 2);
+        assert_eq!(solution.line.len(), 3);
+        assert!(sans(&rook, &solution.line)[2].ends_with('#'));
+        assert!(solution.keys.len() > 1);
+
+        let start = GameState::from(crate::fen::START_FEN.to_string());
+        assert!(solve(&start, 2).is_none());
+    }
+}
@@ -0,0 +1,261 @@
+use crate::board::*;
+use crate::moves::Move;
+use crate::search::MAX_PLY;
+use crate::see::SEE_VALUES;
+
+/// History scores saturate towards this bound
+const MAX_HISTORY: i32 = 16384;
+
+/// Quiet move statistics gathered during a search, where moves are stored
+/// packed as in the transposition table
+pub struct Heuristics {
+    /// Two quiet moves per ply that recently caused beta cutoffs
+    killers: [[u16; 2]; MAX_PLY],
+    /// Replies that refuted the opponent's last move, by its side, piece and target
+    countermoves: [[[u16; 64]; 6]; 2],
+    /// How often a quiet move by side, from and to squares caused a cutoff
+    history: [[[i32; 64]; 64]; 2],
+}
+
+impl Default for Heuristics {
+    fn default() -> Self {
+        Heuristics {
+            killers: [[0; 2]; MAX_PLY],
+            countermoves: [[[0; 64]; 6]; 2],
+            history: [[[0; 64]; 64]; 2],
+        }
+    }
+}
+
+impl Heuristics {
+    /// Forgets the killers of the previous position and weakens its history
+    pub fn new_search(&mut self) {
+        self.killers = [[0; 2]; MAX_PLY];
+        for score in self.history.iter_mut().flatten().flatten() {
+            *score /= 2;
+        }
+    }
+
+    pub fn killers(&self, ply: usize) -> [u16; 2] {
+        self.killers[ply]
+    }
+
+    pub fn countermove(&self, game: &GameState, prev: Option<&Move>) -> u16 {
+        prev.map_or(0, |m| {
+            self.countermoves[game.turn.switch() as usize][m.piece as usize][m.to as usize]
+        })
+    }
+
+    pub fn history(&self, side: Sides, mov: &Move) -> i32 {
+        self.history[side as usize][mov.from as usize][mov.to as usize]
+    }
+
+    /// Rewards the quiet move `best` that caused a beta cutoff and penalises
+    /// the quiet moves `tried` before it
+    pub fn update(
+        &mut self,
+        game: &GameState,
+        prev: Option<&Move>,
+        ply: usize,
+        depth: u8,
+        best: &Move,
+        tried: &[Move],
+    ) {
+        let packed = best.pack();
+        let killers = &mut self.killers[ply];
+        if killers[0] != packed {
+            killers[1] = killers[0];
+            killers[0] = packed;
+        }
+        if let Some(prev) = prev {
+            self.countermoves[game.turn.switch() as usize][prev.piece as usize][prev.to as usize] =
+                packed;
+        }
+
+        let bonus = (depth as i32 * depth as i32).min(MAX_HISTORY);
+        let side = game.turn as usize;
+        let mut adjust = |mov: &Move, bonus: i32| {
+            let score = &mut self.history[side][mov.from as usize][mov.to as usize];
+            *score += bonus - *score * bonus.abs() / MAX_HISTORY;
+        };
+        adjust(best, bonus);
+        for mov in tried {
+            adjust(mov, -bonus);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    HashMove,
+    GoodCaptures,
+    Promotions,
+    Killers,
+    Countermove,
+    Quiets,
+    /// Captures that lose material by static exchange
+    BadCaptures,
+    Done,
+}
+
+/// Hands out the legal moves of a position best first, scoring and selecting
+/// each group only when the search gets to it, since a cutoff usually comes
+/// before the whole list is needed
+pub struct MovePicker {
+    stage: Stage,
+    hash_move: Option<Move>,
+    captures: Vec<(Move, i32)>,
+    promotions: Vec<(Move, i32)>,
+    quiets: Vec<(Move, i32)>,
+    quiets_scored: bool,
+    bad_captures: Vec<(Move, i32)>,
+    killers: [u16; 2],
+    countermove: u16,
+}
+
+impl MovePicker {
+    pub fn new(
+        game: &GameState,
+        moves: Vec<Move>,
+        hash_move: u16,
+        killers: [u16; 2],
+        countermove: u16,
+    ) -> Self {
+        let mut picker = MovePicker {
+            stage: Stage::HashMove,
+            hash_move: None,
+            captures: vec![],
+            promotions: vec![],
+            quiets: vec![],
+            quiets_scored: false,
+            bad_captures: vec![],
+            killers,
+            countermove,
+        };
+        for mov in moves {
+            if hash_move != 0 && mov.pack() == hash_move {
+                picker.hash_move = Some(mov);
+            } else if let Some(sq) = mov.capture {
+                let score = mvv_lva(game, &mov, sq);
+                picker.captures.push((mov, score));
+            } else if let Some(promotion) = mov.promotion {
+                picker
+                    .promotions
+                    .push((mov, SEE_VALUES[promotion as usize]));
+            } else {
+                picker.quiets.push((mov, 0));
+            }
+        }
+        picker
+    }
+
+    /// Stage of the move last handed out
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn next(&mut self, game: &GameState, heuristics: &Heuristics) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::HashMove => match self.hash_move.take() {
+                    Some(mov) => return Some(mov),
+                    None => self.stage = Stage::GoodCaptures,
+                },
+                Stage::GoodCaptures => match pick_best(&mut self.captures) {
+                    Some((mov, _)) if game.see(&mov) >= 0 => return Some(mov),
+                    Some(bad) => self.bad_captures.push(bad),
+                    None => self.stage = Stage::Promotions,
+                },
+                Stage::Promotions => match pick_best(&mut self.promotions) {
+                    Some((mov, _)) => return Some(mov),
+                    None => self.stage = Stage::Killers,
+                },
+                Stage::Killers => {
+                    for i in 0..2 {
+                        let killer = std::mem::take(&mut self.killers[i]);
+                        if let Some(mov) = self.take_quiet(killer) {
+                            return Some(mov);
+                        }
+                    }
+                    self.stage = Stage::Countermove;
+                }
+                Stage::Countermove => {
+                    let countermove = std::mem::take(&mut self.countermove);
+                    match self.take_quiet(countermove) {
+                        Some(mov) => return Some(mov),
+                        None => self.stage = Stage::Quiets,
+                    }
+                }
+                Stage::Quiets => {
+                    if !self.quiets_scored {
+                        for (mov, score) in self.quiets.iter_mut() {
+                            *score = heuristics.history(game.turn, mov);
+                        }
+                        self.quiets_scored = true;
+                    }
+                    match pick_best(&mut self.quiets) {
+                        Some((mov, _)) => return Some(mov),
+                        None => self.stage = Stage::BadCaptures,
+                    }
+                }
+                Stage::BadCaptures => match pick_best(&mut self.bad_captures) {
+                    Some((mov, _)) => return Some(mov),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    fn take_quiet(&mut self, packed: u16) -> Option<Move> {
+        if packed == 0 {
+            return None;
+        }
+        let index = self.quiets.iter().position(|(m, _)| m.pack() == packed)?;
+        Some(self.quiets.swap_remove(index).0)
+    }
+}
+
+/// Most valuable victim first, then least valuable attacker
+fn mvv_lva(game: &GameState, mov: &Move, capture: Square) -> i32 {
+    let victim = game
+        .piece_at(capture)
+        .map_or(Piece::Pawn, |(_, piece)| piece);
+    let promotion = mov.promotion.map_or(0, |piece| SEE_VALUES[piece as usize]);
+    (SEE_VALUES[victim as usize] + promotion) * 10 - SEE_VALUES[mov.piece as usize] / 100
+}
+
+/// Removes the highest scored move, without sorting the rest
+fn pick_best(moves: &mut Vec<(Move, i32)>) -> Option<(Move, i32)> {
+    let index = (0..moves.len()).max_by_key(|&i| moves[i].1)?;
+    Some(moves.swap_remove(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages() {
+        // Rxd5 wins a Pawn, Qxb7 loses the Queen, h3 is the killer
+        let game = GameState::from("2b1k3/1p6/p7/3p4/8/8/7P/1Q1RK3 w - - 0 1".to_string());
+        let find = |san: &str| game.parse_san(san).unwrap();
+        let moves = game.moves();
+        let count = moves.len();
+
+        let mut heuristics = Heuristics::default();
+        heuristics.update(&game, None, 0, 4, &find("Qb4"), &[find("Qc2")]);
+        let killers = [find("h3").pack(), 0];
+        let mut picker = MovePicker::new(&game, moves, find("Kf2").pack(), killers, 0);
+
+        let mut order = vec![];
+        while let Some(mov) = picker.next(&game, &heuristics) {
+            order.push(game.san(&mov));
+        }
+        assert_eq!(order.len(), count);
+        assert_eq!(order[..4], ["Kf2", "Rxd5", "h3", "Qb4"]);
+        assert_eq!(order.last().unwrap(), "Qxb7");
+        assert!(heuristics.history(Sides::White, &find("Qc2")) < 0);
+        assert_eq!(picker.stage(), Stage::Done);
+    }
+}
@@ -1,9 +1,10 @@
 use crate::board::Square::*;
 use crate::board::*;
+use crate::variant::Variant;
 
 // TODO: Optimize on redundant storage of data, especially with
 // edge cases like Castling and Capturing
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Move {
     pub piece: Piece,
     pub from: Square,
@@ -13,6 +14,8 @@ pub struct Move {
     pub capture: Option<Square>,
     pub castle: Option<Castle>,
     pub promotion: Option<Piece>,
+    /// Crazyhouse drop of `piece` from the pocket onto `to`, with `from == to`
+    pub drop: bool,
 }
 
 impl std::fmt::Display for Move {
@@ -38,6 +41,9 @@ impl std::fmt::Display for Move {
             Piece::Queen => "Queen",
             Piece::King => "King",
         };
+        if self.drop {
+            return write!(f, "{} drop to {:?}", piece, self.to);
+        }
         let mut msg = format!("{} {:?} to {:?}", piece, self.from, self.to);
         if self.capture.is_some() {
             msg.push_str(" with capture");
@@ -49,6 +55,74 @@ impl std::fmt::Display for Move {
     }
 }
 
+pub const ROOK_DIRS: [Direction; 4] = [Direction::N, Direction::S, Direction::E, Direction::W];
+pub const BISHOP_DIRS: [Direction; 4] =
+    [Direction::NE, Direction::SE, Direction::NW, Direction::SW];
+
+#[rustfmt::skip]
+pub const KING_DIRS: [Direction; 8] = [
+    Direction::N, Direction::S, Direction::E, Direction::W,
+    Direction::NE, Direction::SE, Direction::NW, Direction::SW,
+];
+
+pub fn knight_squares(square: Square) -> Vec<Square> {
+    let dirs = [
+        (Direction::NE, [Direction::N, Direction::E]),
+        (Direction::NW, [Direction::N, Direction::W]),
+        (Direction::SE, [Direction::S, Direction::E]),
+        (Direction::SW, [Direction::S, Direction::W]),
+    ];
+    let mut squares = vec![];
+    for (base, next) in dirs {
+        if let Some(sq) = square.step(base) {
+            for dir in next {
+                if let Some(sq) = sq.step(dir) {
+                    squares.push(sq);
+                }
+            }
+        }
+    }
+    squares
+}
+
+/// Squares attacked by `piece` of `side` on `square`, with sliders stopped
+/// by the first piece in `occupied`
+pub fn piece_attacks(piece: Piece, side: Sides, square: Square, occupied: &BitBoard) -> BitBoard {
+    let mut attacks = BitBoard(0);
+    let steps: &[Direction] = match (piece, side) {
+        (Piece::Pawn, Sides::White) => &[Direction::NW, Direction::NE],
+        (Piece::Pawn, Sides::Black) => &[Direction::SW, Direction::SE],
+        (Piece::King, _) => &KING_DIRS,
+        _ => &[],
+    };
+    for sq in steps.iter().filter_map(|&dir| square.step(dir)) {
+        attacks.0 |= 1u64 << sq as u64;
+    }
+    if piece == Piece::Knight {
+        for sq in knight_squares(square) {
+            attacks.0 |= 1u64 << sq as u64;
+        }
+    }
+
+    let rays: &[Direction] = match piece {
+        Piece::Rook => &ROOK_DIRS,
+        Piece::Bishop => &BISHOP_DIRS,
+        Piece::Queen => &KING_DIRS,
+        _ => &[],
+    };
+    for &dir in rays {
+        let mut curr = square;
+        while let Some(next) = curr.step(dir) {
+            curr = next;
+            attacks.0 |= 1u64 << curr as u64;
+            if occupied.get(curr) {
+                break;
+            }
+        }
+    }
+    attacks
+}
+
 struct Masks {
     our_board: BitBoard,
     opp_board: BitBoard,
@@ -59,43 +133,52 @@ impl GameState {
     pub fn apply(&self, mov: Move) -> Self {
         let mut game = self.clone();
 
-        game.board_mut(self.turn, mov.piece).flip(mov.from);
-        game.board_mut(self.turn, mov.piece).flip(mov.to);
+        if mov.drop {
+            game.board_mut(self.turn, mov.piece).flip(mov.to);
+            game.pockets[self.turn as usize][mov.piece as usize] -= 1;
+        } else {
+            game.board_mut(self.turn, mov.piece).flip(mov.from);
+            game.board_mut(self.turn, mov.piece).flip(mov.to);
+        }
 
         if let Some(s) = mov.capture {
             for piece in PIECES {
                 let board = game.board_mut(self.turn.switch(), piece);
                 if board.get(s) {
                     board.flip(s);
+                    if self.variant == Variant::Crazyhouse {
+                        // Promoted pieces are demoted back to Pawns in the pocket
+                        let pocket = match self.promoted.get(s) {
+                            true => Piece::Pawn,
+                            false => piece,
+                        };
+                        game.pockets[self.turn as usize][pocket as usize] += 1;
+                    }
                 }
             }
+            if game.promoted.get(s) {
+                game.promoted.flip(s);
+            }
+        }
+
+        if game.promoted.get(mov.from) && !mov.drop {
+            game.promoted.flip(mov.from);
+            game.promoted.flip(mov.to);
         }
 
         if let Some(castle) = mov.castle {
+            let (_, rook_to) = castle.targets();
             let board = game.board_mut(self.turn, Piece::Rook);
-            match castle {
-                Castle::WhiteQueen => {
-                    board.flip(A1);
-                    board.flip(D1);
-                }
-                Castle::WhiteKing => {
-                    board.flip(H1);
-                    board.flip(F1);
-                }
-                Castle::BlackQueen => {
-                    board.flip(A8);
-                    board.flip(D8);
-                }
-                Castle::BlackKing => {
-                    board.flip(H8);
-                    board.flip(F8);
-                }
-            }
+            board.flip(self.castle_rook(castle));
+            board.flip(rook_to);
         }
 
         if let Some(piece) = mov.promotion {
             game.board_mut(self.turn, Piece::Pawn).flip(mov.to);
             game.board_mut(self.turn, piece).flip(mov.to);
+            if self.variant == Variant::Crazyhouse {
+                game.promoted.flip(mov.to);
+            }
         }
 
         // Update Castling Rights
@@ -106,31 +189,7 @@ impl GameState {
             }
         }
 
-        if mov.piece == Piece::Rook {
-            let disable = match (self.turn, mov.from) {
-                (Sides::White, A1) => Castle::WhiteQueen as u8,
-                (Sides::White, H1) => Castle::WhiteKing as u8,
-                (Sides::Black, A8) => Castle::BlackQueen as u8,
-                (Sides::Black, H8) => Castle::BlackKing as u8,
-                _ => 0,
-            };
-            game.castle_rights &= 0b1111 ^ disable;
-        }
-
-        if !game.board(Sides::White, Piece::Rook).get(A1) {
-            game.castle_rights &= 0b1111 ^ Castle::WhiteQueen as u8;
-        }
-        if !game.board(Sides::White, Piece::Rook).get(H1) {
-            game.castle_rights &= 0b1111 ^ Castle::WhiteKing as u8;
-        }
-        if !game.board(Sides::Black, Piece::Rook).get(A8) {
-            game.castle_rights &= 0b1111 ^ Castle::BlackQueen as u8;
-        }
-        if !game.board(Sides::Black, Piece::Rook).get(H8) {
-            game.castle_rights &= 0b1111 ^ Castle::BlackKing as u8;
-        }
-
-        // TODO: Check castling when in check, probably in legal move generation
+        game.clear_lost_castle_rights();
 
         // Update En Passant square
         game.en_passant = None;
@@ -142,12 +201,142 @@ impl GameState {
             }
         }
 
+        game.halfmoves = match mov.piece == Piece::Pawn || mov.capture.is_some() {
+            true => 0,
+            false => self.halfmoves + 1,
+        };
+        if self.turn == Sides::Black {
+            game.fullmoves += 1;
+        }
+
+        game.turn = game.turn.switch();
+        game.after_move(self, &mov);
+        game
+    }
+
+    /// Passes the turn without moving, for null move pruning
+    pub fn apply_null(&self) -> Self {
+        let mut game = self.clone();
+        game.en_passant = None;
+        game.halfmoves += 1;
+        if self.turn == Sides::Black {
+            game.fullmoves += 1;
+        }
         game.turn = game.turn.switch();
         game
     }
 
+    /// Drops castling rights whose Rook has left its square
+    pub(crate) fn clear_lost_castle_rights(&mut self) {
+        for castle in CASTLES {
+            if !self
+                .board(castle.side(), Piece::Rook)
+                .get(self.castle_rook(castle))
+            {
+                self.castle_rights &= 0b1111 ^ castle as u8;
+            }
+        }
+    }
+
+    /// Legal moves, pseudo-legal moves filtered by whether they leave the King attacked
     pub fn moves(&self) -> Vec<Move> {
-        self.pseudo_moves()
+        if self.variant_end().is_some() {
+            return vec![];
+        }
+        let moves = self
+            .pseudo_moves()
+            .into_iter()
+            .filter(|m| self.is_legal(m))
+            .collect();
+        self.filter_variant_moves(moves)
+    }
+
+    pub fn is_legal(&self, mov: &Move) -> bool {
+        let opp = self.turn.switch();
+        if let Some(castle) = mov.castle {
+            let (king_to, _) = castle.targets();
+            let (lo, hi) = (
+                (mov.from as u8).min(king_to as u8),
+                (mov.from as u8).max(king_to as u8),
+            );
+            if (lo..=hi).any(|sq| self.attacked(Square::from(sq), opp)) {
+                return false;
+            }
+        }
+        self.apply(mov.clone()).king_safe(self.turn)
+    }
+
+    pub fn king_square(&self, side: Sides) -> Option<Square> {
+        SQUARES
+            .into_iter()
+            .find(|&sq| self.board(side, Piece::King).get(sq))
+    }
+
+    pub fn in_check(&self) -> bool {
+        !self.king_safe(self.turn)
+    }
+
+    /// Whether any piece of side `by` attacks `square`
+    pub fn attacked(&self, square: Square, by: Sides) -> bool {
+        let pawn_dirs = match by {
+            Sides::White => [Direction::SW, Direction::SE],
+            Sides::Black => [Direction::NW, Direction::NE],
+        };
+        let pawns = self.board(by, Piece::Pawn);
+        if pawn_dirs
+            .into_iter()
+            .any(|dir| square.step(dir).is_some_and(|sq| pawns.get(sq)))
+        {
+            return true;
+        }
+
+        let knights = self.board(by, Piece::Knight);
+        if knight_squares(square).into_iter().any(|sq| knights.get(sq)) {
+            return true;
+        }
+
+        let king = self.board(by, Piece::King);
+        if KING_DIRS
+            .into_iter()
+            .any(|dir| square.step(dir).is_some_and(|sq| king.get(sq)))
+        {
+            return true;
+        }
+
+        let occupied = self.occupied();
+        let queens = self.board(by, Piece::Queen);
+        for (dirs, slider) in [(&ROOK_DIRS, Piece::Rook), (&BISHOP_DIRS, Piece::Bishop)] {
+            let sliders = self.board(by, slider);
+            for &dir in dirs {
+                let mut curr = square;
+                while let Some(next) = curr.step(dir) {
+                    curr = next;
+                    if sliders.get(curr) || queens.get(curr) {
+                        return true;
+                    }
+                    if occupied.get(curr) {
+                        break;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    pub fn occupied(&self) -> BitBoard {
+        BitBoard(self.state.iter().flatten().fold(0, |acc, b| acc | b.0))
+    }
+
+    /// Piece of either side standing on `square`
+    pub fn piece_at(&self, square: Square) -> Option<(Sides, Piece)> {
+        for side in [Sides::White, Sides::Black] {
+            for piece in PIECES {
+                if self.board(side, piece).get(square) {
+                    return Some((side, piece));
+                }
+            }
+        }
+        None
     }
 
     pub fn pseudo_moves(&self) -> Vec<Move> {
@@ -177,64 +366,33 @@ impl GameState {
             }
         }
 
-        // TODO: Castling, make this better
+        if self.variant == Variant::Crazyhouse {
+            moves.extend(self.pseudo_drops(&masks.block_board));
+        }
 
-        match self.turn {
-            Sides::White => {
-                if self.castle_rights & Castle::WhiteQueen as u8 != 0
-                    && masks.block_board.0
-                        & (1u64 << B1 as u8 | 1u64 << C1 as u8 | 1u64 << D1 as u8)
-                        == 0
-                {
-                    moves.push(Move {
-                        piece: Piece::King,
-                        from: E1,
-                        to: C1,
-                        capture: None,
-                        castle: Some(Castle::WhiteQueen),
-                        promotion: None,
-                    });
+        if let Some(king) = self.king_square(self.turn) {
+            for castle in CASTLES {
+                if castle.side() != self.turn || self.castle_rights & castle as u8 == 0 {
+                    continue;
                 }
-
-                if self.castle_rights & Castle::WhiteKing as u8 != 0
-                    && masks.block_board.0 & (1u64 << F1 as u8 | 1u64 << G1 as u8) == 0
-                {
+                // Every square the King and Rook cross must be empty besides themselves
+                let rook = self.castle_rook(castle);
+                let (king_to, rook_to) = castle.targets();
+                let span = [king, rook, king_to, rook_to].map(|sq| sq as u8);
+                let lo = *span.iter().min().unwrap();
+                let hi = *span.iter().max().unwrap();
+                let blocked = (lo..=hi)
+                    .map(Square::from)
+                    .any(|sq| sq != king && sq != rook && masks.block_board.get(sq));
+                if !blocked {
                     moves.push(Move {
                         piece: Piece::King,
-                        from: E1,
-                        to: G1,
+                        from: king,
+                        to: king_to,
                         capture: None,
-                        castle: Some(Castle::WhiteKing),
-                        promotion: None,
-                    });
-                }
-            }
-            Sides::Black => {
-                if self.castle_rights & Castle::BlackQueen as u8 != 0
-                    && masks.block_board.0
-                        & (1u64 << B8 as u8 | 1u64 << C8 as u8 | 1u64 << D8 as u8)
-                        == 0
-                {
-                    moves.push(Move {
-                        piece: Piece::King,
-                        from: E8,
-                        to: C8,
-                        capture: None,
-                        castle: Some(Castle::BlackQueen),
-                        promotion: None,
-                    });
-                }
-
-                if self.castle_rights & Castle::BlackKing as u8 != 0
-                    && masks.block_board.0 & (1u64 << F8 as u8 | 1u64 << G8 as u8) == 0
-                {
-                    moves.push(Move {
-                        piece: Piece::King,
-                        from: E8,
-                        to: G8,
-                        capture: None,
-                        castle: Some(Castle::BlackKing),
+                        castle: Some(castle),
                         promotion: None,
+                        drop: false,
                     });
                 }
             }
@@ -263,7 +421,9 @@ impl GameState {
             if let Some(s) = square.step(Direction::N) {
                 if !masks.block_board.get(s) {
                     moves.push((s, None));
-                    if (A2..=H2).contains(&square) {
+                    // Horde Pawns may also double step from the first rank
+                    let first_rank = self.variant == Variant::Horde && (A1..=H1).contains(&square);
+                    if (A2..=H2).contains(&square) || first_rank {
                         if let Some(s) = s.step(Direction::N) {
                             if !masks.block_board.get(s) {
                                 moves.push((s, None));
@@ -309,7 +469,11 @@ impl GameState {
 
         for (to, capture) in moves.into_iter() {
             if (self.turn == Sides::White && to <= H8) || (self.turn == Sides::Black && to >= A1) {
-                for piece in [Piece::Bishop, Piece::Knight, Piece::Rook, Piece::Queen] {
+                let mut promotions = vec![Piece::Bishop, Piece::Knight, Piece::Rook, Piece::Queen];
+                if self.variant == Variant::Antichess {
+                    promotions.push(Piece::King);
+                }
+                for piece in promotions {
                     final_moves.push(Move {
                         piece: Piece::Pawn,
                         from: square,
@@ -317,6 +481,7 @@ impl GameState {
                         capture,
                         castle: None,
                         promotion: Some(piece),
+                        drop: false,
                     });
                 }
             } else {
@@ -327,6 +492,7 @@ impl GameState {
                     capture,
                     castle: None,
                     promotion: None,
+                    drop: false,
                 });
             }
         }
@@ -378,29 +544,18 @@ impl GameState {
                 capture,
                 castle: None,
                 promotion: None,
+                drop: false,
             })
             .collect()
     }
 
     fn pseudo_moves_knight(&self, square: Square, masks: &Masks) -> Vec<Move> {
-        let dirs = [
-            (Direction::NE, [Direction::N, Direction::E]),
-            (Direction::NW, [Direction::N, Direction::W]),
-            (Direction::SE, [Direction::S, Direction::E]),
-            (Direction::SW, [Direction::S, Direction::W]),
-        ];
         let mut moves = vec![];
-        for (base, next) in dirs {
-            if let Some(sq) = square.step(base) {
-                for dir in next {
-                    if let Some(sq) = sq.step(dir) {
-                        if masks.opp_board.get(sq) {
-                            moves.push((sq, Some(sq)));
-                        } else if !masks.our_board.get(sq) {
-                            moves.push((sq, None));
-                        }
-                    }
-                }
+        for sq in knight_squares(square) {
+            if masks.opp_board.get(sq) {
+                moves.push((sq, Some(sq)));
+            } else if !masks.our_board.get(sq) {
+                moves.push((sq, None));
             }
         }
         moves
@@ -412,6 +567,7 @@ impl GameState {
                 capture,
                 castle: None,
                 promotion: None,
+                drop: false,
             })
             .collect()
     }
@@ -447,6 +603,7 @@ impl GameState {
                 capture,
                 castle: None,
                 promotion: None,
+                drop: false,
             })
             .collect()
     }
@@ -492,6 +649,16 @@ mod tests {
         count
     }
 
+    fn perft(game: &GameState, depth: u8) -> usize {
+        if depth == 0 {
+            return 1;
+        }
+        game.moves()
+            .into_iter()
+            .map(|m| perft(&game.apply(m), depth - 1))
+            .sum()
+    }
+
     #[test]
     fn initial_state_pseudo() {
         let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
@@ -502,6 +669,23 @@ mod tests {
         // assert_eq!(divide(fen, 5), 4865609);
     }
 
+    // https://www.chessprogramming.org/Perft_Results
+    #[test]
+    fn position_five_legal() {
+        let fen = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+        let game = GameState::from(fen.to_string());
+        assert_eq!(perft(&game, 1), 44);
+        assert_eq!(perft(&game, 2), 1486);
+    }
+
+    #[test]
+    fn kiwipete_legal() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let game = GameState::from(fen.to_string());
+        assert_eq!(perft(&game, 1), 48);
+        assert_eq!(perft(&game, 2), 2039);
+    }
+
     // #[test]
     // https://www.chessprogramming.org/Perft_Results
     // fn position_five_pseudo() {
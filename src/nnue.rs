@@ -0,0 +1,454 @@
+use crate::board::*;
+use crate::endgame;
+use crate::eval::evaluate;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+/// HalfKP inputs: for each square of the perspective's King, every non-King
+/// piece of either colour on every square
+pub const INPUTS: usize = 64 * 640;
+
+/// Quantisation of the hidden layer, which is clipped to 0..=QA
+const QA: i32 = 255;
+/// Quantisation of the output weights
+const QB: i32 = 64;
+/// Centipawns per unit of network output
+const SCALE: i64 = 400;
+/// Network evaluations are kept below the scores of known wins
+const MAX_EVAL: i32 = endgame::KNOWN_WIN - 1;
+
+/// Most pieces besides the Kings a network evaluates, which is as many as
+/// chess starts with
+const MAX_PIECES: usize = 30;
+
+const MAGIC: &[u8; 4] = b"RSNN";
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum NnueError {
+    Io(std::io::Error),
+    Magic,
+    Version(u32),
+    Size,
+    Overflow,
+}
+
+impl std::fmt::Display for NnueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NnueError::Io(e) => write!(f, "Network read failed: {e}"),
+            NnueError::Magic => write!(f, "Not a network file"),
+            NnueError::Version(v) => write!(f, "Unsupported network version {v}"),
+            NnueError::Size => write!(f, "Invalid network size"),
+            NnueError::Overflow => write!(f, "Network weights too large"),
+        }
+    }
+}
+
+impl std::error::Error for NnueError {}
+
+impl From<std::io::Error> for NnueError {
+    fn from(e: std::io::Error) -> Self {
+        NnueError::Io(e)
+    }
+}
+
+/// Which evaluation the search uses
+#[derive(Clone, Default)]
+pub enum Evaluator {
+    #[default]
+    Classical,
+    Nnue(Arc<Network>),
+}
+
+impl Evaluator {
+    /// The network in the file at `path`, or the classical evaluation when
+    /// the path is empty
+    pub fn from_file(path: &str) -> Result<Self, NnueError> {
+        match path {
+            "" | "<empty>" => Ok(Evaluator::Classical),
+            path => {
+                let file = std::io::BufReader::new(std::fs::File::open(path)?);
+                Ok(Evaluator::Nnue(Arc::new(Network::load(file)?)))
+            }
+        }
+    }
+}
+
+/// A network with one hidden layer per perspective, stored as little endian
+/// after a header of the magic bytes, version and hidden layer size:
+/// feature weights by input, feature biases, output weights for the side to
+/// move then the other side, and the output bias
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_bias: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+fn read_i16s<R: Read>(reader: &mut R, count: usize) -> Result<Vec<i16>, NnueError> {
+    let mut bytes = vec![0; count * 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect())
+}
+
+impl Network {
+    pub fn load<R: Read>(mut reader: R) -> Result<Self, NnueError> {
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(NnueError::Magic);
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(NnueError::Version(version));
+        }
+        let hidden = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        if hidden == 0 || hidden > 4096 {
+            return Err(NnueError::Size);
+        }
+
+        let feature_weights = read_i16s(&mut reader, INPUTS * hidden)?;
+        let feature_bias = read_i16s(&mut reader, hidden)?;
+        let output_weights = read_i16s(&mut reader, 2 * hidden)?;
+        let mut bias = [0; 4];
+        reader.read_exact(&mut bias)?;
+        if reader.read(&mut [0])? != 0 {
+            return Err(NnueError::Size);
+        }
+        let net = Network {
+            hidden,
+            feature_weights,
+            feature_bias,
+            output_weights,
+            output_bias: i32::from_le_bytes(bias),
+        };
+        if !net.in_range() {
+            return Err(NnueError::Overflow);
+        }
+        Ok(net)
+    }
+
+    /// Whether the hidden sums of up to `MAX_PIECES` pieces fit in an i16,
+    /// from each neuron's bias and largest weights, and the output in an i32
+    fn in_range(&self) -> bool {
+        let mut column = vec![0; INPUTS];
+        let hidden = (0..self.hidden).all(|n| {
+            for (i, w) in column.iter_mut().enumerate() {
+                *w = (self.feature_weights[i * self.hidden + n] as i32).abs();
+            }
+            let (_, _, largest) = column.select_nth_unstable(INPUTS - MAX_PIECES - 1);
+            let sum: i32 = largest.iter().sum();
+            (self.feature_bias[n] as i32).abs() + sum <= i16::MAX as i32
+        });
+        let output = self
+            .output_weights
+            .iter()
+            .map(|&w| QA as i64 * (w as i64).abs())
+            .sum::<i64>()
+            + (self.output_bias as i64).abs();
+        hidden && output <= i32::MAX as i64
+    }
+
+    /// Whether `game` has few enough pieces for the sums not to overflow,
+    /// which only Horde goes past
+    pub fn covers(game: &GameState) -> bool {
+        let pieces = FEATURE_PIECES
+            .into_iter()
+            .flat_map(|piece| [Sides::White, Sides::Black].map(|side| game.board(side, piece)))
+            .map(|board| board.0.count_ones() as usize)
+            .sum::<usize>();
+        pieces <= MAX_PIECES
+    }
+
+    pub fn save<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.hidden as u32).to_le_bytes())?;
+        for values in [
+            &self.feature_weights,
+            &self.feature_bias,
+            &self.output_weights,
+        ] {
+            let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+            writer.write_all(&bytes)?;
+        }
+        writer.write_all(&self.output_bias.to_le_bytes())
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    fn weights(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+
+    /// Evaluation in centipawns from the side to move's perspective
+    pub fn evaluate(&self, acc: &Accumulator, turn: Sides) -> i32 {
+        let (us, them) = (
+            &acc.values[turn as usize],
+            &acc.values[turn.switch() as usize],
+        );
+        let (ours, theirs) = self.output_weights.split_at(self.hidden);
+        let layer = |values: &[i16], weights: &[i16]| -> i32 {
+            values
+                .iter()
+                .zip(weights)
+                .map(|(&v, &w)| (v as i32).clamp(0, QA) * w as i32)
+                .sum()
+        };
+        let sum = layer(us, ours) + layer(them, theirs) + self.output_bias;
+        (sum as i64 * SCALE / (QA * QB) as i64).clamp(-MAX_EVAL as i64, MAX_EVAL as i64) as i32
+    }
+}
+
+/// Input index of `piece` of `side` on `square`, seen by `perspective`
+/// with its King on `king`, with the board flipped for Black
+fn feature(perspective: Sides, king: Square, side: Sides, piece: Piece, square: Square) -> usize {
+    let orient = |sq: Square| match perspective {
+        Sides::White => sq as usize,
+        Sides::Black => sq as usize ^ 56,
+    };
+    let colour = (side != perspective) as usize;
+    orient(king) * 640 + (piece as usize * 2 + colour) * 64 + orient(square)
+}
+
+/// Pieces the features cover, which is all but the Kings
+const FEATURE_PIECES: [Piece; 5] = [
+    Piece::Pawn,
+    Piece::Rook,
+    Piece::Knight,
+    Piece::Bishop,
+    Piece::Queen,
+];
+
+fn squares(mut board: u64) -> impl Iterator<Item = Square> {
+    std::iter::from_fn(move || {
+        if board == 0 {
+            return None;
+        }
+        let sq = board.trailing_zeros() as u8;
+        board &= board - 1;
+        Some(Square::from(sq))
+    })
+}
+
+/// Hidden layer sums for both perspectives, with the pieces they were
+/// computed from so they can be brought up to date with the next position
+#[derive(Clone)]
+pub struct Accumulator {
+    values: [Vec<i16>; 2],
+    state: [[BitBoard; 6]; 2],
+}
+
+impl Accumulator {
+    pub fn new(net: &Network, game: &GameState) -> Self {
+        let mut acc = Accumulator {
+            values: [vec![0; net.hidden], vec![0; net.hidden]],
+            state: game.state.clone(),
+        };
+        acc.refresh(net, Sides::White);
+        acc.refresh(net, Sides::Black);
+        acc
+    }
+
+    fn king(&self, side: Sides) -> Square {
+        let kings = self.state[side as usize][Piece::King as usize].0;
+        // Variants without a King are seen as having it on H1
+        Square::from((kings.trailing_zeros() as u8).min(63))
+    }
+
+    fn refresh(&mut self, net: &Network, perspective: Sides) {
+        let king = self.king(perspective);
+        let values = &mut self.values[perspective as usize];
+        values.copy_from_slice(&net.feature_bias);
+        for side in [Sides::White, Sides::Black] {
+            for piece in FEATURE_PIECES {
+                for sq in squares(self.state[side as usize][piece as usize].0) {
+                    let weights = net.weights(feature(perspective, king, side, piece, sq));
+                    for (v, &w) in values.iter_mut().zip(weights) {
+                        *v += w;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sets this to `parent` updated for `game`, the position after it. Only
+    /// the pieces that changed are added and removed, except for a
+    /// perspective whose King moved, as every one of its features changes.
+    pub fn update(&mut self, net: &Network, parent: &Accumulator, game: &GameState) {
+        self.state = game.state.clone();
+        for perspective in [Sides::White, Sides::Black] {
+            let p = perspective as usize;
+            self.values[p].resize(net.hidden, 0);
+            if self.king(perspective) != parent.king(perspective) {
+                self.refresh(net, perspective);
+                continue;
+            }
+            self.values[p].copy_from_slice(&parent.values[p]);
+            let king = self.king(perspective);
+            for side in [Sides::White, Sides::Black] {
+                for piece in FEATURE_PIECES {
+                    let before = parent.state[side as usize][piece as usize].0;
+                    let after = self.state[side as usize][piece as usize].0;
+                    for sq in squares(before & !after) {
+                        let weights = net.weights(feature(perspective, king, side, piece, sq));
+                        for (v, &w) in self.values[p].iter_mut().zip(weights) {
+                            *v -= w;
+                        }
+                    }
+                    for sq in squares(after & !before) {
+                        let weights = net.weights(feature(perspective, king, side, piece, sq));
+                        for (v, &w) in self.values[p].iter_mut().zip(weights) {
+                            *v += w;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Evaluator {
+    /// Evaluates `game`, where `acc` must be up to date with it when using
+    /// a network
+    pub fn evaluate(&self, game: &GameState, acc: Option<&Accumulator>) -> i32 {
+        match (self, acc) {
+            (Evaluator::Nnue(net), Some(acc)) if Network::covers(game) => {
+                endgame::evaluate(game, || net.evaluate(acc, game.turn))
+            }
+            _ => evaluate(game),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::START_FEN;
+
+    /// Small untrained network with weights from a fixed sequence
+    fn network(hidden: usize) -> Network {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = |range: i16| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % (2 * range as u64 + 1)) as i16 - range
+        };
+        Network {
+            hidden,
+            feature_weights: (0..INPUTS * hidden).map(|_| next(20)).collect(),
+            feature_bias: (0..hidden).map(|_| next(50)).collect(),
+            output_weights: (0..2 * hidden).map(|_| next(60)).collect(),
+            output_bias: 1000,
+        }
+    }
+
+    #[test]
+    fn incremental_matches_refresh() {
+        let net = network(16);
+        let mut game = GameState::from(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1".to_string(),
+        );
+        let mut acc = Accumulator::new(&net, &game);
+        // Castling, a capture, a double step and en passant, and a King move
+        for san in ["O-O", "hxg2", "a4", "bxa3", "Kxg2", "a2", "Rab1", "axb1=Q"] {
+            let next = game.apply(game.parse_san(san).expect(san));
+            let mut child = acc.clone();
+            child.update(&net, &acc, &next);
+            let fresh = Accumulator::new(&net, &next);
+            assert_eq!(child.values, fresh.values, "after {san}");
+            assert_eq!(
+                net.evaluate(&child, next.turn),
+                net.evaluate(&fresh, next.turn)
+            );
+            (game, acc) = (next, child);
+        }
+    }
+
+    #[test]
+    fn search_with_network() {
+        use crate::search::{Limits, Search};
+        let game = GameState::from("4k3/8/8/3q4/8/8/3R4/3RK3 w - - 0 1".to_string());
+        let mut search = Search::new(Limits {
+            depth: Some(3),
+            ..Default::default()
+        });
+        search.evaluator = Evaluator::Nnue(Arc::new(network(8)));
+        let result = search.run(&game, &[]);
+        assert!(game.moves().contains(&result.best.unwrap()));
+        assert_eq!(result.depth, 3);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let net = network(4);
+        let mut file = vec![];
+        net.save(&mut file).unwrap();
+        let loaded = Network::load(file.as_slice()).unwrap();
+        assert_eq!(loaded.hidden(), 4);
+        assert_eq!(loaded.feature_weights, net.feature_weights);
+        assert_eq!(loaded.output_bias, net.output_bias);
+
+        assert!(matches!(Network::load(&file[..20]), Err(NnueError::Io(_))));
+        file[0] = b'X';
+        assert!(matches!(
+            Network::load(file.as_slice()),
+            Err(NnueError::Magic)
+        ));
+
+        // Mirrored positions evaluate the same for the side to move
+        let start = GameState::from(START_FEN.to_string());
+        let acc = Accumulator::new(&net, &start);
+        let black = GameState::from(START_FEN.replace(" w ", " b "));
+        assert_eq!(
+            net.evaluate(&acc, Sides::White),
+            net.evaluate(&Accumulator::new(&net, &black), Sides::Black)
+        );
+    }
+
+    #[test]
+    fn weights_in_range() {
+        // 30 pieces on the largest weights reach i16::MAX with the bias
+        let hidden = 4;
+        let mut net = Network {
+            hidden,
+            feature_weights: vec![1000; INPUTS * hidden],
+            feature_bias: vec![i16::MAX - 30 * 1000; hidden],
+            output_weights: vec![i16::MAX; 2 * hidden],
+            output_bias: i32::MAX - 2 * hidden as i32 * QA * i16::MAX as i32,
+        };
+        let load = |net: &Network| {
+            let mut file = vec![];
+            net.save(&mut file).unwrap();
+            Network::load(file.as_slice())
+        };
+        let loaded = load(&net).unwrap();
+        let start = GameState::from(START_FEN.to_string());
+        let acc = Accumulator::new(&loaded, &start);
+        assert_eq!(loaded.evaluate(&acc, Sides::White), MAX_EVAL);
+        let mut child = acc.clone();
+        let next = start.apply(start.parse_san("e4").unwrap());
+        child.update(&loaded, &acc, &next);
+        assert_eq!(child.values, acc.values);
+
+        // One more in a bias or in the output overflows
+        net.output_bias += 1;
+        assert!(matches!(load(&net), Err(NnueError::Overflow)));
+        net.output_bias -= 1;
+        net.feature_bias[3] += 1;
+        assert!(matches!(load(&net), Err(NnueError::Overflow)));
+
+        // Horde has more pieces than that, and is evaluated classically
+        let horde = GameState::from(crate::variant::Variant::Horde.start_fen().to_string());
+        assert!(!Network::covers(&horde));
+        assert!(Network::covers(&start));
+    }
+}
@@ -0,0 +1,204 @@
+use crate::board::*;
+use crate::moves::Move;
+
+impl Piece {
+    /// Uppercase letter used by SAN and FEN
+    pub fn symbol(&self) -> char {
+        match self {
+            Piece::Pawn => 'P',
+            Piece::Rook => 'R',
+            Piece::Knight => 'N',
+            Piece::Bishop => 'B',
+            Piece::Queen => 'Q',
+            Piece::King => 'K',
+        }
+    }
+
+    pub fn from_symbol(c: char) -> Option<Piece> {
+        match c.to_ascii_uppercase() {
+            'P' => Some(Piece::Pawn),
+            'R' => Some(Piece::Rook),
+            'N' => Some(Piece::Knight),
+            'B' => Some(Piece::Bishop),
+            'Q' => Some(Piece::Queen),
+            'K' => Some(Piece::King),
+            _ => None,
+        }
+    }
+}
+
+impl Move {
+    /// Long algebraic notation as used by UCI, e.g. "e2e4" or "e7e8q"
+    pub fn uci(&self) -> String {
+        if self.drop {
+            return format!("{}@{}", self.piece.symbol(), self.to.name());
+        }
+        let mut msg = format!("{}{}", self.from.name(), self.to.name());
+        if let Some(piece) = self.promotion {
+            msg.push(piece.symbol().to_ascii_lowercase());
+        }
+        msg
+    }
+}
+
+impl GameState {
+    /// UCI notation of a move, writing castling as King takes Rook in Chess960
+    pub fn uci(&self, mov: &Move) -> String {
+        match mov.castle {
+            Some(castle) if self.chess960 => {
+                format!("{}{}", mov.from.name(), self.castle_rook(castle).name())
+            }
+            _ => mov.uci(),
+        }
+    }
+
+    /// Parses a UCI move, also accepting standard castling notation in Chess960
+    pub fn parse_uci(&self, uci: &str) -> Option<Move> {
+        let moves = self.moves();
+        moves
+            .iter()
+            .find(|m| self.uci(m) == uci)
+            .or_else(|| moves.iter().find(|m| m.uci() == uci))
+            .cloned()
+    }
+
+    /// Standard Algebraic Notation of a legal move, including check and mate suffixes
+    pub fn san(&self, mov: &Move) -> String {
+        let mut msg = self.san_body(mov);
+        let next = self.apply(mov.clone());
+        if next.in_check() {
+            msg.push(if next.moves().is_empty() { '#' } else { '+' });
+        }
+        msg
+    }
+
+    fn san_body(&self, mov: &Move) -> String {
+        if mov.drop {
+            return mov.uci();
+        }
+        if let Some(castle) = mov.castle {
+            return match castle {
+                Castle::WhiteKing | Castle::BlackKing => "O-O".to_string(),
+                Castle::WhiteQueen | Castle::BlackQueen => "O-O-O".to_string(),
+            };
+        }
+
+        let mut msg = String::new();
+        if mov.piece == Piece::Pawn {
+            if mov.capture.is_some() {
+                msg.push((b'a' + mov.from.file()) as char);
+            }
+        } else {
+            msg.push(mov.piece.symbol());
+            let others: Vec<Move> = self
+                .moves()
+                .into_iter()
+                .filter(|m| m.piece == mov.piece && m.to == mov.to && m.from != mov.from)
+                .collect();
+            if !others.is_empty() {
+                let name = mov.from.name();
+                if others.iter().all(|m| m.from.file() != mov.from.file()) {
+                    msg.push_str(&name[..1]);
+                } else if others.iter().all(|m| m.from.rank() != mov.from.rank()) {
+                    msg.push_str(&name[1..]);
+                } else {
+                    msg.push_str(&name);
+                }
+            }
+        }
+        if mov.capture.is_some() {
+            msg.push('x');
+        }
+        msg.push_str(&mov.to.name());
+        if let Some(piece) = mov.promotion {
+            msg.push('=');
+            msg.push(piece.symbol());
+        }
+        msg
+    }
+
+    /// Resolves a SAN move against the legal moves, tolerating missing or redundant
+    /// capture markers, disambiguation and annotation suffixes
+    pub fn parse_san(&self, san: &str) -> Option<Move> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+        let moves = self.moves();
+
+        let castle = match san {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+        if let Some(king_side) = castle {
+            return moves.into_iter().find(|m| match m.castle {
+                Some(Castle::WhiteKing | Castle::BlackKing) => king_side,
+                Some(Castle::WhiteQueen | Castle::BlackQueen) => !king_side,
+                None => false,
+            });
+        }
+
+        if let Some((piece, dest)) = san.split_once('@') {
+            let piece = match piece.chars().next() {
+                Some(c) => Piece::from_symbol(c)?,
+                None => Piece::Pawn,
+            };
+            let to = Square::try_from(dest).ok()?;
+            return moves
+                .into_iter()
+                .find(|m| m.drop && m.piece == piece && m.to == to);
+        }
+
+        let mut chars: Vec<char> = san.chars().filter(|&c| c != 'x' && c != '-').collect();
+
+        let piece = match chars.first() {
+            Some(c) if c.is_ascii_uppercase() => {
+                let piece = Piece::from_symbol(*c)?;
+                chars.remove(0);
+                piece
+            }
+            _ => Piece::Pawn,
+        };
+
+        let mut promotion = None;
+        if let Some(c) = chars.last().filter(|c| c.is_ascii_alphabetic()) {
+            if piece != Piece::Pawn {
+                return None;
+            }
+            promotion = Some(Piece::from_symbol(*c)?);
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+
+        if chars.len() < 2 {
+            return None;
+        }
+        let dest: String = chars.split_off(chars.len() - 2).into_iter().collect();
+        let to = Square::try_from(dest.as_str()).ok()?;
+
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in chars {
+            match c {
+                'a'..='h' => from_file = Some(c as u8 - b'a'),
+                '1'..='8' => from_rank = Some(c as u8 - b'1'),
+                _ => return None,
+            }
+        }
+
+        let mut candidates = moves.into_iter().filter(|m| {
+            m.castle.is_none()
+                && !m.drop
+                && m.piece == piece
+                && m.to == to
+                && m.promotion == promotion
+                && from_file.is_none_or(|f| m.from.file() == f)
+                && from_rank.is_none_or(|r| m.from.rank() == r)
+        });
+        let mov = candidates.next()?;
+        match candidates.next() {
+            Some(_) => None,
+            None => Some(mov),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use crate::board::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Checkmate delivered by the given side
+    Checkmate(Sides),
+    Stalemate,
+    FiftyMoves,
+    InsufficientMaterial,
+    /// Variant specific ending, won by the given side or drawn
+    Variant(Option<Sides>),
+}
+
+impl Outcome {
+    pub fn winner(&self) -> Option<Sides> {
+        match self {
+            Outcome::Checkmate(side) => Some(*side),
+            Outcome::Variant(side) => *side,
+            _ => None,
+        }
+    }
+
+    /// Result as written in PGN
+    pub fn result(&self) -> &'static str {
+        match self.winner() {
+            Some(Sides::White) => "1-0",
+            Some(Sides::Black) => "0-1",
+            None => "1/2-1/2",
+        }
+    }
+}
+
+impl GameState {
+    /// How the game has ended, if it has. Repetitions need the game history
+    /// and are left to the caller.
+    pub fn outcome(&self) -> Option<Outcome> {
+        if let Some(outcome) = self.variant_end() {
+            return Some(outcome);
+        }
+        if self.moves().is_empty() {
+            return Some(self.no_moves_outcome());
+        }
+        if self.halfmoves >= 100 {
+            return Some(Outcome::FiftyMoves);
+        }
+        if self.insufficient_material() {
+            return Some(Outcome::InsufficientMaterial);
+        }
+        None
+    }
+}
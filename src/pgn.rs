@@ -0,0 +1,469 @@
+use crate::board::*;
+use crate::fen::FenError;
+use crate::moves::Move;
+use crate::variant::{PositionError, Variant};
+use std::io::BufRead;
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+#[derive(Clone, Debug)]
+pub struct PgnGame {
+    pub headers: Vec<(String, String)>,
+    /// Mainline moves, left empty when reading headers only
+    pub moves: Vec<Move>,
+    pub result: Option<String>,
+}
+
+impl PgnGame {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Starts an empty game record from any valid position, tagging its variant
+    /// and setup so that the written PGN replays under the same rules
+    pub fn from_position(start: &GameState) -> Result<PgnGame, PositionError> {
+        start.validate()?;
+        let mut game = PgnGame {
+            headers: vec![],
+            moves: vec![],
+            result: None,
+        };
+        for name in ["Event", "Site", "Date", "Round", "White", "Black"] {
+            game.set_header(name, "?");
+        }
+        game.set_header("Result", "*");
+
+        let custom = *start != GameState::start(start.variant);
+        if start.chess960 && start.variant == Variant::Standard {
+            game.set_header("Variant", "Chess960");
+        } else if start.variant != Variant::Standard {
+            game.set_header("Variant", start.variant.name());
+        } else if custom {
+            game.set_header("Variant", "From Position");
+        }
+        if custom {
+            game.set_header("SetUp", "1");
+            game.set_header("FEN", &start.fen());
+        }
+        Ok(game)
+    }
+
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        match self.headers.iter_mut().find(|(key, _)| key == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.headers.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Position the game starts from, taken from the FEN tag when present or
+    /// else the start of the variant, played under the rules named by the
+    /// Variant tag
+    pub fn start(&self) -> Result<GameState, FenError> {
+        let tag = self.header("Variant");
+        let chess960 = matches!(tag, Some("Chess960" | "Fischerandom"));
+        let variant = tag.and_then(Variant::from_name).unwrap_or_default();
+        let mut game = GameState::from_fen(self.header("FEN").unwrap_or(variant.start_fen()))?;
+        game.variant = variant;
+        game.chess960 |= chess960;
+        Ok(game)
+    }
+}
+
+impl std::fmt::Display for PgnGame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let result = self.result.as_deref().unwrap_or("*");
+        for (key, value) in &self.headers {
+            let value = match key.as_str() {
+                "Result" => result.to_string(),
+                _ => value.replace('\\', "\\\\").replace('"', "\\\""),
+            };
+            writeln!(f, "[{key} \"{value}\"]")?;
+        }
+        writeln!(f)?;
+
+        // Moves from a start that can't be read are kept in coordinate
+        // notation rather than lost
+        let Ok(mut state) = self.start() else {
+            let mut tokens: Vec<String> = self.moves.iter().map(Move::uci).collect();
+            tokens.push(result.to_string());
+            return write_movetext(f, tokens);
+        };
+        let mut tokens = vec![];
+        for (ply, mov) in self.moves.iter().enumerate() {
+            if state.turn == Sides::White {
+                tokens.push(format!("{}.", state.fullmoves));
+            } else if ply == 0 {
+                tokens.push(format!("{}...", state.fullmoves));
+            }
+            tokens.push(state.san(mov));
+            state = state.apply(mov.clone());
+        }
+        tokens.push(result.to_string());
+        write_movetext(f, tokens)
+    }
+}
+
+/// Writes movetext tokens in lines of under 80 characters
+fn write_movetext(f: &mut std::fmt::Formatter, tokens: Vec<String>) -> std::fmt::Result {
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + token.len() >= 80 {
+            writeln!(f, "{line}")?;
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    writeln!(f, "{line}")
+}
+
+#[derive(Debug)]
+pub enum PgnError {
+    Io(std::io::Error),
+    Header {
+        line: usize,
+    },
+    Fen {
+        line: usize,
+        error: FenError,
+    },
+    Move {
+        line: usize,
+        ply: usize,
+        san: String,
+    },
+}
+
+impl std::fmt::Display for PgnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PgnError::Io(e) => write!(f, "PGN read failed: {e}"),
+            PgnError::Header { line } => write!(f, "Malformed PGN header in game at line {line}"),
+            PgnError::Fen { line, error } => write!(f, "{error} in game at line {line}"),
+            PgnError::Move { line, ply, san } => {
+                write!(f, "Illegal move {san} at ply {ply} in game at line {line}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+/// Streams games out of a PGN database one at a time, so only the game being
+/// parsed is held in memory. Errors are reported per game and reading resumes
+/// with the next one.
+pub struct PgnReader<R> {
+    reader: R,
+    line: usize,
+    pending: Option<String>,
+    headers_only: bool,
+    done: bool,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> Self {
+        PgnReader {
+            reader,
+            line: 0,
+            pending: None,
+            headers_only: false,
+            done: false,
+        }
+    }
+
+    /// Skips movetext parsing, yielding games with headers only
+    pub fn headers_only(mut self, headers_only: bool) -> Self {
+        self.headers_only = headers_only;
+        self
+    }
+
+    fn next_line(&mut self) -> std::io::Result<Option<String>> {
+        if let Some(line) = self.pending.take() {
+            return Ok(Some(line));
+        }
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        Ok(Some(line.trim().to_string()))
+    }
+
+    /// Reads the raw text of the next game, returning its starting line
+    fn next_raw(&mut self) -> std::io::Result<Option<(usize, Vec<String>, String)>> {
+        let mut start = None;
+        let mut headers = vec![];
+        let mut movetext = String::new();
+        let mut in_movetext = false;
+        let mut comment_depth = 0usize;
+
+        while let Some(line) = self.next_line()? {
+            if line.is_empty() || line.starts_with('%') {
+                continue;
+            }
+            if comment_depth == 0 && line.starts_with('[') {
+                if in_movetext {
+                    self.pending = Some(line);
+                    break;
+                }
+                start.get_or_insert(self.line);
+                headers.push(line);
+                continue;
+            }
+
+            start.get_or_insert(self.line);
+            in_movetext = true;
+            for c in line.chars() {
+                match c {
+                    '{' => comment_depth += 1,
+                    '}' => comment_depth = comment_depth.saturating_sub(1),
+                    ';' if comment_depth == 0 => break,
+                    _ => {}
+                }
+            }
+            if !self.headers_only {
+                movetext.push_str(&line);
+                movetext.push('\n');
+            }
+            if comment_depth == 0
+                && line
+                    .split_whitespace()
+                    .last()
+                    .is_some_and(|token| RESULTS.contains(&token))
+            {
+                break;
+            }
+        }
+
+        Ok(start.map(|start| (start, headers, movetext)))
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let (line, raw_headers, movetext) = match self.next_raw() {
+            Ok(Some(raw)) => raw,
+            Ok(None) => return None,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(PgnError::Io(e)));
+            }
+        };
+
+        let mut game = PgnGame {
+            headers: vec![],
+            moves: vec![],
+            result: None,
+        };
+        for header in raw_headers {
+            match parse_header(&header) {
+                Some(pair) => game.headers.push(pair),
+                None => return Some(Err(PgnError::Header { line })),
+            }
+        }
+        game.result = game.header("Result").map(str::to_string);
+
+        let mut state = match game.start() {
+            Ok(state) => state,
+            Err(error) => return Some(Err(PgnError::Fen { line, error })),
+        };
+        if self.headers_only {
+            return Some(Ok(game));
+        }
+        for token in movetext_tokens(&movetext) {
+            if RESULTS.contains(&token.as_str()) {
+                game.result = Some(token);
+                continue;
+            }
+            match state.parse_san(&token) {
+                Some(mov) => {
+                    state = state.apply(mov.clone());
+                    game.moves.push(mov);
+                }
+                None => {
+                    let ply = game.moves.len() + 1;
+                    return Some(Err(PgnError::Move {
+                        line,
+                        ply,
+                        san: token,
+                    }));
+                }
+            }
+        }
+
+        Some(Ok(game))
+    }
+}
+
+fn parse_header(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?.trim();
+    let (key, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((
+        key.to_string(),
+        value.replace("\\\"", "\"").replace("\\\\", "\\"),
+    ))
+}
+
+fn flush_token(token: &mut String, tokens: &mut Vec<String>) {
+    let word = std::mem::take(token);
+    if word.is_empty() || word.starts_with('$') {
+        return;
+    }
+    if RESULTS.contains(&word.as_str()) {
+        tokens.push(word);
+        return;
+    }
+    let word = word.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    if !word.is_empty() {
+        tokens.push(word.to_string());
+    }
+}
+
+/// Splits movetext into SAN moves and result tokens, dropping move numbers,
+/// comments, NAGs and variations
+fn movetext_tokens(movetext: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut comment = false;
+    let mut line_comment = false;
+    let mut variation = 0usize;
+
+    for c in movetext.chars() {
+        match c {
+            '\n' if line_comment => line_comment = false,
+            _ if line_comment => {}
+            '}' if comment => comment = false,
+            _ if comment => {}
+            '{' => {
+                flush_token(&mut token, &mut tokens);
+                comment = true;
+            }
+            ';' => {
+                flush_token(&mut token, &mut tokens);
+                line_comment = true;
+            }
+            '(' => {
+                flush_token(&mut token, &mut tokens);
+                variation += 1;
+            }
+            ')' => variation = variation.saturating_sub(1),
+            _ if variation > 0 => {}
+            c if c.is_whitespace() => flush_token(&mut token, &mut tokens),
+            c => token.push(c),
+        }
+    }
+    flush_token(&mut token, &mut tokens);
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::VARIANTS;
+
+    const DATABASE: &str = r#"[Event "First"]
+[White "A"]
+[Result "1-0"]
+
+1. e4 e5 2. Bc4 {attacking f7} Nc6 3. Qh5 Nf6?? (3... g6 4. Qf3) 4. Qxf7# 1-0
+
+[Event "Broken"]
+[Result "*"]
+
+1. e4 e4 *
+
+[Event "Third"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+[Result "1/2-1/2"]
+
+1.e4 $1 Kd7 ; a line comment 2. Ke2
+2. Kd2 1/2-1/2
+"#;
+
+    #[test]
+    fn streams_games_past_errors() {
+        let games: Vec<_> = PgnReader::new(DATABASE.as_bytes()).collect();
+        assert_eq!(games.len(), 3);
+
+        let first = games[0].as_ref().unwrap();
+        assert_eq!(first.header("White"), Some("A"));
+        assert_eq!(first.moves.len(), 7);
+        assert_eq!(first.moves[6].uci(), "h5f7");
+
+        assert!(matches!(games[1], Err(PgnError::Move { ply: 2, .. })));
+
+        let third = games[2].as_ref().unwrap();
+        assert_eq!(third.moves.len(), 3);
+        assert_eq!(third.result.as_deref(), Some("1/2-1/2"));
+    }
+
+    #[test]
+    fn variant_round_trip() {
+        let mut start = GameState::from("4k3/4p3/8/8/8/8/8/4QK2 b - - 0 1".to_string());
+        start.variant = Variant::Atomic;
+        let mut game = PgnGame::from_position(&start).unwrap();
+        let mut state = start.clone();
+        for san in ["Kd8", "Qxe7"] {
+            let mov = state.parse_san(san).unwrap();
+            state = state.apply(mov.clone());
+            game.moves.push(mov);
+        }
+        game.result = state.outcome().map(|o| o.result().to_string());
+
+        let pgn = game.to_string();
+        assert!(pgn.contains("[Variant \"Atomic\"]"));
+        assert!(pgn.ends_with("1... Kd8 2. Qxe7# 1-0\n"));
+
+        let read = PgnReader::new(pgn.as_bytes()).next().unwrap().unwrap();
+        assert_eq!(read.start().unwrap(), start);
+        assert_eq!(read.moves, game.moves);
+        assert_eq!(read.result.as_deref(), Some("1-0"));
+
+        // Starting positions of variants are written without a FEN tag
+        for variant in VARIANTS {
+            let start = GameState::start(variant);
+            let mut game = PgnGame::from_position(&start).unwrap();
+            game.moves.push(start.moves()[0].clone());
+            let pgn = game.to_string();
+            let read = PgnReader::new(pgn.as_bytes()).next().unwrap().unwrap();
+            assert_eq!(read.start().unwrap(), start, "{variant:?}");
+            assert_eq!(read.moves, game.moves, "{variant:?}");
+        }
+    }
+
+    #[test]
+    fn malformed_fen() {
+        let pgn = "[FEN \"8/8 w - - 0 1\"]\n\n1. e4 *\n";
+        let read = PgnReader::new(pgn.as_bytes()).headers_only(true).next();
+        assert!(matches!(read, Some(Err(PgnError::Fen { line: 1, .. }))));
+
+        let mut game = PgnGame::from_position(&GameState::start(Variant::Standard)).unwrap();
+        game.moves.push(game.start().unwrap().moves()[0].clone());
+        game.set_header("FEN", "8/8 w - - 0 1");
+        assert!(game
+            .to_string()
+            .ends_with(&format!("{} *\n", game.moves[0].uci())));
+    }
+
+    #[test]
+    fn headers_only() {
+        let games: Vec<_> = PgnReader::new(DATABASE.as_bytes())
+            .headers_only(true)
+            .map(|g| g.unwrap())
+            .collect();
+        assert_eq!(games.len(), 3);
+        assert!(games.iter().all(|g| g.moves.is_empty()));
+        assert_eq!(games[1].header("Event"), Some("Broken"));
+    }
+}
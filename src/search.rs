@@ -0,0 +1,1077 @@
+use crate::board::*;
+use crate::endgame;
+use crate::eval::PIECE_VALUES;
+use crate::movepick::{Heuristics, MovePicker, Stage};
+use crate::moves::Move;
+use crate::nnue::{Accumulator, Evaluator, Network};
+use crate::syzygy::{Tablebases, Wdl};
+use crate::timeman::{TimeControl, TimeManager};
+use crate::tt::{score_from_tt, Bound, TranspositionTable};
+use crate::variant::Variant;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const MATE: i32 = 30000;
+pub const INFINITY: i32 = 32000;
+pub const MAX_PLY: usize = 128;
+
+/// Scores beyond this are mates, with the distance in plies encoded as `MATE - score`
+pub const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+
+/// Tablebase wins score below mates, less the plies to reach them
+pub const TB_WIN: i32 = MATE_BOUND - 1;
+/// Scores beyond this are tablebase wins or mates, which count the plies
+/// from the root
+pub const TB_BOUND: i32 = TB_WIN - MAX_PLY as i32;
+
+/// Default transposition table size in megabytes
+pub const DEFAULT_HASH_MB: usize = 16;
+
+/// Slack over the captured piece before a capture can't raise alpha
+const DELTA_MARGIN: i32 = 200;
+
+/// Half width of the first root window around the previous iteration's score
+const ASPIRATION_WINDOW: i32 = 25;
+
+/// Reverse futility pruning applies up to this depth, with this margin per ply
+const RFP_DEPTH: u8 = 6;
+const RFP_MARGIN: i32 = 80;
+
+/// Quiet moves are skipped up to this depth when the static evaluation is
+/// this far below alpha per ply
+const FUTILITY_DEPTH: u8 = 3;
+const FUTILITY_MARGIN: i32 = 120;
+
+/// Moves searched at full depth before late move reductions start
+const LMR_MOVES: usize = 3;
+
+/// Checked every this many nodes, so the clock isn't read at every node
+const CHECK_EVERY: u64 = 1024;
+
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+    /// Game clock the search budgets its own time from
+    pub clock: Option<TimeControl>,
+}
+
+/// Switches for the selective search techniques, all on by default, so each
+/// can be measured on its own
+#[derive(Clone, Copy, Debug)]
+pub struct SearchOptions {
+    /// Principal variation search, with zero windows after the first move
+    pub pvs: bool,
+    pub null_move: bool,
+    /// Late move reductions
+    pub lmr: bool,
+    pub reverse_futility: bool,
+    pub futility: bool,
+    pub aspiration: bool,
+    pub check_extensions: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            pvs: true,
+            null_move: true,
+            lmr: true,
+            reverse_futility: true,
+            futility: true,
+            aspiration: true,
+            check_extensions: true,
+        }
+    }
+}
+
+/// One of the best root moves with its score and principal variation
+#[derive(Clone, Debug)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best: Option<Move>,
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub depth: u8,
+    pub nodes: u64,
+    /// The best `multipv` root moves, best first, where the first is the
+    /// same as `best`, `score` and `pv`
+    pub lines: Vec<PvLine>,
+    pub time: Duration,
+    /// Permille of the transposition table in use
+    pub hashfull: usize,
+    /// Positions found in the endgame tablebases
+    pub tbhits: u64,
+}
+
+/// Called with the result of every finished iteration, to show progress
+pub type IterationCallback = Box<dyn FnMut(&SearchResult) + Send>;
+
+/// Moves to mate for a mate score, negative when being mated
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score >= MATE_BOUND {
+        Some((MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        Some(-(MATE + score) / 2)
+    } else {
+        None
+    }
+}
+
+pub struct Search {
+    pub limits: Limits,
+    pub options: SearchOptions,
+    pub evaluator: Evaluator,
+    /// Kept between searches, so later moves of a game start from earlier results
+    pub tt: Arc<TranspositionTable>,
+    /// Threads searching the root together, each but the first a helper that
+    /// only fills the shared table
+    pub threads: usize,
+    /// Root moves to find a line for, besides the best
+    pub multipv: usize,
+    pub on_iteration: Option<IterationCallback>,
+    /// Endgame tablebases, probed at the root and wherever a capture or Pawn
+    /// move brings the pieces down to what they hold
+    pub tablebases: Option<Arc<Tablebases>>,
+    start: Instant,
+    /// When the clock started, which is at the ponder hit when pondering
+    clock_start: Instant,
+    timer: Option<TimeManager>,
+    /// Set from outside while the opponent's clock is running
+    ponder: Arc<AtomicBool>,
+    /// Whether this search still hasn't seen the ponder hit
+    pondering: bool,
+    nodes: u64,
+    stopped: bool,
+    /// Set from outside to end the search, and left for the owner to clear
+    stop: Arc<AtomicBool>,
+    /// Set by the main thread to end the search on every thread once a limit
+    /// is hit or it has finished
+    abort: Arc<AtomicBool>,
+    /// Nodes searched by all threads, counted in steps of `CHECK_EVERY`
+    shared_nodes: Arc<AtomicU64>,
+    /// Thread number, where helpers start at different depths so the
+    /// threads don't all search the same tree in step
+    id: usize,
+    /// Root moves already given a line in the current iteration
+    excluded: Vec<Move>,
+    /// Root moves the search chooses from, which the tablebases narrow down
+    /// to those keeping the best result
+    root_moves: Vec<Move>,
+    tb_hits: u64,
+    /// Hashes of positions played before and during the search, for
+    /// repetition detection
+    history: Vec<u64>,
+    heuristics: Heuristics,
+    /// Network accumulators by ply, when evaluating with a network
+    accumulators: Vec<Accumulator>,
+}
+
+impl Search {
+    pub fn new(limits: Limits) -> Self {
+        endgame::init();
+        Search {
+            limits,
+            options: SearchOptions::default(),
+            evaluator: Evaluator::Classical,
+            tt: Arc::new(TranspositionTable::new(DEFAULT_HASH_MB)),
+            threads: 1,
+            multipv: 1,
+            on_iteration: None,
+            tablebases: None,
+            start: Instant::now(),
+            clock_start: Instant::now(),
+            timer: None,
+            ponder: Arc::new(AtomicBool::new(false)),
+            pondering: false,
+            nodes: 0,
+            stopped: false,
+            stop: Arc::new(AtomicBool::new(false)),
+            abort: Arc::new(AtomicBool::new(false)),
+            shared_nodes: Arc::new(AtomicU64::new(0)),
+            id: 0,
+            excluded: vec![],
+            root_moves: vec![],
+            tb_hits: 0,
+            history: vec![],
+            heuristics: Heuristics::default(),
+            accumulators: vec![],
+        }
+    }
+
+    /// Flag that stops the running search when set from any thread, or the
+    /// next one if none is running, until it is cleared again
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Flag that makes a search ponder while set: it searches the position
+    /// after the expected move on the opponent's time, ignoring its own time
+    /// limits. Clearing it during the search is the ponder hit, when the
+    /// opponent played that move and the engine's clock starts running.
+    pub fn ponder_handle(&self) -> Arc<AtomicBool> {
+        self.ponder.clone()
+    }
+
+    /// A helper thread's search, sharing the table and stop flag but with its
+    /// own heuristics
+    fn helper(&self, id: usize) -> Search {
+        Search {
+            limits: Limits {
+                depth: self.limits.depth,
+                ..Default::default()
+            },
+            options: self.options,
+            evaluator: self.evaluator.clone(),
+            tt: self.tt.clone(),
+            threads: 1,
+            multipv: 1,
+            on_iteration: None,
+            tablebases: self.tablebases.clone(),
+            start: self.start,
+            clock_start: self.start,
+            timer: None,
+            ponder: Arc::new(AtomicBool::new(false)),
+            pondering: false,
+            nodes: 0,
+            stopped: false,
+            stop: self.stop.clone(),
+            abort: self.abort.clone(),
+            shared_nodes: self.shared_nodes.clone(),
+            id,
+            excluded: vec![],
+            root_moves: self.root_moves.clone(),
+            tb_hits: 0,
+            history: self.history.clone(),
+            heuristics: Heuristics::default(),
+            accumulators: vec![],
+        }
+    }
+
+    /// Searches `game` by iterative deepening until a limit is hit, where
+    /// `history` holds the positions played before it. With more than one
+    /// thread the helpers search the same root until this thread finishes,
+    /// and the result counts the nodes of all of them.
+    pub fn run(&mut self, game: &GameState, history: &[GameState]) -> SearchResult {
+        self.start = Instant::now();
+        self.clock_start = self.start;
+        self.timer = self.limits.clock.as_ref().map(TimeManager::new);
+        self.pondering = self.ponder.load(Ordering::Relaxed);
+        self.nodes = 0;
+        self.tb_hits = 0;
+        self.stopped = false;
+        self.abort.store(false, Ordering::Relaxed);
+        self.shared_nodes.store(0, Ordering::Relaxed);
+        self.history = history.iter().map(GameState::hash).collect();
+        self.tt.new_search();
+        self.heuristics.new_search();
+
+        let mut moves = game.moves();
+        // Only moves keeping the tablebase result are searched
+        let ranked = self
+            .tablebases
+            .as_ref()
+            .and_then(|tb| tb.rank_root_moves(game, &self.history));
+        if let Some(ranked) = ranked {
+            let best = ranked.iter().map(|m| m.rank).max();
+            moves = ranked
+                .into_iter()
+                .filter(|m| Some(m.rank) == best)
+                .map(|m| m.mov)
+                .collect();
+            self.tb_hits += 1;
+        }
+        self.root_moves = moves.clone();
+        let result = SearchResult {
+            best: moves.first().cloned(),
+            score: 0,
+            pv: vec![],
+            depth: 0,
+            nodes: 0,
+            lines: vec![],
+            time: Duration::ZERO,
+            hashfull: 0,
+            tbhits: 0,
+        };
+        if result.best.is_none() {
+            return SearchResult {
+                score: outcome_score(game, 0),
+                ..result
+            };
+        }
+
+        std::thread::scope(|scope| {
+            let helpers: Vec<_> = (1..self.threads)
+                .map(|id| {
+                    let mut helper = self.helper(id);
+                    let (moves, first) = (&moves, result.clone());
+                    scope.spawn(move || {
+                        helper.iterate(game, moves, first);
+                        (helper.nodes, helper.tb_hits)
+                    })
+                })
+                .collect();
+            let mut result = self.iterate(game, &moves, result);
+            self.abort.store(true, Ordering::Relaxed);
+            result.nodes = self.nodes;
+            result.tbhits = self.tb_hits;
+            for helper in helpers {
+                let (nodes, tb_hits) = helper.join().unwrap();
+                result.nodes += nodes;
+                result.tbhits += tb_hits;
+            }
+            result.time = self.start.elapsed();
+            result.hashfull = self.tt.hashfull();
+            result
+        })
+    }
+
+    fn iterate(
+        &mut self,
+        game: &GameState,
+        moves: &[Move],
+        mut result: SearchResult,
+    ) -> SearchResult {
+        let max_depth = self.limits.depth.unwrap_or(MAX_PLY as u8 - 1);
+        let first_depth = (1 + self.id % 2).min(max_depth as usize) as u8;
+        for depth in first_depth..=max_depth {
+            // Each line searches the root without the moves of the lines before it
+            let mut lines = vec![];
+            self.excluded.clear();
+            for index in 0..self.multipv.clamp(1, moves.len()) {
+                let last = result.lines.get(index).map_or(0, |l| l.score);
+                let Some(line) = self.search_root(game, depth, last) else {
+                    break;
+                };
+                self.excluded.push(line.pv[0].clone());
+                lines.push(line);
+            }
+            if self.stopped {
+                break;
+            }
+            lines.sort_by_key(|l| -l.score);
+            result = SearchResult {
+                best: lines[0].pv.first().cloned(),
+                score: lines[0].score,
+                pv: lines[0].pv.clone(),
+                depth,
+                // Helpers' nodes are only counted in steps of `CHECK_EVERY`
+                nodes: self.shared_nodes.load(Ordering::Relaxed) + self.nodes % CHECK_EVERY,
+                lines,
+                time: self.start.elapsed(),
+                hashfull: self.tt.hashfull(),
+                tbhits: self.tb_hits,
+            };
+            if let Some(callback) = &mut self.on_iteration {
+                callback(&result);
+            }
+            let score = result.score;
+            // A mate found within the search depth is already the shortest
+            if score.abs() >= MATE_BOUND && MATE - score.abs() <= depth as i32 {
+                break;
+            }
+            if let Some(timer) = self.timer.as_mut().filter(|_| !self.pondering) {
+                // A forced move needs no thought when playing on a clock
+                let best = result.best.as_ref().map_or(0, Move::pack);
+                let elapsed = self.clock_start.elapsed();
+                if moves.len() == 1 || !timer.next_iteration(elapsed, best, score) {
+                    break;
+                }
+            }
+        }
+        result
+    }
+
+    /// Searches the root to `depth` for the best move not yet excluded, with
+    /// an aspiration window around `last`, its score at the previous depth
+    fn search_root(&mut self, game: &GameState, depth: u8, last: i32) -> Option<PvLine> {
+        let mut pv = vec![];
+        // Search a narrow window around the last score first, widening it
+        // on whichever side the score falls outside
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = match self.options.aspiration && depth >= 5 {
+            true if last.abs() < MATE_BOUND => (last - delta, last + delta),
+            _ => (-INFINITY, INFINITY),
+        };
+        loop {
+            pv.clear();
+            let score = self.negamax(game, depth, 0, alpha, beta, None, &mut pv);
+            if self.stopped {
+                return None;
+            }
+            if score <= alpha {
+                alpha = (score - delta).max(-INFINITY);
+            } else if score >= beta {
+                beta = (score + delta).min(INFINITY);
+            } else {
+                self.extend_pv(game, &mut pv, depth as usize);
+                return Some(PvLine { score, pv });
+            }
+            delta *= 2;
+        }
+    }
+
+    fn check_limits(&mut self) {
+        if !self.nodes.is_multiple_of(CHECK_EVERY) {
+            return;
+        }
+        let nodes = self.shared_nodes.fetch_add(CHECK_EVERY, Ordering::Relaxed) + CHECK_EVERY;
+        let out_of_nodes = self.limits.nodes.is_some_and(|n| nodes >= n);
+        if self.pondering && !self.ponder.load(Ordering::Relaxed) {
+            self.pondering = false;
+            self.clock_start = Instant::now();
+            // Pondering for longer than the move was worth leaves nothing to add
+            let pondered = self.start.elapsed();
+            if self.timer.as_ref().is_some_and(|t| pondered >= t.soft()) {
+                self.abort.store(true, Ordering::Relaxed);
+            }
+        }
+        let elapsed = self.clock_start.elapsed();
+        let out_of_time = !self.pondering
+            && (self.limits.time.is_some_and(|t| elapsed >= t)
+                || self.timer.as_ref().is_some_and(|t| elapsed >= t.hard()));
+        if out_of_nodes || out_of_time {
+            self.abort.store(true, Ordering::Relaxed);
+        }
+        if self.stop.load(Ordering::Relaxed) || self.abort.load(Ordering::Relaxed) {
+            self.stopped = true;
+        }
+    }
+
+    fn is_repetition(&self, game: &GameState, hash: u64) -> bool {
+        // Only positions since the last capture or Pawn move can repeat
+        self.history
+            .iter()
+            .rev()
+            .take(game.halfmoves as usize)
+            .skip(1)
+            .step_by(2)
+            .any(|&prev| prev == hash)
+    }
+
+    /// Table cutoffs leave the PV short, so it is completed from exact entries
+    fn extend_pv(&self, game: &GameState, pv: &mut Vec<Move>, depth: usize) {
+        let mut game = pv.iter().fold(game.clone(), |g, m| g.apply(m.clone()));
+        let mut seen = vec![];
+        while pv.len() < depth {
+            let hash = game.hash();
+            if seen.contains(&hash) {
+                break;
+            }
+            seen.push(hash);
+            let next = self
+                .tt
+                .probe(hash)
+                .filter(|e| e.bound == Bound::Exact)
+                .and_then(|e| game.unpack(e.best));
+            let Some(mov) = next else {
+                break;
+            };
+            game = game.apply(mov.clone());
+            pv.push(mov);
+        }
+    }
+
+    /// Brings the network accumulator of `ply` up to date with `game`, from
+    /// the one of the position before it. Positions with too many pieces for
+    /// the network are left without one, to be evaluated classically.
+    fn update_accumulator(&mut self, game: &GameState, ply: usize) {
+        let Evaluator::Nnue(net) = &self.evaluator else {
+            return;
+        };
+        if !Network::covers(game) {
+            self.accumulators.truncate(ply);
+            return;
+        }
+        if ply == 0 || self.accumulators.len() < ply {
+            self.accumulators.truncate(ply);
+            self.accumulators.push(Accumulator::new(net, game));
+            return;
+        }
+        if self.accumulators.len() == ply {
+            let parent = self.accumulators[ply - 1].clone();
+            self.accumulators.push(parent);
+        }
+        let (parents, rest) = self.accumulators.split_at_mut(ply);
+        rest[0].update(net, &parents[ply - 1], game);
+    }
+
+    fn evaluate(&self, game: &GameState, ply: usize) -> i32 {
+        self.evaluator.evaluate(game, self.accumulators.get(ply))
+    }
+
+    /// Alpha-beta search of `game`, reached by the move `prev`, which is none
+    /// at the root and after a null move
+    #[allow(clippy::too_many_arguments)]
+    fn negamax(
+        &mut self,
+        game: &GameState,
+        mut depth: u8,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        prev: Option<&Move>,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        let hash = game.hash();
+        if ply > 0 {
+            if game.halfmoves >= 100
+                || game.insufficient_material()
+                || self.is_repetition(game, hash)
+            {
+                return 0;
+            }
+            if game.variant_end().is_some() {
+                return outcome_score(game, ply);
+            }
+        }
+
+        let in_check = game.in_check();
+        if in_check && self.options.check_extensions {
+            depth = depth.saturating_add(1);
+        }
+        if depth == 0 {
+            return self.quiescence(game, ply, alpha, beta);
+        }
+        self.update_accumulator(game, ply);
+
+        self.nodes += 1;
+        self.check_limits();
+        if self.stopped {
+            return 0;
+        }
+
+        // Cutoffs are left out of PV nodes, so the PV comes from the search
+        let pv_node = beta - alpha > 1;
+        let entry = self.tt.probe(hash);
+        if let Some(entry) = entry.filter(|e| !pv_node && e.depth >= depth) {
+            let score = score_from_tt(entry.score, ply);
+            let cutoff = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+            };
+            if cutoff {
+                return score;
+            }
+        }
+
+        // Right after a capture or Pawn move the tablebases know the result,
+        // scored as a draw when the fifty move rule saves it
+        if let Some(wdl) = self
+            .tablebases
+            .as_ref()
+            .filter(|_| ply > 0 && game.halfmoves == 0)
+            .and_then(|tb| tb.probe_wdl(game))
+        {
+            self.tb_hits += 1;
+            let (score, bound) = match wdl {
+                Wdl::Win => (TB_WIN - ply as i32, Bound::Lower),
+                Wdl::Loss => (-TB_WIN + ply as i32, Bound::Upper),
+                _ => (wdl.value(), Bound::Exact),
+            };
+            let cutoff = match bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+            };
+            if cutoff {
+                let depth = depth.saturating_add(6).min(MAX_PLY as u8 - 1);
+                self.tt.store(hash, depth, bound, score, None, ply);
+                return score;
+            }
+        }
+
+        let moves = game.moves();
+        if moves.is_empty() {
+            return outcome_score(game, ply);
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.evaluate(game, ply);
+        }
+        let legal = moves.len();
+
+        let static_eval = if in_check {
+            -INFINITY
+        } else {
+            self.evaluate(game, ply)
+        };
+        if !pv_node && !in_check && beta.abs() < MATE_BOUND {
+            if self.options.reverse_futility
+                && depth <= RFP_DEPTH
+                && static_eval - RFP_MARGIN * depth as i32 >= beta
+            {
+                return static_eval;
+            }
+
+            // Passing is usually worse than the best move, except in
+            // zugzwang, which is likely with only Pawns left or in Antichess
+            if self.options.null_move
+                && depth >= 3
+                && prev.is_some()
+                && static_eval >= beta
+                && game.variant != Variant::Antichess
+                && has_pieces(game, game.turn)
+            {
+                let reduction = 3 + depth / 6;
+                let next = game.apply_null();
+                self.history.push(hash);
+                let score = -self.negamax(
+                    &next,
+                    depth.saturating_sub(1 + reduction),
+                    ply + 1,
+                    -beta,
+                    -beta + 1,
+                    None,
+                    &mut vec![],
+                );
+                self.history.pop();
+                if self.stopped {
+                    return 0;
+                }
+                if score >= beta {
+                    // Mates found after passing can't be trusted
+                    return if score >= MATE_BOUND { beta } else { score };
+                }
+            }
+        }
+        let futile = self.options.futility
+            && !pv_node
+            && !in_check
+            && depth <= FUTILITY_DEPTH
+            && static_eval + FUTILITY_MARGIN * depth as i32 <= alpha;
+
+        let mut picker = MovePicker::new(
+            game,
+            moves,
+            entry.map_or(0, |e| e.best),
+            self.heuristics.killers(ply),
+            self.heuristics.countermove(game, prev),
+        );
+
+        let alpha_orig = alpha;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        let mut searched = 0;
+        let mut quiets_tried = vec![];
+        let mut child_pv = vec![];
+        while let Some(mov) = picker.next(game, &self.heuristics) {
+            if ply == 0 && (self.excluded.contains(&mov) || !self.root_moves.contains(&mov)) {
+                continue;
+            }
+            let quiet = mov.capture.is_none() && mov.promotion.is_none();
+            let next = game.apply(mov.clone());
+            let gives_check = next.in_check();
+            if futile && quiet && searched > 0 && !gives_check {
+                continue;
+            }
+
+            self.history.push(hash);
+            child_pv.clear();
+            let new_depth = depth - 1;
+            let score = if searched == 0 {
+                -self.negamax(
+                    &next,
+                    new_depth,
+                    ply + 1,
+                    -beta,
+                    -alpha,
+                    Some(&mov),
+                    &mut child_pv,
+                )
+            } else {
+                let reduction = match self.options.lmr
+                    && depth >= 3
+                    && searched >= LMR_MOVES
+                    && quiet
+                    && !in_check
+                    && !gives_check
+                {
+                    true => lmr_reduction(depth, searched, pv_node).min(new_depth - 1),
+                    false => 0,
+                };
+                // Later moves only need to be shown worse than the best so far
+                let (lower, upper) = match self.options.pvs {
+                    true => (-alpha - 1, -alpha),
+                    false => (-beta, -alpha),
+                };
+                let mut score = -self.negamax(
+                    &next,
+                    new_depth - reduction,
+                    ply + 1,
+                    lower,
+                    upper,
+                    Some(&mov),
+                    &mut child_pv,
+                );
+                if reduction > 0 && score > alpha {
+                    child_pv.clear();
+                    score = -self.negamax(
+                        &next,
+                        new_depth,
+                        ply + 1,
+                        lower,
+                        upper,
+                        Some(&mov),
+                        &mut child_pv,
+                    );
+                }
+                if self.options.pvs && score > alpha && score < beta {
+                    child_pv.clear();
+                    score = -self.negamax(
+                        &next,
+                        new_depth,
+                        ply + 1,
+                        -beta,
+                        -alpha,
+                        Some(&mov),
+                        &mut child_pv,
+                    );
+                }
+                score
+            };
+            self.history.pop();
+            searched += 1;
+
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                best_move = Some(mov.clone());
+                if score > alpha {
+                    alpha = score;
+                    pv.clear();
+                    pv.push(mov.clone());
+                    pv.extend_from_slice(&child_pv);
+                }
+            }
+            if alpha >= beta {
+                if quiet {
+                    self.heuristics
+                        .update(game, prev, ply, depth, &mov, &quiets_tried);
+                }
+                break;
+            }
+            if quiet {
+                quiets_tried.push(mov);
+            }
+        }
+
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > alpha_orig {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        // A fail low has no real best move, so only a better bound's move is kept
+        let best_move = best_move.filter(|_| bound != Bound::Upper);
+        // A root searched without some of its moves has no true score
+        if ply > 0 || (self.excluded.is_empty() && self.root_moves.len() == legal) {
+            self.tt
+                .store(hash, depth, bound, best, best_move.as_ref(), ply);
+        }
+        best
+    }
+
+    /// Searches captures and promotions until the position is quiet, so the
+    /// evaluation isn't taken in the middle of an exchange
+    fn quiescence(&mut self, game: &GameState, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.update_accumulator(game, ply);
+        self.nodes += 1;
+        self.check_limits();
+        if self.stopped {
+            return 0;
+        }
+        if game.variant_end().is_some() {
+            return outcome_score(game, ply);
+        }
+
+        let moves = game.moves();
+        if moves.is_empty() {
+            return outcome_score(game, ply);
+        }
+        if ply >= MAX_PLY - 1 {
+            return self.evaluate(game, ply);
+        }
+
+        // Every evasion is searched when in check, as standing pat may be mated
+        let in_check = game.in_check();
+        let stand_pat = if in_check {
+            -INFINITY
+        } else {
+            self.evaluate(game, ply)
+        };
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let moves = moves
+            .into_iter()
+            .filter(|m| in_check || m.capture.is_some() || m.promotion.is_some())
+            .collect();
+        let mut picker = MovePicker::new(game, moves, 0, [0; 2], 0);
+
+        let mut best = stand_pat;
+        while let Some(mov) = picker.next(game, &self.heuristics) {
+            if !in_check {
+                // Only losing captures are left
+                if picker.stage() == Stage::BadCaptures {
+                    break;
+                }
+                let victim = mov.capture.and_then(|sq| game.piece_at(sq));
+                let mut gain = victim.map_or(0, |(_, piece)| PIECE_VALUES[piece as usize]);
+                if let Some(promotion) = mov.promotion {
+                    gain += PIECE_VALUES[promotion as usize] - PIECE_VALUES[Piece::Pawn as usize];
+                }
+                if stand_pat + gain + DELTA_MARGIN <= alpha {
+                    continue;
+                }
+            }
+
+            let next = game.apply(mov);
+            let score = -self.quiescence(&next, ply + 1, -beta, -alpha);
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                alpha = alpha.max(score);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+/// Plies to reduce a late quiet move by, growing with depth and move number
+fn lmr_reduction(depth: u8, searched: usize, pv_node: bool) -> u8 {
+    let reduction = 0.75 + (depth as f64).ln() * (searched as f64).ln() / 2.25;
+    (reduction as u8).saturating_sub(pv_node as u8)
+}
+
+/// Whether `side` has anything besides Pawns and the King
+fn has_pieces(game: &GameState, side: Sides) -> bool {
+    [Piece::Rook, Piece::Knight, Piece::Bishop, Piece::Queen]
+        .into_iter()
+        .any(|piece| game.board(side, piece).0 != 0)
+}
+
+/// Score of a finished game from the side to move's perspective, preferring
+/// shorter mates
+fn outcome_score(game: &GameState, ply: usize) -> i32 {
+    let outcome = game
+        .variant_end()
+        .unwrap_or_else(|| game.no_moves_outcome());
+    match outcome.winner() {
+        Some(side) if side == game.turn => MATE - ply as i32,
+        Some(_) => -MATE + ply as i32,
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(fen: &str, depth: u8) -> SearchResult {
+        let game = GameState::from(fen.to_string());
+        let limits = Limits {
+            depth: Some(depth),
+            ..Default::default()
+        };
+        Search::new(limits).run(&game, &[])
+    }
+
+    #[test]
+    fn finds_mate() {
+        let result = search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3);
+        assert_eq!(result.best.unwrap().uci(), "a1a8");
+        assert_eq!(mate_in(result.score), Some(1));
+
+        let result = search("r5k1/8/8/8/8/8/5PPP/6K1 b - - 0 1", 3);
+        assert_eq!(mate_in(result.score), Some(1));
+        assert_eq!(result.pv.len(), 1);
+    }
+
+    #[test]
+    fn wins_material() {
+        let result = search("7k/6pp/8/3q4/8/8/3R4/3RK3 w - - 0 1", 2);
+        assert_eq!(result.best.unwrap().uci(), "d2d5");
+        assert!(result.score > 500);
+    }
+
+    #[test]
+    fn quiescence_sees_recaptures() {
+        let result = search("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", 1);
+        assert_ne!(result.best.unwrap().uci(), "d1d5");
+        assert!(result.score < 800);
+    }
+
+    #[test]
+    fn table_saves_nodes() {
+        let game = GameState::from(crate::fen::START_FEN.to_string());
+        let mut search = Search::new(Limits {
+            depth: Some(4),
+            ..Default::default()
+        });
+        // Small enough for the sampled slots to fill at this depth
+        search.tt = Arc::new(TranspositionTable::new(1));
+        let first = search.run(&game, &[]);
+        assert!(search.tt.hashfull() > 0);
+        let second = search.run(&game, &[]);
+        assert!(second.nodes < first.nodes / 2);
+        assert_eq!(second.pv.len(), 4);
+    }
+
+    #[test]
+    fn selective_search() {
+        let game = GameState::from(crate::fen::START_FEN.to_string());
+        let limits = Limits {
+            depth: Some(4),
+            ..Default::default()
+        };
+        let mut plain = Search::new(limits.clone());
+        plain.options = SearchOptions {
+            pvs: false,
+            null_move: false,
+            lmr: false,
+            reverse_futility: false,
+            futility: false,
+            aspiration: false,
+            check_extensions: false,
+        };
+        let plain = plain.run(&game, &[]);
+        let selective = Search::new(limits).run(&game, &[]);
+        assert!(selective.nodes < plain.nodes);
+    }
+
+    #[test]
+    fn node_limit() {
+        let game = GameState::from(crate::fen::START_FEN.to_string());
+        let limits = Limits {
+            nodes: Some(2000),
+            ..Default::default()
+        };
+        let result = Search::new(limits).run(&game, &[]);
+        assert!(result.best.is_some());
+        assert!(result.nodes <= 2000 + CHECK_EVERY);
+    }
+
+    #[test]
+    fn clock() {
+        let clock = TimeControl::Clock {
+            remaining: Duration::from_secs(6),
+            increment: Duration::ZERO,
+            moves_to_go: None,
+        };
+        let mut search = Search::new(Limits {
+            clock: Some(clock),
+            ..Default::default()
+        });
+        // Ka7 is the only move
+        let forced = GameState::from("k7/8/1R6/8/8/8/8/7K b - - 0 1".to_string());
+        let result = search.run(&forced, &[]);
+        assert_eq!(result.depth, 1);
+
+        let game = GameState::from(crate::fen::START_FEN.to_string());
+        let start = Instant::now();
+        let result = search.run(&game, &[]);
+        assert!(result.depth > 1);
+        assert!(start.elapsed() < TimeManager::new(&clock).hard() + Duration::from_millis(500));
+    }
+
+    #[test]
+    fn helper_threads() {
+        let game = GameState::from("7k/6pp/8/3q4/8/8/3R4/3RK3 w - - 0 1".to_string());
+        let mut search = Search::new(Limits {
+            depth: Some(6),
+            ..Default::default()
+        });
+        let single = search.run(&game, &[]);
+
+        search.threads = 4;
+        search.tt.clear();
+        let result = search.run(&game, &[]);
+        assert_eq!(result.best.unwrap().uci(), "d2d5");
+        assert_eq!(result.depth, 6);
+        // Helpers add their nodes to the count
+        assert!(result.nodes > single.nodes);
+
+        // Stopping ends every thread, leaving the last finished iteration
+        search.limits = Limits::default();
+        let stop = search.stop_handle();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            stop.store(true, Ordering::Relaxed);
+        });
+        let result = search.run(&game, &[]);
+        stopper.join().unwrap();
+        search.stop_handle().store(false, Ordering::Relaxed);
+        assert!(result.best.is_some());
+        assert!(result.depth < MAX_PLY as u8 - 1);
+    }
+
+    #[test]
+    fn multipv() {
+        let game = GameState::from(crate::fen::START_FEN.to_string());
+        let mut search = Search::new(Limits {
+            depth: Some(4),
+            ..Default::default()
+        });
+        search.multipv = 3;
+        let result = search.run(&game, &[]);
+        assert_eq!(result.lines.len(), 3);
+        assert_eq!(result.lines[0].pv, result.pv);
+        assert_eq!(result.lines[0].score, result.score);
+        let mut firsts: Vec<_> = result.lines.iter().map(|l| l.pv[0].uci()).collect();
+        firsts.dedup();
+        assert_eq!(firsts.len(), 3);
+        assert!(result.lines.windows(2).all(|w| w[0].score >= w[1].score));
+
+        // Never more lines than legal moves
+        search.multipv = 5;
+        let forced = GameState::from("k7/8/1R6/8/8/8/8/7K b - - 0 1".to_string());
+        assert_eq!(search.run(&forced, &[]).lines.len(), 1);
+    }
+
+    #[test]
+    fn ponder() {
+        let game = GameState::from(crate::fen::START_FEN.to_string());
+        // Far too little time to reach the ponder hit without pondering
+        let clock = TimeControl::Clock {
+            remaining: Duration::from_millis(1),
+            increment: Duration::ZERO,
+            moves_to_go: None,
+        };
+        let mut search = Search::new(Limits {
+            clock: Some(clock),
+            ..Default::default()
+        });
+        let ponder = search.ponder_handle();
+        ponder.store(true, Ordering::Relaxed);
+        // The opponent plays the expected move once depth 4 is done, after
+        // which the time already spent ends the search
+        let hit = ponder.clone();
+        search.on_iteration = Some(Box::new(move |result| {
+            if result.depth >= 4 {
+                hit.store(false, Ordering::Relaxed);
+            }
+        }));
+        let result = search.run(&game, &[]);
+        assert!(!ponder.load(Ordering::Relaxed));
+        assert!(result.depth >= 4);
+        assert!(result.best.is_some());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::board::*;
+use crate::moves::{knight_squares, Move, BISHOP_DIRS, KING_DIRS, ROOK_DIRS};
+
+/// Piece values for exchanges in PIECES order, where losing the King ends it
+pub const SEE_VALUES: [i32; 6] = [100, 500, 320, 330, 900, 20000];
+
+/// Attackers are tried from the least valuable up
+const CAPTURE_ORDER: [Piece; 6] = [
+    Piece::Pawn,
+    Piece::Knight,
+    Piece::Bishop,
+    Piece::Rook,
+    Piece::Queen,
+    Piece::King,
+];
+
+impl GameState {
+    /// Pieces of both sides attacking `square` when only `occupied` is filled,
+    /// so removing a piece reveals the sliders behind it
+    pub fn attackers_to(&self, square: Square, occupied: &BitBoard) -> BitBoard {
+        let mut attackers = 0u64;
+        let mut add = |sq: Square| attackers |= 1u64 << sq as u64;
+
+        for (side, dirs) in [
+            (Sides::White, [Direction::SW, Direction::SE]),
+            (Sides::Black, [Direction::NW, Direction::NE]),
+        ] {
+            let pawns = self.board(side, Piece::Pawn);
+            for sq in dirs.into_iter().filter_map(|dir| square.step(dir)) {
+                if pawns.get(sq) {
+                    add(sq);
+                }
+            }
+        }
+
+        let either = |piece: Piece| {
+            BitBoard(self.board(Sides::White, piece).0 | self.board(Sides::Black, piece).0)
+        };
+        let knights = either(Piece::Knight);
+        for sq in knight_squares(square) {
+            if knights.get(sq) {
+                add(sq);
+            }
+        }
+        let kings = either(Piece::King);
+        for sq in KING_DIRS.into_iter().filter_map(|dir| square.step(dir)) {
+            if kings.get(sq) {
+                add(sq);
+            }
+        }
+
+        let queens = either(Piece::Queen);
+        for (dirs, slider) in [(&ROOK_DIRS, Piece::Rook), (&BISHOP_DIRS, Piece::Bishop)] {
+            let sliders = either(slider);
+            for &dir in dirs {
+                let mut curr = square;
+                while let Some(next) = curr.step(dir) {
+                    curr = next;
+                    if occupied.get(curr) {
+                        if sliders.get(curr) || queens.get(curr) {
+                            add(curr);
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        BitBoard(attackers & occupied.0)
+    }
+
+    /// Static exchange evaluation: the material the side to move wins from
+    /// `mov` and the best sequence of recaptures on its target square
+    pub fn see(&self, mov: &Move) -> i32 {
+        let mut occupied = self.occupied().0;
+        if !mov.drop {
+            occupied &= !(1u64 << mov.from as u64);
+        }
+
+        let mut gain = [0i32; 33];
+        if let Some((_, victim)) = mov.capture.and_then(|sq| self.piece_at(sq)) {
+            gain[0] = SEE_VALUES[victim as usize];
+            occupied &= !(1u64 << mov.capture.unwrap() as u64);
+        }
+        let mut on_square = mov.piece;
+        if let Some(promotion) = mov.promotion {
+            gain[0] += SEE_VALUES[promotion as usize] - SEE_VALUES[Piece::Pawn as usize];
+            on_square = promotion;
+        }
+
+        let mut side = self.turn.switch();
+        let mut depth = 0;
+        loop {
+            let attackers = self.attackers_to(mov.to, &BitBoard(occupied)).0;
+            let next = CAPTURE_ORDER.into_iter().find_map(|piece| {
+                let own = self.board(side, piece).0 & attackers;
+                (own != 0).then(|| (piece, own & own.wrapping_neg()))
+            });
+            let Some((piece, from)) = next else {
+                break;
+            };
+
+            depth += 1;
+            gain[depth] = SEE_VALUES[on_square as usize] - gain[depth - 1];
+            if depth == gain.len() - 1 {
+                break;
+            }
+            occupied &= !from;
+            on_square = piece;
+            side = side.switch();
+        }
+        // Each side only recaptures when that beats stopping the exchange
+        while depth > 0 {
+            gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+            depth -= 1;
+        }
+        gain[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn see(fen: &str, san: &str) -> i32 {
+        let game = GameState::from(fen.to_string());
+        game.see(&game.parse_san(san).unwrap())
+    }
+
+    #[test]
+    fn exchanges() {
+        assert_eq!(
+            see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "Rxe5"),
+            100
+        );
+        assert_eq!(
+            see(
+                "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+                "Nxe5"
+            ),
+            -220
+        );
+        // The Queen behind the Rook only joins in once the Rook has captured
+        assert_eq!(see("3r2k1/8/8/3p4/8/8/3R4/3Q2K1 w - - 0 1", "Rxd5"), 100);
+        assert_eq!(see("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", "Qxd5"), -800);
+        assert_eq!(see("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "Ra4"), 0);
+    }
+}
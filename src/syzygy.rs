@@ -0,0 +1,1740 @@
+use crate::board::*;
+use crate::moves::Move;
+use crate::variant::Variant;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::ops::Neg;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Most pieces, Kings included, that Syzygy tables exist for
+pub const MAX_PIECES: usize = 7;
+
+/// Rank of a root move that wins or loses whatever the fifty move rule
+const MAX_DTZ: i32 = 1 << 18;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// Flags of each part of a table
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/// Result with perfect play, where a cursed win or blessed loss only wins or
+/// loses if the fifty move rule is ignored
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            ..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    /// From -2 for a loss to 2 for a win
+    pub fn value(self) -> i32 {
+        self as i32 - 2
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Wdl {
+        Wdl::from_value(-self.value())
+    }
+}
+
+/// DTZ of the move before a zeroing move, which the tables don't store
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+    }
+}
+
+/// Index tables the generator numbers positions by. Squares are numbered
+/// as in the table files, from a1 = 0 to h8 = 63.
+struct Encoding {
+    /// Pawn squares a2 to h7, numbered down from the edge files inwards so
+    /// the leading Pawn is the one with the highest number
+    map_pawns: [usize; 64],
+    /// Squares below the a1-h8 diagonal
+    map_b1h1h7: [usize; 64],
+    /// Squares of the a1-d1-d4 triangle, with the diagonal last
+    map_a1d1d4: [usize; 64],
+    /// The 462 placements of two Kings, the first in the triangle
+    map_kk: [[usize; 64]; 10],
+    /// Ways to choose `k` of `n` squares, by `k` and `n`
+    binomial: [[u64; 64]; MAX_PIECES],
+    /// First index of the leading Pawns by their count and the leading one's
+    /// square, and the indices taken up by each file
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES],
+    lead_pawns_size: [[u64; 4]; MAX_PIECES],
+}
+
+/// Ranks above the a1-h8 diagonal, negative below it
+const fn off_diagonal(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+static ENCODING: Encoding = {
+    let mut e = Encoding {
+        map_pawns: [0; 64],
+        map_b1h1h7: [0; 64],
+        map_a1d1d4: [0; 64],
+        map_kk: [[0; 64]; 10],
+        binomial: [[0; 64]; MAX_PIECES],
+        lead_pawn_idx: [[0; 64]; MAX_PIECES],
+        lead_pawns_size: [[0; 4]; MAX_PIECES],
+    };
+
+    let mut code = 0;
+    let mut sq = 0;
+    while sq < 64 {
+        if off_diagonal(sq) < 0 {
+            e.map_b1h1h7[sq] = code;
+            code += 1;
+        }
+        sq += 1;
+    }
+
+    let mut diagonal = [0; 4];
+    let mut on_diagonal = 0;
+    code = 0;
+    sq = 0;
+    // Up to d4
+    while sq <= 27 {
+        if sq % 8 <= 3 && off_diagonal(sq) < 0 {
+            e.map_a1d1d4[sq] = code;
+            code += 1;
+        } else if sq % 8 <= 3 && off_diagonal(sq) == 0 {
+            diagonal[on_diagonal] = sq;
+            on_diagonal += 1;
+        }
+        sq += 1;
+    }
+    let mut i = 0;
+    while i < on_diagonal {
+        e.map_a1d1d4[diagonal[i]] = code;
+        code += 1;
+        i += 1;
+    }
+
+    // With the first King on the diagonal the second isn't above it, and
+    // placements with both on it come last
+    let mut both = [(0, 0); 32];
+    let mut both_count = 0;
+    code = 0;
+    let mut idx = 0;
+    while idx < 10 {
+        let mut s1 = 0;
+        while s1 <= 27 {
+            // Squares outside the triangle are 0 as well as b1
+            if e.map_a1d1d4[s1] == idx && (idx != 0 || s1 == 1) {
+                let mut s2 = 0;
+                while s2 < 64 {
+                    let files = (s1 % 8) as i32 - (s2 % 8) as i32;
+                    let ranks = (s1 / 8) as i32 - (s2 / 8) as i32;
+                    if files.abs() <= 1 && ranks.abs() <= 1 {
+                        // Kings next to each other
+                    } else if off_diagonal(s1) == 0 && off_diagonal(s2) > 0 {
+                        // Above the diagonal
+                    } else if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both[both_count] = (idx, s2);
+                        both_count += 1;
+                    } else {
+                        e.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                    s2 += 1;
+                }
+            }
+            s1 += 1;
+        }
+        idx += 1;
+    }
+    i = 0;
+    while i < both_count {
+        e.map_kk[both[i].0][both[i].1] = code;
+        code += 1;
+        i += 1;
+    }
+
+    e.binomial[0][0] = 1;
+    let mut n = 1;
+    while n < 64 {
+        let mut k = 0;
+        while k < MAX_PIECES && k <= n {
+            let mut ways = 0;
+            if k > 0 {
+                ways += e.binomial[k - 1][n - 1];
+            }
+            if k < n {
+                ways += e.binomial[k][n - 1];
+            }
+            e.binomial[k][n] = ways;
+            k += 1;
+        }
+        n += 1;
+    }
+
+    // Up to five leading Pawns, with other Pawns neither further from the
+    // centre nor, on the same file, further back
+    let mut available = 48;
+    let mut lead = 1;
+    while lead < MAX_PIECES - 1 {
+        let mut file = 0;
+        while file < 4 {
+            let mut idx = 0;
+            let mut rank = 1;
+            while rank <= 6 {
+                let sq = rank * 8 + file;
+                if lead == 1 {
+                    available -= 1;
+                    e.map_pawns[sq] = available;
+                    available -= 1;
+                    e.map_pawns[sq ^ 7] = available;
+                }
+                e.lead_pawn_idx[lead][sq] = idx;
+                idx += e.binomial[lead - 1][e.map_pawns[sq]];
+                rank += 1;
+            }
+            e.lead_pawns_size[lead][file] = idx;
+            file += 1;
+        }
+        lead += 1;
+    }
+    e
+};
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Reads `buf` from `at` in the file, leaving the file's position alone so
+/// that threads can share it
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], at: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, at)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut at: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, at)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                at += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(_: &File, _: &mut [u8], _: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Big endian bits of compressed data, which may be read past its end
+fn read_bits(bytes: &[u8], at: usize, len: usize) -> u64 {
+    (0..len).fold(0, |bits, i| {
+        bits << 8 | bytes.get(at + i).copied().unwrap_or(0) as u64
+    })
+}
+
+/// One compressed part of a table, for a side to move and, with Pawns, the
+/// file of the leading Pawn. Positions are numbered by their pieces' squares
+/// and the values stored in blocks of Huffman coded symbols, each standing
+/// for a pair of symbols until they come down to values.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    /// Positions between entries of the sparse index
+    span: u64,
+    num_blocks: usize,
+    min_sym_len: u8,
+    /// Offsets into the file of the lowest symbol of each length, of the
+    /// pair each symbol stands for, of the positions in each block, of the
+    /// sparse index and of the blocks
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    /// Lowest code of each symbol length, left aligned
+    base64: Vec<u64>,
+    /// Values each symbol stands for, less one
+    symlen: Vec<u8>,
+    /// Pieces in the order they are numbered, as 1 to 6 for White's Pawn,
+    /// Knight, Bishop, Rook, Queen and King, and 9 to 14 for Black's
+    pieces: [u8; MAX_PIECES],
+    /// Pieces numbered together, such as the two Rooks of KRRvK, and what
+    /// each group's number is multiplied by
+    group_len: [usize; MAX_PIECES + 1],
+    group_idx: [u64; MAX_PIECES + 1],
+    /// Where the DTZ values of wins, losses, cursed wins and blessed losses
+    /// are mapped from
+    map_idx: [u16; 4],
+}
+
+impl PairsData {
+    /// The two symbols `sym` stands for, where a right one of 0xFFF makes
+    /// the left one a value
+    fn children(&self, bytes: &[u8], sym: usize) -> Option<(usize, usize)> {
+        let pair = bytes.get(self.btree + 3 * sym..self.btree + 3 * sym + 3)?;
+        let left = ((pair[1] & 0xF) as usize) << 8 | pair[0] as usize;
+        let right = (pair[2] as usize) << 4 | (pair[1] >> 4) as usize;
+        Some((left, right))
+    }
+
+    fn set_symlen(&mut self, bytes: &[u8], sym: usize, visited: &mut [bool]) -> Option<u8> {
+        *visited.get_mut(sym)? = true;
+        let (left, right) = self.children(bytes, sym)?;
+        if right == 0xFFF {
+            return Some(0);
+        }
+        for child in [left, right] {
+            if !*visited.get(child)? {
+                self.symlen[child] = self.set_symlen(bytes, child, visited)?;
+            }
+        }
+        Some(
+            self.symlen[left]
+                .wrapping_add(self.symlen[right])
+                .wrapping_add(1),
+        )
+    }
+
+    /// Positions in the part, which is what the last group's number is
+    /// multiplied by
+    fn size(&self) -> u64 {
+        let groups = self
+            .group_len
+            .iter()
+            .position(|&l| l == 0)
+            .unwrap_or(MAX_PIECES);
+        self.group_idx[groups]
+    }
+
+    /// Reads the sizes of the part from `at`, returning where they end
+    fn set_sizes(&mut self, bytes: &[u8], mut at: usize) -> Option<usize> {
+        self.flags = *bytes.get(at)?;
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            // Every position has this value
+            self.min_sym_len = *bytes.get(at + 1)?;
+            return Some(at + 2);
+        }
+        let tb_size = self.size();
+        let header = bytes.get(at + 1..at + 10)?;
+        self.block_size = 1usize.checked_shl(header[0] as u32)?;
+        self.span = 1u64.checked_shl(header[1] as u32)?;
+        self.sparse_index_size = tb_size.div_ceil(self.span) as usize;
+        self.num_blocks = read_u32(bytes, at + 4)? as usize;
+        // Padded so the sparse index never points past the end
+        self.block_length_size = self.num_blocks + header[2] as usize;
+        let (max_sym_len, min_sym_len) = (header[7], header[8]);
+        self.min_sym_len = min_sym_len;
+        at += 10;
+
+        // Canonical Huffman codes, where longer codes have lower values
+        self.lowest_sym = at;
+        let lengths = max_sym_len.checked_sub(min_sym_len)? as usize + 1;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = read_u16(bytes, at + 2 * i)? as u64;
+            let next = read_u16(bytes, at + 2 * i + 2)? as u64;
+            self.base64[i] = self.base64[i + 1].wrapping_add(lowest).wrapping_sub(next) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            let shift = 64u32.checked_sub(i as u32 + min_sym_len as u32)?;
+            *base = base.checked_shl(shift).unwrap_or(0);
+        }
+        at += 2 * lengths;
+
+        let symbols = read_u16(bytes, at)? as usize;
+        self.btree = at + 2;
+        bytes.get(self.btree..self.btree + 3 * symbols)?;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(bytes, sym, &mut visited)?;
+            }
+        }
+        Some(self.btree + 3 * symbols + (symbols & 1))
+    }
+
+    /// Splits the pieces into groups and works out what each group's number
+    /// is multiplied by, in the order the table gives
+    fn set_groups(&mut self, table: &Table, order: [u8; 2], file: usize) {
+        let encoding = &ENCODING;
+        let mut n = 0;
+        // Without Pawns the first group is three different pieces, or the Kings
+        let mut first_len = match (table.has_pawns, table.has_unique_pieces) {
+            (true, _) => 0,
+            (false, true) => 3,
+            (false, false) => 2,
+        };
+        self.group_len[0] = 1;
+        for i in 1..table.pieces {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] == self.pieces[i - 1] {
+                self.group_len[n] += 1;
+            } else {
+                n += 1;
+                self.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+
+        // Pawns of both sides come before the pieces
+        let both_pawns = table.has_pawns && table.pawn_count[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free = 64 - self.group_len[0] - if both_pawns { self.group_len[1] } else { 0 };
+        let mut idx = 1;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                self.group_idx[0] = idx;
+                idx *= match (table.has_pawns, table.has_unique_pieces) {
+                    (true, _) => encoding.lead_pawns_size[self.group_len[0]][file],
+                    (false, true) => 31332,
+                    (false, false) => 462,
+                };
+            } else if k == order[1] as usize {
+                self.group_idx[1] = idx;
+                idx *= encoding.binomial[self.group_len[1]][48 - self.group_len[0]];
+            } else {
+                self.group_idx[next] = idx;
+                idx *= encoding.binomial[self.group_len[next]][free];
+                free -= self.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        self.group_idx[n] = idx;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    /// Win, draw or loss
+    Wdl,
+    /// Distance to zeroing the fifty move counter
+    Dtz,
+}
+
+/// Headers of a table file are read first and only ever take a small part
+/// of it, well within this
+const MAX_HEADER: u64 = 1 << 22;
+
+/// A table file with its parts decoded from the headers, which are kept in
+/// memory. The sparse indices, block lengths and blocks that follow make up
+/// nearly all of the file, so they are read from it as positions are probed.
+struct TableData {
+    file: File,
+    /// Everything before the sparse indices: the parts' sizes and symbols,
+    /// and the DTZ value maps
+    header: Vec<u8>,
+    /// Parts by side to move, of which DTZ and symmetric tables only store
+    /// one, and then by the file of the leading Pawn
+    pairs: Vec<Vec<PairsData>>,
+    /// Start of the DTZ value maps
+    map: usize,
+}
+
+impl TableData {
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        let side = &self.pairs[stm % self.pairs.len()];
+        &side[file % side.len()]
+    }
+
+    fn read(&self, buf: &mut [u8], at: usize) -> Option<()> {
+        read_exact_at(&self.file, buf, at as u64).ok()
+    }
+
+    fn read_u16(&self, at: usize) -> Option<u16> {
+        let mut buf = [0; 2];
+        self.read(&mut buf, at)?;
+        Some(u16::from_le_bytes(buf))
+    }
+
+    /// Value of the position numbered `idx` in part `d`
+    fn decompress(&self, d: &PairsData, idx: u64) -> Option<i32> {
+        let header = &self.header[..];
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(d.min_sym_len as i32);
+        }
+
+        // The sparse index points to a block and an offset in it every span
+        // positions, from where the blocks' lengths lead to the position
+        let k = (idx / d.span) as usize;
+        let mut entry = [0; 6];
+        self.read(&mut entry, d.sparse_index + 6 * k)?;
+        let mut block = read_u32(&entry, 0)? as usize;
+        let mut offset = read_u16(&entry, 4)? as i64;
+        offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+        let block_length = |block: usize| self.read_u16(d.block_length + 2 * block);
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? as i64 + 1;
+        }
+        while offset > block_length(block)? as i64 {
+            offset -= block_length(block)? as i64 + 1;
+            block += 1;
+        }
+
+        // Then the block's symbols are skipped until the one standing for
+        // the position's value
+        let min_sym_len = d.min_sym_len as usize;
+        let mut bytes = vec![0; d.block_size];
+        self.read(&mut bytes, d.data + block * d.block_size)?;
+        let mut at = 0;
+        let mut buf = read_bits(&bytes, at, 8);
+        let mut buf_size = 64;
+        at += 8;
+        let mut sym = loop {
+            let mut len = 0;
+            while buf < *d.base64.get(len)? {
+                len += 1;
+            }
+            let shift = 64usize.checked_sub(len + min_sym_len)? as u32;
+            let code = (buf - d.base64[len]).checked_shr(shift).unwrap_or(0);
+            let sym =
+                (code as u16).wrapping_add(read_u16(header, d.lowest_sym + 2 * len)?) as usize;
+            let values = *d.symlen.get(sym)? as i64 + 1;
+            if offset < values {
+                break sym;
+            }
+            offset -= values;
+            len += min_sym_len;
+            buf = buf.checked_shl(len as u32).unwrap_or(0);
+            buf_size -= len as i32;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= read_bits(&bytes, at, 4) << (64 - buf_size);
+                at += 4;
+            }
+        };
+
+        // Which is expanded into its pairs down to the value
+        while d.symlen[sym] != 0 {
+            let (left, right) = d.children(header, sym)?;
+            let values = *d.symlen.get(left)? as i64 + 1;
+            if offset < values {
+                sym = left;
+            } else {
+                offset -= values;
+                sym = right;
+            }
+            d.symlen.get(sym)?;
+        }
+        Some(d.children(header, sym)?.0 as i32)
+    }
+
+    /// Turns a stored DTZ value into plies, for a position whose result is
+    /// `wdl`
+    fn map_dtz(&self, file: usize, mut value: i32, wdl: Wdl) -> Option<i32> {
+        let d = self.get(0, file);
+        if d.flags & FLAG_MAPPED != 0 {
+            let map = match wdl {
+                Wdl::Win | Wdl::Draw => 0,
+                Wdl::Loss => 1,
+                Wdl::CursedWin => 2,
+                Wdl::BlessedLoss => 3,
+            };
+            let at = d.map_idx[map] as usize + value as usize;
+            value = match d.flags & FLAG_WIDE {
+                0 => *self.header.get(self.map + at)? as i32,
+                _ => read_u16(&self.header, self.map + 2 * at)? as i32,
+            };
+        }
+        // Stored in moves unless the table says plies
+        let moves = match wdl {
+            Wdl::Win => d.flags & FLAG_WIN_PLIES == 0,
+            Wdl::Loss => d.flags & FLAG_LOSS_PLIES == 0,
+            Wdl::CursedWin | Wdl::BlessedLoss => true,
+            Wdl::Draw => false,
+        };
+        Some(if moves { value * 2 } else { value } + 1)
+    }
+}
+
+/// What looking a position up in its table gives
+enum Lookup {
+    Found(i32),
+    /// The DTZ table only stores the other side to move
+    OtherSide,
+}
+
+/// The WDL and DTZ files of one material balance, such as KRvK for a Rook
+/// against a lone King, which also covers KvKR
+struct Table {
+    wdl_path: PathBuf,
+    dtz_path: Option<PathBuf>,
+    pieces: usize,
+    has_pawns: bool,
+    /// Whether any side has a piece besides the King that is the only one
+    /// of its kind
+    has_unique_pieces: bool,
+    /// Pawns of the leading side, the one with fewer but some, then of the
+    /// other
+    pawn_count: [usize; 2],
+    /// Both sides have the same material, so only White to move is stored
+    symmetric: bool,
+    /// Opened and decoded when first probed
+    wdl: OnceLock<Option<TableData>>,
+    dtz: OnceLock<Option<TableData>>,
+}
+
+impl Table {
+    fn new(white: &str, black: &str, wdl_path: PathBuf, dtz_path: Option<PathBuf>) -> Table {
+        let count = |side: &str, piece: char| side.matches(piece).count();
+        let has_unique_pieces = [white, black]
+            .into_iter()
+            .any(|side| "QRBNP".chars().any(|piece| count(side, piece) == 1));
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        Table {
+            wdl_path,
+            dtz_path,
+            pieces: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count: match white_leads {
+                true => [white_pawns, black_pawns],
+                false => [black_pawns, white_pawns],
+            },
+            symmetric: white == black,
+            wdl: OnceLock::new(),
+            dtz: OnceLock::new(),
+        }
+    }
+
+    fn data(&self, kind: Kind) -> Option<&TableData> {
+        let (data, path) = match kind {
+            Kind::Wdl => (&self.wdl, Some(&self.wdl_path)),
+            Kind::Dtz => (&self.dtz, self.dtz_path.as_ref()),
+        };
+        data.get_or_init(|| self.open(File::open(path?).ok()?, kind))
+            .as_ref()
+    }
+
+    /// Reads the headers of a table file, more of the file at a time until
+    /// they are all in
+    fn open(&self, file: File, kind: Kind) -> Option<TableData> {
+        let len = file.metadata().ok()?.len();
+        if len % 64 != 16 {
+            return None;
+        }
+        let mut size = 1 << 16;
+        loop {
+            let mut header = vec![0; size.min(len) as usize];
+            read_exact_at(&file, &mut header, 0).ok()?;
+            match self.read(&header, len, kind) {
+                Some((pairs, map, end)) => {
+                    header.truncate(end);
+                    return Some(TableData {
+                        file,
+                        header,
+                        pairs,
+                        map,
+                    });
+                }
+                None if size < len.min(MAX_HEADER) => size *= 4,
+                None => return None,
+            }
+        }
+    }
+
+    /// Decodes the headers of a table file `len` bytes long, which are
+    /// followed by the sparse indices, block lengths and blocks of every part
+    /// in turn, returning the parts, the start of the DTZ value maps and the
+    /// end of the headers
+    fn read(
+        &self,
+        bytes: &[u8],
+        len: u64,
+        kind: Kind,
+    ) -> Option<(Vec<Vec<PairsData>>, usize, usize)> {
+        let magic = match kind {
+            Kind::Wdl => WDL_MAGIC,
+            Kind::Dtz => DTZ_MAGIC,
+        };
+        if *bytes.get(..4)? != magic {
+            return None;
+        }
+        let has_pawns = bytes[4] & 2 != 0;
+        if has_pawns != self.has_pawns {
+            return None;
+        }
+        let sides = if kind == Kind::Wdl && !self.symmetric {
+            2
+        } else {
+            1
+        };
+        let files = if has_pawns { 4 } else { 1 };
+        let both_pawns = has_pawns && self.pawn_count[1] > 0;
+        let mut pairs = vec![vec![PairsData::default(); files]; sides];
+
+        let mut at = 5;
+        for file in 0..files {
+            // The order groups are numbered in, for each side
+            let first = *bytes.get(at)?;
+            let second = if both_pawns {
+                *bytes.get(at + 1)?
+            } else {
+                0xFF
+            };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            at += 1 + both_pawns as usize;
+            for k in 0..self.pieces {
+                let piece = *bytes.get(at + k)?;
+                for (side, side_pairs) in pairs.iter_mut().enumerate() {
+                    side_pairs[file].pieces[k] = if side == 0 { piece & 0xF } else { piece >> 4 };
+                }
+            }
+            at += self.pieces;
+            for (side, side_pairs) in pairs.iter_mut().enumerate() {
+                side_pairs[file].set_groups(self, order[side], file);
+            }
+        }
+        at += at & 1;
+
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                at = side_pairs[file].set_sizes(bytes, at)?;
+            }
+        }
+
+        let map = at;
+        if kind == Kind::Dtz {
+            for d in pairs[0].iter_mut().filter(|d| d.flags & FLAG_MAPPED != 0) {
+                for i in 0..4 {
+                    if d.flags & FLAG_WIDE != 0 {
+                        at += at & 1;
+                        d.map_idx[i] = ((at - map) / 2 + 1) as u16;
+                        at += 2 * read_u16(bytes, at)? as usize + 2;
+                    } else {
+                        d.map_idx[i] = (at - map + 1) as u16;
+                        at += *bytes.get(at)? as usize + 1;
+                    }
+                }
+            }
+            at += at & 1;
+        }
+        let end = at;
+
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                side_pairs[file].sparse_index = at;
+                at += 6 * side_pairs[file].sparse_index_size;
+            }
+        }
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                side_pairs[file].block_length = at;
+                at += 2 * side_pairs[file].block_length_size;
+            }
+        }
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                // Blocks start on 64 byte boundaries
+                at = at.next_multiple_of(64);
+                side_pairs[file].data = at;
+                at += side_pairs[file].num_blocks * side_pairs[file].block_size;
+            }
+        }
+        (at as u64 <= len).then_some((pairs, map, end))
+    }
+
+    /// Looks up `game`, seen with the colours swapped when `swapped`, where
+    /// DTZ values are turned into plies knowing the result is `wdl`
+    fn lookup(&self, game: &GameState, swapped: bool, kind: Kind, wdl: Wdl) -> Option<Lookup> {
+        let data = self.data(kind)?;
+        // Tables are stored with White as the stronger side, and symmetric
+        // ones with White to move
+        let flip = swapped || (self.symmetric && game.turn == Sides::Black);
+        let (flip_color, flip_squares) = if flip { (8, 56) } else { (0, 0) };
+        let stm = flip as usize ^ game.turn as usize;
+
+        let board = tb_pieces(game);
+        let mut squares = [0; MAX_PIECES];
+        let mut pieces = [0; MAX_PIECES];
+        let mut size = 0;
+        let mut file = 0;
+        let mut lead_pawn = None;
+        if self.has_pawns {
+            // Tables with Pawns have a part for each file of the leading Pawn
+            let pawn = data.get(0, 0).pieces[0] ^ flip_color;
+            for &(sq, piece) in board.iter().filter(|(_, piece)| *piece == pawn) {
+                squares[size] = sq ^ flip_squares;
+                pieces[size] = piece ^ flip_color;
+                size += 1;
+            }
+            let map_pawns = &ENCODING.map_pawns;
+            let lead = (0..size).fold(0, |lead, i| {
+                match map_pawns[squares[i]] > map_pawns[squares[lead]] {
+                    true => i,
+                    false => lead,
+                }
+            });
+            squares.swap(0, lead);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+            lead_pawn = Some(pawn);
+        }
+        let lead_pawns = size;
+
+        // Symmetric tables without Pawns hold both sides to move as one
+        let one_side = self.symmetric && !self.has_pawns;
+        if kind == Kind::Dtz && !one_side {
+            let flags = data.get(stm, file).flags;
+            if (flags & FLAG_STM) as usize != stm {
+                return Some(Lookup::OtherSide);
+            }
+        }
+
+        for &(sq, piece) in board.iter().filter(|(_, piece)| Some(*piece) != lead_pawn) {
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = piece ^ flip_color;
+            size += 1;
+        }
+        let d = data.get(stm, file);
+        let idx = self.encode(d, &mut squares[..size], &mut pieces[..size], lead_pawns);
+        let value = data.decompress(d, idx)?;
+        Some(Lookup::Found(match kind {
+            Kind::Wdl => value - 2,
+            Kind::Dtz => data.map_dtz(file, value, wdl)?,
+        }))
+    }
+
+    /// Number of the position in part `d`, with the leading Pawns first in
+    /// `squares` and the one furthest from the centre first of all
+    fn encode(
+        &self,
+        d: &PairsData,
+        squares: &mut [usize],
+        pieces: &mut [u8],
+        lead_pawns: usize,
+    ) -> u64 {
+        let encoding = &ENCODING;
+        let size = squares.len();
+        // Puts the pieces in the order of the part
+        for i in lead_pawns..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|&j| pieces[j] == d.pieces[i]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        // Mirrors the board so the leading piece is on the queen side
+        if squares[0] % 8 > 3 {
+            squares.iter_mut().for_each(|sq| *sq ^= 7);
+        }
+
+        let mut idx = if self.has_pawns {
+            let mut idx = encoding.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|&sq| encoding.map_pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += encoding.binomial[i][encoding.map_pawns[sq]];
+            }
+            idx
+        } else {
+            // Then into the lower half and below the a1-h8 diagonal, going
+            // by the first piece of the leading group off the diagonal
+            if squares[0] / 8 > 3 {
+                squares.iter_mut().for_each(|sq| *sq ^= 56);
+            }
+            if let Some(i) = (0..d.group_len[0]).find(|&i| off_diagonal(squares[i]) != 0) {
+                if off_diagonal(squares[i]) > 0 {
+                    squares[i..]
+                        .iter_mut()
+                        .for_each(|sq| *sq = ((*sq >> 3) | (*sq << 3)) & 63);
+                }
+            }
+            if self.has_unique_pieces {
+                encode_unique(squares[0], squares[1], squares[2])
+            } else {
+                encoding.map_kk[encoding.map_a1d1d4[squares[0]]][squares[1]] as u64
+            }
+        };
+
+        // The other groups each choose from the squares left, in order
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        for next in 1..d.group_len.len() {
+            let len = d.group_len[next];
+            if len == 0 {
+                break;
+            }
+            let (before, group) = squares.split_at_mut(start);
+            let group = &mut group[..len];
+            group.sort_unstable();
+            let mut n = 0;
+            for (i, &sq) in group.iter().enumerate() {
+                let taken = before.iter().filter(|&&s| sq > s).count();
+                n += encoding.binomial[i + 1][sq - taken - 8 * remaining_pawns as usize];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+        }
+        idx
+    }
+}
+
+/// Number of three different leading pieces, the first in the a1-d1-d4
+/// triangle and the first off the diagonal below it
+fn encode_unique(s0: usize, s1: usize, s2: usize) -> u64 {
+    let encoding = &ENCODING;
+    let adjust1 = (s1 > s0) as usize;
+    let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+    let idx = if off_diagonal(s0) != 0 {
+        (encoding.map_a1d1d4[s0] * 63 + s1 - adjust1) * 62 + s2 - adjust2
+    } else if off_diagonal(s1) != 0 {
+        (6 * 63 + (s0 / 8) * 28 + encoding.map_b1h1h7[s1]) * 62 + s2 - adjust2
+    } else if off_diagonal(s2) != 0 {
+        6 * 63 * 62
+            + 4 * 28 * 62
+            + (s0 / 8) * 7 * 28
+            + (s1 / 8 - adjust1) * 28
+            + encoding.map_b1h1h7[s2]
+    } else {
+        6 * 63 * 62
+            + 4 * 28 * 62
+            + 4 * 7 * 28
+            + (s0 / 8) * 7 * 6
+            + (s1 / 8 - adjust1) * 6
+            + (s2 / 8 - adjust2)
+    };
+    idx as u64
+}
+
+/// Every piece by square numbered from a1, coded as in the tables
+fn tb_pieces(game: &GameState) -> Vec<(usize, u8)> {
+    let mut pieces = vec![];
+    for side in [Sides::White, Sides::Black] {
+        for piece in PIECES {
+            let code = match piece {
+                Piece::Pawn => 1,
+                Piece::Knight => 2,
+                Piece::Bishop => 3,
+                Piece::Rook => 4,
+                Piece::Queen => 5,
+                Piece::King => 6,
+            } | (side as u8) << 3;
+            let mut bits = game.board(side, piece).0;
+            while bits != 0 {
+                pieces.push((bits.trailing_zeros() as usize ^ 56, code));
+                bits &= bits - 1;
+            }
+        }
+    }
+    pieces.sort_unstable();
+    pieces
+}
+
+/// Material of `side` as in table names, such as KRP
+fn signature(game: &GameState, side: Sides) -> String {
+    [
+        (Piece::King, 'K'),
+        (Piece::Queen, 'Q'),
+        (Piece::Rook, 'R'),
+        (Piece::Bishop, 'B'),
+        (Piece::Knight, 'N'),
+        (Piece::Pawn, 'P'),
+    ]
+    .into_iter()
+    .flat_map(|(piece, c)| std::iter::repeat_n(c, game.board(side, piece).0.count_ones() as usize))
+    .collect()
+}
+
+/// Material of each side from a table name such as KRPvKR
+fn parse_name(name: &str) -> Option<(String, String)> {
+    let side = |side: &str| {
+        let valid = side.starts_with('K')
+            && side.matches('K').count() == 1
+            && side.chars().all(|c| "KQRBNP".contains(c));
+        valid.then(|| {
+            "KQRBNP"
+                .chars()
+                .flat_map(|c| std::iter::repeat_n(c, side.matches(c).count()))
+                .collect::<String>()
+        })
+    };
+    let (white, black) = name.split_once('v')?;
+    let (white, black) = (side(white)?, side(black)?);
+    (white.len() + black.len() <= MAX_PIECES).then_some((white, black))
+}
+
+/// A root move ranked by the tables
+#[derive(Clone, Debug)]
+pub struct RootMove {
+    pub mov: Move,
+    /// Plies to the next capture or Pawn move with perfect play from the
+    /// root, positive when winning and 0 for draws
+    pub dtz: i32,
+    /// Higher for better moves, equal for wins that the fifty move rule
+    /// can't stop and for losses it can't save
+    pub rank: i32,
+}
+
+/// Syzygy endgame tablebases, which hold the result of every position with
+/// few enough pieces in WDL files and the distance to zeroing the fifty
+/// move counter (a capture or Pawn move) that keeps it in DTZ files. Only
+/// positions of standard chess without castling rights are in them.
+#[derive(Default)]
+pub struct Tablebases {
+    tables: Vec<Table>,
+    /// Tables by the material of White and Black, and whether it is seen
+    /// with the colours swapped
+    index: HashMap<(String, String), (usize, bool)>,
+    max_pieces: usize,
+}
+
+impl Tablebases {
+    /// Finds the tables in `paths`, directories separated as in PATH, which
+    /// are opened when first probed
+    pub fn open(paths: &str) -> io::Result<Tablebases> {
+        let mut files: HashMap<String, (Option<PathBuf>, Option<PathBuf>)> = HashMap::new();
+        for dir in std::env::split_paths(paths) {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                let (Some(name), Some(ext)) = (path.file_stem(), path.extension()) else {
+                    continue;
+                };
+                let found = files
+                    .entry(name.to_string_lossy().into_owned())
+                    .or_default();
+                // The first directory with a table wins
+                match ext.to_str() {
+                    Some("rtbw") if found.0.is_none() => found.0 = Some(path),
+                    Some("rtbz") if found.1.is_none() => found.1 = Some(path),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut tablebases = Tablebases::default();
+        let mut names: Vec<_> = files.into_iter().collect();
+        names.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, (wdl, dtz)) in names {
+            let (Some((white, black)), Some(wdl)) = (parse_name(&name), wdl) else {
+                continue;
+            };
+            let i = tablebases.tables.len();
+            let table = Table::new(&white, &black, wdl, dtz);
+            tablebases.max_pieces = tablebases.max_pieces.max(table.pieces);
+            tablebases.tables.push(table);
+            tablebases
+                .index
+                .insert((black.clone(), white.clone()), (i, true));
+            tablebases.index.insert((white, black), (i, false));
+        }
+        Ok(tablebases)
+    }
+
+    /// Number of tables found
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Most pieces of any table, Kings included
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    fn probeable(&self, game: &GameState) -> bool {
+        let pieces: u32 = game.state.iter().flatten().map(|b| b.0.count_ones()).sum();
+        game.variant == Variant::Standard
+            && game.castle_rights == 0
+            && pieces as usize <= self.max_pieces
+    }
+
+    fn probe_table(&self, game: &GameState, kind: Kind, wdl: Wdl) -> Option<Lookup> {
+        let (white, black) = (signature(game, Sides::White), signature(game, Sides::Black));
+        if white.len() + black.len() == 2 {
+            return Some(Lookup::Found(0));
+        }
+        let &(i, swapped) = self.index.get(&(white, black))?;
+        self.tables[i].lookup(game, swapped, kind, wdl)
+    }
+
+    /// Result of `game` and whether a zeroing move achieves it. The tables
+    /// may store anything for positions a capture wins, and a drawing capture
+    /// makes them at least drawn, so captures are tried as well as the table,
+    /// and with `pawn_moves` Pawn moves too.
+    fn search(&self, game: &GameState, pawn_moves: bool) -> Option<(Wdl, bool)> {
+        let moves = game.moves();
+        let mut best = Wdl::Loss;
+        let mut zeroing = 0;
+        for mov in &moves {
+            if mov.capture.is_none() && !(pawn_moves && mov.piece == Piece::Pawn) {
+                continue;
+            }
+            zeroing += 1;
+            let value = -self.search(&game.apply(mov.clone()), false)?.0;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // Positions with an en passant capture aren't stored, so when every
+        // move was tried the table isn't needed and may well be wrong
+        let all_zeroing = zeroing > 0 && zeroing == moves.len();
+        let value = match all_zeroing {
+            true => best,
+            false => match self.probe_table(game, Kind::Wdl, Wdl::Draw)? {
+                Lookup::Found(value) => Wdl::from_value(value),
+                Lookup::OtherSide => return None,
+            },
+        };
+        if best >= value {
+            return Some((best, best > Wdl::Draw || all_zeroing));
+        }
+        Some((value, false))
+    }
+
+    fn dtz(&self, game: &GameState) -> Option<i32> {
+        let (wdl, zeroing) = self.search(game, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+        if let Lookup::Found(dtz) = self.probe_table(game, Kind::Dtz, wdl)? {
+            return Some((dtz + 100 * cursed as i32) * wdl.value().signum());
+        }
+
+        // The table stores the other side to move, so the best move's DTZ
+        // is taken instead
+        let mut min = i32::MAX;
+        for mov in game.moves() {
+            let zeroing = mov.capture.is_some() || mov.piece == Piece::Pawn;
+            let next = game.apply(mov);
+            let mut dtz = match zeroing {
+                true => -dtz_before_zeroing(self.search(&next, false)?.0),
+                false => -self.dtz(&next)?,
+            };
+            if dtz == 1 && next.in_check() && next.moves().is_empty() {
+                min = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min && dtz.signum() == wdl.value().signum() {
+                min = dtz;
+            }
+        }
+        // Without legal moves it is mate
+        Some(if min == i32::MAX { -1 } else { min })
+    }
+
+    /// Result of `game` for the side to move, if it is in the tables
+    pub fn probe_wdl(&self, game: &GameState) -> Option<Wdl> {
+        if !self.probeable(game) {
+            return None;
+        }
+        self.search(game, false).map(|(wdl, _)| wdl)
+    }
+
+    /// Plies to the next capture or Pawn move with perfect play, positive
+    /// when the side to move wins, negative when it loses and 0 for draws.
+    /// Cursed wins and blessed losses count 100 more.
+    pub fn probe_dtz(&self, game: &GameState) -> Option<i32> {
+        if !self.probeable(game) {
+            return None;
+        }
+        self.dtz(game)
+    }
+
+    /// Ranks every legal move of `game` by DTZ, where `history` holds the
+    /// hashes of the positions before it, so repetitions and the fifty move
+    /// rule are taken into account. Wins sure to come in time rank equally,
+    /// leaving the search to choose between them, and closer to the fifty
+    /// move rule the faster ones rank higher.
+    pub fn rank_root_moves(&self, game: &GameState, history: &[u64]) -> Option<Vec<RootMove>> {
+        if !self.probeable(game) {
+            return None;
+        }
+        let halfmoves = game.halfmoves as i32;
+        let mut seen = history[history.len().saturating_sub(game.halfmoves as usize)..].to_vec();
+        seen.push(game.hash());
+        let mut sorted = seen.clone();
+        sorted.sort_unstable();
+        let repeated = sorted.windows(2).any(|w| w[0] == w[1]);
+
+        game.moves()
+            .into_iter()
+            .map(|mov| {
+                let next = game.apply(mov.clone());
+                let mated = next.in_check() && next.moves().is_empty();
+                let mut dtz = if next.halfmoves == 0 {
+                    dtz_before_zeroing(-self.search(&next, false)?.0)
+                } else if (next.halfmoves >= 100 && !mated) || seen.contains(&next.hash()) {
+                    0
+                } else {
+                    let dtz = -self.dtz(&next)?;
+                    dtz + dtz.signum()
+                };
+                if dtz == 2 && mated {
+                    dtz = 1;
+                }
+                let rank = if dtz > 0 {
+                    match dtz + halfmoves <= 99 && !repeated {
+                        true => MAX_DTZ,
+                        false => MAX_DTZ - (dtz + halfmoves),
+                    }
+                } else if dtz < 0 {
+                    match -dtz * 2 + halfmoves < 100 {
+                        true => -MAX_DTZ,
+                        false => -MAX_DTZ + (-dtz + halfmoves),
+                    }
+                } else {
+                    0
+                };
+                Some(RootMove { mov, dtz, rank })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let name = |s: &str| parse_name(s).map(|(w, b)| format!("{w}v{b}"));
+        assert_eq!(name("KRvK").as_deref(), Some("KRvK"));
+        assert_eq!(name("KPRvKN").as_deref(), Some("KRPvKN"));
+        assert_eq!(name("KQRBNPvKP"), None);
+        assert_eq!(name("QvK"), None);
+        assert_eq!(name("KRK"), None);
+
+        let game = GameState::from("8/8/8/4k3/8/1P6/2R5/4K3 b - - 0 1".to_string());
+        assert_eq!(signature(&game, Sides::White), "KRP");
+        assert_eq!(signature(&game, Sides::Black), "K");
+        assert_eq!(-Wdl::CursedWin, Wdl::BlessedLoss);
+    }
+
+    #[test]
+    fn encoding() {
+        let e = &ENCODING;
+        assert_eq!(e.map_kk.iter().flatten().max(), Some(&461));
+        assert_eq!(e.map_a1d1d4[0], 6);
+        assert_eq!(e.map_pawns[8], 47);
+        assert_eq!(e.binomial[2][5], 10);
+        assert_eq!(e.lead_pawns_size[1], [6; 4]);
+    }
+
+    /// The eight ways to turn and mirror a board, of which those that move
+    /// Pawns sideways only are used with Pawns
+    fn symmetries(sq: usize, s: usize) -> usize {
+        let sq = if s & 1 != 0 { sq ^ 7 } else { sq };
+        let sq = if s & 2 != 0 { sq ^ 56 } else { sq };
+        if s & 4 != 0 {
+            ((sq >> 3) | (sq << 3)) & 63
+        } else {
+            sq
+        }
+    }
+
+    /// Numbers every placement of three pieces, checking that boards number
+    /// the same in the same part exactly when they are symmetric
+    fn check_encoding(name: &str, pieces: [u8; 3], pawn: bool) {
+        let (white, black) = parse_name(name).unwrap();
+        let table = Table::new(&white, &black, PathBuf::new(), None);
+        let parts: Vec<PairsData> = (0..4)
+            .map(|file| {
+                let mut d = PairsData {
+                    pieces: [pieces[0], pieces[1], pieces[2], 0, 0, 0, 0],
+                    ..Default::default()
+                };
+                d.set_groups(&table, [0, 0xF], file);
+                d
+            })
+            .collect();
+        let symmetric = if pawn { 2 } else { 8 };
+        let mut boards = HashMap::new();
+        for a in (0..64).filter(|&sq| !pawn || (8..56).contains(&sq)) {
+            for b in (0..64).filter(|&sq| sq != a) {
+                for c in (0..64).filter(|&sq| sq != a && sq != b) {
+                    let board = |s| [symmetries(a, s), symmetries(b, s), symmetries(c, s)];
+                    // With Pawns each file of the Pawn has its own part
+                    let part = if pawn { (a % 8).min(7 - a % 8) } else { 0 };
+                    let d = &parts[part];
+                    let idx = table.encode(d, &mut board(0), &mut pieces.clone(), pawn as usize);
+                    assert!(idx < d.size());
+                    let canonical = (0..symmetric).map(board).min().unwrap();
+                    assert_eq!(*boards.entry((part, idx)).or_insert(canonical), canonical);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn indices() {
+        check_encoding("KRvK", [6, 4, 14], false);
+        check_encoding("KPvK", [1, 6, 14], true);
+    }
+
+    #[test]
+    fn missing_tables() {
+        assert!(Tablebases::open("/nonexistent/syzygy").is_err());
+
+        let dir = std::env::temp_dir().join(format!("rustle-syzygy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("KRvK.rtbw"), [0; 80]).unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+        let tablebases = Tablebases::open(dir.to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(tablebases.len(), 1);
+        assert_eq!(tablebases.max_pieces(), 3);
+
+        // A broken file can't be probed, but a bare King draws regardless
+        let game = GameState::from("8/8/8/4k3/8/8/2R5/4K3 w - - 0 1".to_string());
+        assert_eq!(tablebases.probe_wdl(&game), None);
+        let bare = GameState::from("8/8/8/4k3/8/8/8/4K3 w - - 0 1".to_string());
+        assert_eq!(tablebases.probe_wdl(&bare), Some(Wdl::Draw));
+        let start = GameState::from(crate::fen::START_FEN.to_string());
+        assert_eq!(tablebases.probe_dtz(&start), None);
+    }
+
+    // No table files come with the sources, so KRvK tables are made here: its
+    // positions are solved by retrograde analysis and written in the table
+    // format, each value a symbol of a canonical Huffman code
+
+    fn distance(a: usize, b: usize) -> usize {
+        (a % 8).abs_diff(b % 8).max((a / 8).abs_diff(b / 8))
+    }
+
+    /// Whether a Rook on `rook` attacks `sq` with `blocker` in the way
+    fn rook_attacks(rook: usize, sq: usize, blocker: usize) -> bool {
+        let between = |a: usize, b: usize, x: usize| a.min(b) < x && x < a.max(b);
+        if rook == sq {
+            false
+        } else if rook % 8 == sq % 8 {
+            !(blocker % 8 == sq % 8 && between(rook / 8, sq / 8, blocker / 8))
+        } else if rook / 8 == sq / 8 {
+            !(blocker / 8 == sq / 8 && between(rook % 8, sq % 8, blocker % 8))
+        } else {
+            false
+        }
+    }
+
+    fn krk_index(wk: usize, wr: usize, bk: usize) -> usize {
+        wk << 12 | wr << 6 | bk
+    }
+
+    fn krk_squares(i: usize) -> (usize, usize, usize) {
+        (i >> 12, i >> 6 & 63, i & 63)
+    }
+
+    fn krk_legal(wk: usize, wr: usize, bk: usize, white: bool) -> bool {
+        wk != wr && wr != bk && distance(wk, bk) > 1 && !(white && rook_attacks(wr, bk, wk))
+    }
+
+    /// Plies to mate in every KRvK position by `krk_index`, with White to
+    /// move and with Black to move, or -1 for draws and impossible ones
+    struct Krk {
+        white: Vec<i32>,
+        black: Vec<i32>,
+    }
+
+    fn solve_krk() -> Krk {
+        let n = 1 << 18;
+        let (mut white, mut black) = (vec![-1; n], vec![-1; n]);
+        let mut white_moves = vec![vec![]; n];
+        // Black's moves, unless it takes the Rook or has none
+        let mut black_moves: Vec<Option<Vec<u32>>> = vec![None; n];
+        let king_moves = |sq: usize| (0..64).filter(move |&to| distance(sq, to) == 1);
+        for i in 0..n {
+            let (wk, wr, bk) = krk_squares(i);
+            if krk_legal(wk, wr, bk, true) {
+                let king = king_moves(wk)
+                    .filter(|&to| to != wr && distance(to, bk) > 1)
+                    .map(|to| krk_index(to, wr, bk));
+                let rook = (0..64)
+                    .filter(|&to| to != wk && to != bk)
+                    .filter(|&to| rook_attacks(wr, to, wk) && rook_attacks(wr, to, bk))
+                    .map(|to| krk_index(wk, to, bk));
+                white_moves[i] = king.chain(rook).map(|j| j as u32).collect();
+            }
+            if krk_legal(wk, wr, bk, false) {
+                let escapes = king_moves(bk).filter(|&to| distance(to, wk) > 1);
+                let takes = escapes.clone().any(|to| to == wr);
+                let moves: Vec<u32> = escapes
+                    .filter(|&to| to != wr && !rook_attacks(wr, to, wk))
+                    .map(|to| krk_index(wk, wr, to) as u32)
+                    .collect();
+                if moves.is_empty() && !takes && rook_attacks(wr, bk, wk) {
+                    black[i] = 0;
+                } else if !moves.is_empty() && !takes {
+                    black_moves[i] = Some(moves);
+                }
+            }
+        }
+
+        // White mates on odd plies, and Black is lost on even ones once
+        // every move is
+        let (mut plies, mut idle) = (0, 0);
+        while idle < 2 {
+            plies += 1;
+            let mut changed = false;
+            for i in 0..n {
+                let solved = if plies % 2 == 1 {
+                    white[i] < 0
+                        && white_moves[i]
+                            .iter()
+                            .any(|&j| black[j as usize] == plies - 1)
+                } else {
+                    black[i] < 0
+                        && black_moves[i]
+                            .as_ref()
+                            .is_some_and(|moves| moves.iter().all(|&j| white[j as usize] >= 0))
+                };
+                if solved {
+                    match plies % 2 {
+                        1 => white[i] = plies,
+                        _ => black[i] = plies,
+                    }
+                    changed = true;
+                }
+            }
+            idle = if changed { 0 } else { idle + 1 };
+        }
+        Krk { white, black }
+    }
+
+    /// Sizes, sparse index, block lengths and blocks of one part of a table
+    #[derive(Default)]
+    struct Part {
+        sizes: Vec<u8>,
+        sparse_index: Vec<u8>,
+        block_lengths: Vec<u8>,
+        blocks: Vec<u8>,
+    }
+
+    /// Compresses `values` by position number into a part with `flags`,
+    /// with None where no position has the number
+    fn write_part(flags: u8, values: &[Option<u16>]) -> Part {
+        let mut counts: HashMap<u16, u64> = HashMap::new();
+        for &value in values.iter().flatten() {
+            *counts.entry(value).or_default() += 1;
+        }
+        let filler = counts
+            .iter()
+            .max_by_key(|&(&v, &c)| (c, v))
+            .map_or(0, |(&v, _)| v);
+        let values: Vec<u16> = values.iter().map(|v| v.unwrap_or(filler)).collect();
+        if counts.len() <= 1 {
+            return Part {
+                sizes: vec![flags | FLAG_SINGLE_VALUE, filler as u8],
+                ..Default::default()
+            };
+        }
+
+        // Huffman code lengths, from merging the two rarest groups of values
+        let mut lengths: HashMap<u16, u8> = HashMap::new();
+        let mut groups: Vec<(u64, Vec<u16>)> = counts.iter().map(|(&v, &c)| (c, vec![v])).collect();
+        while groups.len() > 1 {
+            groups.sort_by(|a, b| b.cmp(a));
+            let (a, b) = (groups.pop().unwrap(), groups.pop().unwrap());
+            for &value in a.1.iter().chain(&b.1) {
+                *lengths.entry(value).or_default() += 1;
+            }
+            groups.push((a.0 + b.0, [a.1, b.1].concat()));
+        }
+        let min = *lengths.values().min().unwrap();
+        let max = *lengths.values().max().unwrap();
+
+        // Symbols are numbered from the longest codes, which are the lowest
+        let mut symbols: Vec<u16> = lengths.keys().copied().collect();
+        symbols.sort_by_key(|v| (std::cmp::Reverse(lengths[v]), *v));
+        let levels = (max - min) as usize + 1;
+        let mut count = vec![0u64; levels];
+        for v in &symbols {
+            count[(lengths[v] - min) as usize] += 1;
+        }
+        let (mut lowest, mut base) = (vec![0u64; levels], vec![0u64; levels]);
+        for i in (0..levels - 1).rev() {
+            lowest[i] = lowest[i + 1] + count[i + 1];
+            base[i] = (base[i + 1] + count[i + 1]) / 2;
+        }
+        let code = |value: u16| {
+            let sym = symbols.iter().position(|&v| v == value).unwrap() as u64;
+            let i = (lengths[&value] - min) as usize;
+            (base[i] + sym - lowest[i], lengths[&value])
+        };
+        let codes: HashMap<u16, (u64, u8)> = symbols.iter().map(|&v| (v, code(v))).collect();
+
+        // Blocks of 64 bytes hold as many codes as fit
+        const BLOCK: usize = 64;
+        let (mut blocks, mut starts, mut lengths_in_blocks) = (vec![], vec![], vec![]);
+        let (mut bits, mut used) = (vec![0u8; BLOCK], 0);
+        for (idx, value) in values.iter().enumerate() {
+            let (code, len) = codes[value];
+            if used + len as usize > 8 * BLOCK || idx == 0 {
+                if idx > 0 {
+                    blocks.extend(std::mem::replace(&mut bits, vec![0; BLOCK]));
+                }
+                starts.push(idx);
+                lengths_in_blocks.push(0u16);
+                used = 0;
+            } else {
+                *lengths_in_blocks.last_mut().unwrap() += 1;
+            }
+            for k in (0..len).rev() {
+                if code >> k & 1 != 0 {
+                    bits[used / 8] |= 0x80 >> (used % 8);
+                }
+                used += 1;
+            }
+        }
+        blocks.extend(bits);
+
+        let mut sizes = vec![flags, 6, 6, 0];
+        sizes.extend((starts.len() as u32).to_le_bytes());
+        sizes.extend([max, min]);
+        for &lowest in &lowest {
+            sizes.extend((lowest as u16).to_le_bytes());
+        }
+        sizes.extend((symbols.len() as u16).to_le_bytes());
+        for &value in &symbols {
+            sizes.extend([value as u8, (value >> 8) as u8 | 0xF0, 0xFF]);
+        }
+        if symbols.len() % 2 == 1 {
+            sizes.push(0);
+        }
+
+        // Every 64 positions the sparse index points to the block and offset
+        // of the middle one
+        let mut sparse_index = vec![];
+        for k in 0..values.len().div_ceil(64) {
+            let idx = k * 64 + 32;
+            let block = starts.iter().rposition(|&start| start <= idx).unwrap();
+            sparse_index.extend((block as u32).to_le_bytes());
+            sparse_index.extend(((idx - starts[block]) as u16).to_le_bytes());
+        }
+        Part {
+            sizes,
+            sparse_index,
+            block_lengths: lengths_in_blocks
+                .iter()
+                .flat_map(|l| l.to_le_bytes())
+                .collect(),
+            blocks,
+        }
+    }
+
+    /// A KRvK table file with the pieces numbered in the order `pieces`, and
+    /// for DTZ the value maps of its part
+    fn write_table(magic: [u8; 4], pieces: [u8; 3], parts: &[Part], maps: &[u8]) -> Vec<u8> {
+        let mut file = magic.to_vec();
+        file.push(0);
+        // The three pieces are one group, numbered first
+        file.push(0);
+        file.extend(pieces.map(|p| p | p << 4));
+        file.push(0);
+        for part in parts {
+            file.extend(&part.sizes);
+        }
+        if magic == DTZ_MAGIC {
+            file.extend(maps);
+            file.resize(file.len().next_multiple_of(2), 0);
+        }
+        for part in parts {
+            file.extend(&part.sparse_index);
+        }
+        for part in parts {
+            file.extend(&part.block_lengths);
+        }
+        for part in parts {
+            file.resize(file.len().next_multiple_of(64), 0);
+            file.extend(&part.blocks);
+        }
+        // Ending with a checksum
+        file.resize(file.len().next_multiple_of(64) + 16, 0);
+        file
+    }
+
+    /// Writes KRvK.rtbw and KRvK.rtbz into `dir`, from the solved positions
+    fn write_krk(dir: &std::path::Path, krk: &Krk) {
+        let table = Table::new("KR", "K", PathBuf::new(), None);
+        let pieces = [6, 4, 14];
+        let mut d = PairsData {
+            pieces: [6, 4, 14, 0, 0, 0, 0],
+            ..Default::default()
+        };
+        d.set_groups(&table, [0, 0xF], 0);
+        let size = d.size() as usize;
+        let mut wdl = vec![vec![None; size]; 2];
+        let mut dtz = vec![None; size];
+        let set = |values: &mut Vec<Option<u16>>, idx: usize, value: u16| {
+            assert!(values[idx].is_none_or(|v| v == value));
+            values[idx] = Some(value);
+        };
+        for i in 0..1 << 18 {
+            let (wk, wr, bk) = krk_squares(i);
+            if !krk_legal(wk, wr, bk, false) {
+                continue;
+            }
+            let idx = table.encode(&d, &mut [wk, wr, bk], &mut pieces.clone(), 0) as usize;
+            if krk_legal(wk, wr, bk, true) {
+                let plies = krk.white[i];
+                set(&mut wdl[0], idx, if plies > 0 { 4 } else { 2 });
+                if plies > 0 {
+                    // Stored in moves, as wins take an odd number of plies
+                    set(&mut dtz, idx, (plies / 2) as u16);
+                }
+            }
+            if krk_legal(wk, wr, bk, false) {
+                set(&mut wdl[1], idx, if krk.black[i] >= 0 { 0 } else { 2 });
+            }
+        }
+
+        // The part holds indices into a map of the DTZ values
+        let mut map: Vec<u16> = dtz.iter().flatten().copied().collect();
+        map.sort_unstable();
+        map.dedup();
+        let symbols: Vec<Option<u16>> = dtz
+            .iter()
+            .map(|v| v.map(|v| map.binary_search(&v).unwrap() as u16))
+            .collect();
+        let mut maps = vec![map.len() as u8];
+        maps.extend(map.iter().map(|&v| v as u8));
+        maps.extend([0, 0, 0]);
+
+        let parts = [write_part(0, &wdl[0]), write_part(0, &wdl[1])];
+        let file = write_table(WDL_MAGIC, pieces, &parts, &[]);
+        std::fs::write(dir.join("KRvK.rtbw"), file).unwrap();
+        let parts = [write_part(FLAG_MAPPED, &symbols)];
+        let file = write_table(DTZ_MAGIC, pieces, &parts, &maps);
+        std::fs::write(dir.join("KRvK.rtbz"), file).unwrap();
+    }
+
+    fn krk_fen(wk: usize, wr: usize, bk: usize, white: bool) -> String {
+        let mut rows = vec![];
+        for rank in (0..8).rev() {
+            let mut row = String::new();
+            let mut empty = 0;
+            for sq in rank * 8..rank * 8 + 8 {
+                let piece = [(wk, 'K'), (wr, 'R'), (bk, 'k')]
+                    .into_iter()
+                    .find(|&(s, _)| s == sq);
+                match piece {
+                    Some((_, piece)) => {
+                        if empty > 0 {
+                            row.push_str(&empty.to_string());
+                        }
+                        row.push(piece);
+                        empty = 0;
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                row.push_str(&empty.to_string());
+            }
+            rows.push(row);
+        }
+        let turn = if white { 'w' } else { 'b' };
+        format!("{} {turn} - - 0 1", rows.join("/"))
+    }
+
+    #[test]
+    fn krk_tables() {
+        let krk = solve_krk();
+        // The longest mate with a Rook takes 16 moves
+        assert_eq!(krk.white.iter().max(), Some(&31));
+
+        let dir = std::env::temp_dir().join(format!("rustle-krk-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_krk(&dir, &krk);
+        let tablebases = Tablebases::open(dir.to_str().unwrap()).unwrap();
+        assert_eq!(tablebases.len(), 1);
+
+        let longest = krk.white.iter().position(|&p| p == 31).unwrap();
+        let samples = (0..1 << 18).step_by(97).chain([longest]);
+        for i in samples {
+            let (wk, wr, bk) = krk_squares(i);
+            for white in [true, false] {
+                if !krk_legal(wk, wr, bk, white) {
+                    continue;
+                }
+                let game = GameState::from(krk_fen(wk, wr, bk, white));
+                let plies = if white { krk.white[i] } else { krk.black[i] };
+                let expected = match (white, plies) {
+                    (_, -1) => (Wdl::Draw, 0),
+                    (true, plies) => (Wdl::Win, plies),
+                    (false, 0) => (Wdl::Loss, -1),
+                    (false, plies) => (Wdl::Loss, -plies),
+                };
+                let probed = (tablebases.probe_wdl(&game), tablebases.probe_dtz(&game));
+                assert_eq!(
+                    probed,
+                    (Some(expected.0), Some(expected.1)),
+                    "{}",
+                    game.fen()
+                );
+            }
+        }
+
+        // With the colours swapped
+        let black = GameState::from("8/8/8/4K3/8/8/2r5/4k3 b - - 0 1".to_string());
+        let white = GameState::from("4K3/2R5/8/8/4k3/8/8/8 w - - 0 1".to_string());
+        assert_eq!(tablebases.probe_wdl(&black), Some(Wdl::Win));
+        assert_eq!(tablebases.probe_dtz(&black), tablebases.probe_dtz(&white));
+
+        // The root moves keeping the win are those ranked highest
+        let ranked = tablebases.rank_root_moves(&white, &[]).unwrap();
+        let best = ranked.iter().map(|m| m.rank).max().unwrap();
+        for root in ranked.iter().filter(|m| m.rank == best) {
+            let next = white.apply(root.mov.clone());
+            assert_eq!(tablebases.probe_wdl(&next), Some(Wdl::Loss));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+/// Kept back from every allocation for the time it takes to send the move
+const MOVE_OVERHEAD: Duration = Duration::from_millis(20);
+
+/// Moves assumed left in the game when the time control doesn't say
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+/// Largest share of the remaining time a single move may use
+const MAX_SHARE: f64 = 0.75;
+
+/// How the time for a move is given, by a front-end or the GUI's clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeControl {
+    /// Exactly this long for the move
+    MoveTime(Duration),
+    /// Time left for the side to move, added after each move, with the moves
+    /// left until the next time control if there is one
+    Clock {
+        remaining: Duration,
+        increment: Duration,
+        moves_to_go: Option<u32>,
+    },
+}
+
+/// Decides when to stop searching a move. The search always stops at the
+/// hard limit, but only starts a new iteration before the soft limit, which
+/// grows when the best move keeps changing or the score drops and shrinks
+/// when the best move is stable.
+#[derive(Clone, Debug)]
+pub struct TimeManager {
+    soft: Duration,
+    hard: Duration,
+    /// Best move of the last iteration, packed
+    best: u16,
+    /// Iterations in a row that kept the same best move
+    stability: u32,
+    score: Option<i32>,
+}
+
+impl TimeManager {
+    pub fn new(control: &TimeControl) -> Self {
+        let (soft, hard) = match *control {
+            TimeControl::MoveTime(time) => {
+                let time = time.saturating_sub(MOVE_OVERHEAD);
+                (time, time)
+            }
+            TimeControl::Clock {
+                remaining,
+                increment,
+                moves_to_go,
+            } => {
+                let left = remaining.saturating_sub(MOVE_OVERHEAD);
+                let moves = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).clamp(1, 50);
+                let hard = left.mul_f64(MAX_SHARE);
+                let soft = (left / moves + increment.mul_f64(0.75)).min(hard);
+                (soft, (soft * 4).min(hard))
+            }
+        };
+        TimeManager {
+            soft: soft.max(Duration::from_millis(1)),
+            hard: hard.max(Duration::from_millis(1)),
+            best: 0,
+            stability: 0,
+            score: None,
+        }
+    }
+
+    pub fn soft(&self) -> Duration {
+        self.soft
+    }
+
+    pub fn hard(&self) -> Duration {
+        self.hard
+    }
+
+    /// Records a finished iteration's best move and score, returning whether
+    /// there is time to start another after `elapsed`
+    pub fn next_iteration(&mut self, elapsed: Duration, best: u16, score: i32) -> bool {
+        if best == self.best {
+            self.stability += 1;
+        } else {
+            self.best = best;
+            self.stability = 0;
+        }
+        let unstable = 1.5 - 0.2 * self.stability.min(4) as f64;
+        let drop = self.score.map_or(0, |last| last - score);
+        let falling = 1.0 + drop.clamp(0, 100) as f64 / 100.0;
+        self.score = Some(score);
+        elapsed < self.soft.mul_f64(unstable * falling).min(self.hard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocation() {
+        let fixed = TimeManager::new(&TimeControl::MoveTime(Duration::from_secs(2)));
+        assert_eq!(fixed.soft(), fixed.hard());
+        assert!(fixed.hard() < Duration::from_secs(2));
+
+        let clock = |remaining, increment, moves_to_go| {
+            TimeManager::new(&TimeControl::Clock {
+                remaining: Duration::from_secs(remaining),
+                increment: Duration::from_secs(increment),
+                moves_to_go,
+            })
+        };
+        let sudden_death = clock(60, 0, None);
+        assert_eq!(sudden_death.soft().as_secs(), 1);
+        assert!(sudden_death.hard() > sudden_death.soft());
+        assert!(clock(60, 1, None).soft() > sudden_death.soft());
+        // The last move before the time control may use most of the clock
+        let last = clock(60, 0, Some(1));
+        assert!(last.soft() > Duration::from_secs(40));
+        assert!(last.hard() < Duration::from_secs(46));
+        assert!(clock(0, 0, None).hard() > Duration::ZERO);
+    }
+
+    #[test]
+    fn stability() {
+        let control = TimeControl::Clock {
+            remaining: Duration::from_secs(60),
+            increment: Duration::ZERO,
+            moves_to_go: None,
+        };
+        let mut stable = TimeManager::new(&control);
+        let elapsed = stable.soft().mul_f64(0.95);
+        for _ in 0..4 {
+            stable.next_iteration(Duration::ZERO, 1, 20);
+        }
+        assert!(!stable.next_iteration(elapsed, 1, 20));
+
+        let mut changing = TimeManager::new(&control);
+        changing.next_iteration(Duration::ZERO, 1, 20);
+        assert!(changing.next_iteration(elapsed, 2, 20));
+
+        let mut falling = TimeManager::new(&control);
+        for _ in 0..4 {
+            falling.next_iteration(Duration::ZERO, 1, 20);
+        }
+        assert!(falling.next_iteration(elapsed, 1, -80));
+    }
+}
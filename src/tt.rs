@@ -0,0 +1,298 @@
+use crate::board::*;
+use crate::moves::Move;
+use crate::search::TB_BOUND;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+/// Entries per bucket, so a bucket fills a 64 byte cache line
+const BUCKET: usize = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Bound {
+    #[default]
+    Exact,
+    /// Failed high, the score is at least this
+    Lower,
+    /// Failed low, the score is at most this
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Entry {
+    key: u64,
+    pub best: u16,
+    pub score: i16,
+    pub depth: u8,
+    pub bound: Bound,
+    age: u8,
+}
+
+impl Entry {
+    fn encode(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        self.best as u64
+            | (self.score as u16 as u64) << 16
+            | (self.depth as u64) << 32
+            | bound << 40
+            | (self.age as u64) << 48
+    }
+
+    fn decode(key: u64, data: u64) -> Self {
+        Entry {
+            key,
+            best: data as u16,
+            score: (data >> 16) as u16 as i16,
+            depth: (data >> 32) as u8,
+            bound: match (data >> 40) & 3 {
+                1 => Bound::Lower,
+                2 => Bound::Upper,
+                _ => Bound::Exact,
+            },
+            age: (data >> 48) as u8,
+        }
+    }
+}
+
+/// An entry as two words, where the key is stored xored with the data. Threads
+/// read and write slots without locking, so a slot can hold halves of two
+/// different writes, which then no longer match their key and are ignored.
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self) -> Entry {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed) ^ data;
+        Entry::decode(key, data)
+    }
+
+    fn save(&self, entry: &Entry) {
+        let data = entry.encode();
+        self.data.store(data, Ordering::Relaxed);
+        self.key.store(entry.key ^ data, Ordering::Relaxed);
+    }
+}
+
+impl Move {
+    /// Compact form stored in the table, where 0 is no move. Drops set the high
+    /// bit and keep the dropped piece where promotions are stored.
+    pub fn pack(&self) -> u16 {
+        let piece = match (self.drop, self.promotion) {
+            (true, _) => self.piece as u16 + 1,
+            (false, Some(piece)) => piece as u16 + 1,
+            (false, None) => 0,
+        };
+        self.from as u16 | (self.to as u16) << 6 | piece << 12 | (self.drop as u16) << 15
+    }
+}
+
+impl GameState {
+    /// Finds the legal move matching a packed move, which may be stale or
+    /// belong to another position sharing the bucket
+    pub fn unpack(&self, packed: u16) -> Option<Move> {
+        if packed == 0 {
+            return None;
+        }
+        self.moves().into_iter().find(|m| m.pack() == packed)
+    }
+}
+
+/// Converts a mate or tablebase score relative to the root into one
+/// relative to this node
+pub fn score_to_tt(score: i32, ply: usize) -> i16 {
+    let score = if score >= TB_BOUND {
+        score + ply as i32
+    } else if score <= -TB_BOUND {
+        score - ply as i32
+    } else {
+        score
+    };
+    score as i16
+}
+
+pub fn score_from_tt(score: i16, ply: usize) -> i32 {
+    let score = score as i32;
+    if score >= TB_BOUND {
+        score - ply as i32
+    } else if score <= -TB_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+/// Shared by every search thread, which all read and write it at once
+pub struct TranspositionTable {
+    buckets: Vec<[Slot; BUCKET]>,
+    age: AtomicU8,
+}
+
+impl TranspositionTable {
+    pub fn new(mb: usize) -> Self {
+        let mut tt = TranspositionTable {
+            buckets: vec![],
+            age: AtomicU8::new(0),
+        };
+        tt.resize(mb);
+        tt
+    }
+
+    pub fn resize(&mut self, mb: usize) {
+        let count = (mb * 1024 * 1024 / std::mem::size_of::<[Slot; BUCKET]>()).max(1);
+        self.buckets = (0..count).map(|_| Default::default()).collect();
+    }
+
+    pub fn clear(&self) {
+        for slot in self.buckets.iter().flatten() {
+            slot.save(&Entry::default());
+        }
+        self.age.store(0, Ordering::Relaxed);
+    }
+
+    /// Ages every entry, so entries from earlier searches are replaced first
+    pub fn new_search(&self) {
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bucket(&self, hash: u64) -> usize {
+        ((hash as u128 * self.buckets.len() as u128) >> 64) as usize
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<Entry> {
+        self.buckets[self.bucket(hash)]
+            .iter()
+            .map(Slot::load)
+            .find(|e| e.key == hash && e.depth > 0)
+    }
+
+    /// Stores a search result of at least one ply, replacing the same position
+    /// or else the entry that is shallowest once older searches are discounted
+    pub fn store(
+        &self,
+        hash: u64,
+        depth: u8,
+        bound: Bound,
+        score: i32,
+        best: Option<&Move>,
+        ply: usize,
+    ) {
+        let age = self.age.load(Ordering::Relaxed);
+        let bucket = self.buckets[self.bucket(hash)].each_ref().map(Slot::load);
+        let slot = match bucket.iter().position(|e| e.key == hash) {
+            Some(slot) => slot,
+            None => (0..BUCKET)
+                .min_by_key(|&i| {
+                    let entry = &bucket[i];
+                    let stale = age.wrapping_sub(entry.age) as i32;
+                    entry.depth as i32 - 8 * stale
+                })
+                .unwrap(),
+        };
+
+        let entry = &bucket[slot];
+        // Keep the old move when the new search didn't produce one
+        let best = match best {
+            Some(m) => m.pack(),
+            None if entry.key == hash => entry.best,
+            None => 0,
+        };
+        // Shallower results only overwrite the same position's exact scores
+        // when they come from the current search
+        let target = &self.buckets[self.bucket(hash)][slot];
+        if entry.key == hash && entry.age == age && depth < entry.depth && bound != Bound::Exact {
+            target.save(&Entry { best, ..*entry });
+            return;
+        }
+        target.save(&Entry {
+            key: hash,
+            best,
+            score: score_to_tt(score, ply),
+            depth,
+            bound,
+            age,
+        });
+    }
+
+    /// Permille of sampled entries written by the current search
+    pub fn hashfull(&self) -> usize {
+        let age = self.age.load(Ordering::Relaxed);
+        let sample = self.buckets.iter().take(1000 / BUCKET).flatten();
+        let total = (self.buckets.len() * BUCKET).min(1000);
+        let used = sample
+            .map(Slot::load)
+            .filter(|e| e.depth > 0 && e.age == age)
+            .count();
+        used * 1000 / total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::START_FEN;
+    use crate::search::{MATE, MATE_BOUND, TB_WIN};
+
+    #[test]
+    fn hashes_transpose() {
+        let start = GameState::from(START_FEN.to_string());
+        let play = |sans: &[&str]| {
+            sans.iter().fold(start.clone(), |game, san| {
+                game.apply(game.parse_san(san).unwrap())
+            })
+        };
+        let a = play(&["Nf3", "Nf6", "Nc3"]);
+        let b = play(&["Nc3", "Nf6", "Nf3"]);
+        assert_eq!(a.hash(), b.hash());
+        assert_ne!(a.hash(), play(&["Nc3", "Nf6"]).hash());
+        assert_ne!(play(&["e4"]).hash(), play(&["e3", "a6", "e4"]).hash());
+
+        // A promoted Queen goes to the pocket as a Pawn
+        let queen = GameState::from("4k3/8/8/8/8/8/8/Q3K3[] w - - 0 1".to_string());
+        let promoted = GameState::from("4k3/8/8/8/8/8/8/Q~3K3[] w - - 0 1".to_string());
+        assert_ne!(queen.hash(), promoted.hash());
+    }
+
+    #[test]
+    fn distance_scores() {
+        // Found 5 plies from the root, then reached again at 8
+        for score in [MATE - 7, TB_WIN - 5, TB_WIN - 120] {
+            let stored = score_to_tt(score, 5);
+            assert_eq!(score_from_tt(stored, 8), score - 3);
+            assert_eq!(score_from_tt(-stored, 8), 3 - score);
+        }
+        assert_eq!(score_from_tt(score_to_tt(250, 5), 8), 250);
+    }
+
+    #[test]
+    fn store_and_probe() {
+        let game = GameState::from(START_FEN.to_string());
+        let best = game.parse_san("e4").unwrap();
+        let tt = TranspositionTable::new(1);
+        tt.store(
+            game.hash(),
+            5,
+            Bound::Lower,
+            MATE_BOUND + 10,
+            Some(&best),
+            3,
+        );
+
+        let entry = tt.probe(game.hash()).unwrap();
+        assert_eq!(entry.depth, 5);
+        assert_eq!(entry.bound, Bound::Lower);
+        assert_eq!(score_from_tt(entry.score, 3), MATE_BOUND + 10);
+        assert_eq!(game.unpack(entry.best), Some(best));
+        assert!(tt.probe(game.hash() ^ 1).is_none());
+
+        // The smallest table is a single bucket
+        let tt = TranspositionTable::new(0);
+        tt.store(game.hash(), 1, Bound::Exact, 0, None, 0);
+        assert_eq!(tt.hashfull(), 250);
+    }
+}
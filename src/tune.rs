@@ -0,0 +1,314 @@
+use crate::board::*;
+use crate::eval::*;
+use crate::fen::FenError;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+
+const BETA1: f64 = 0.9;
+const BETA2: f64 = 0.999;
+
+#[derive(Debug)]
+pub enum TuneError {
+    Io(std::io::Error),
+    Fen {
+        line: usize,
+        error: FenError,
+    },
+    /// A dataset line without a game result at its end
+    Result {
+        line: usize,
+    },
+    Weights {
+        line: usize,
+    },
+}
+
+impl std::fmt::Display for TuneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TuneError::Io(e) => write!(f, "Read failed: {e}"),
+            TuneError::Fen { line, error } => write!(f, "{error} at line {line}"),
+            TuneError::Result { line } => write!(f, "Missing game result at line {line}"),
+            TuneError::Weights { line } => write!(f, "Malformed weight at line {line}"),
+        }
+    }
+}
+
+impl std::error::Error for TuneError {}
+
+impl From<std::io::Error> for TuneError {
+    fn from(e: std::io::Error) -> Self {
+        TuneError::Io(e)
+    }
+}
+
+/// A position reduced to the evaluation terms it uses, with White's result
+pub struct Sample {
+    /// Parameter and how many more times it applies to White than Black
+    terms: Vec<(usize, i32)>,
+    /// Middlegame share of the tapered score
+    phase: f64,
+    result: f64,
+}
+
+impl Sample {
+    pub fn new(game: &GameState, result: f64) -> Self {
+        let trace = Trace::new(game);
+        let [white, black] = trace.counts;
+        let terms = (0..PARAM_COUNT)
+            .map(|param| (param, white[param] - black[param]))
+            .filter(|&(_, count)| count != 0)
+            .collect();
+        Sample {
+            terms,
+            phase: trace.phase as f64 / MAX_PHASE as f64,
+            result,
+        }
+    }
+
+    fn eval(&self, weights: &[[f64; 2]]) -> f64 {
+        let (mut mg, mut eg) = (0.0, 0.0);
+        for &(param, count) in &self.terms {
+            mg += weights[param][0] * count as f64;
+            eg += weights[param][1] * count as f64;
+        }
+        mg * self.phase + eg * (1.0 - self.phase)
+    }
+}
+
+/// White's score for a result written as `1-0`, `[0.5]`, `"1/2-1/2"` and so on
+pub fn parse_result(token: &str) -> Option<f64> {
+    let token = token.trim_matches(|c| matches!(c, '[' | ']' | '"' | ';'));
+    match token {
+        "1-0" | "1.0" => Some(1.0),
+        "0-1" | "0.0" => Some(0.0),
+        "1/2-1/2" | "0.5" => Some(0.5),
+        _ => None,
+    }
+}
+
+/// Reads one position per line, a FEN followed by the game's result, or the
+/// `fen | score | result` lines of the data generator. Lines that are empty
+/// or start with `#` are skipped.
+pub fn read_dataset<R: BufRead>(reader: R) -> Result<Vec<Sample>, TuneError> {
+    let mut samples = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Generated data is `fen | score | result`, where only the ends matter here
+        let (fen, result) = match line.split_once('|') {
+            Some((fen, rest)) => (fen, rest.rsplit('|').next().unwrap_or(rest).trim()),
+            None => line.rsplit_once(char::is_whitespace).unwrap_or(("", line)),
+        };
+        let result = parse_result(result).ok_or(TuneError::Result { line: i + 1 })?;
+        let game = GameState::from_fen(fen.trim_end().trim_end_matches(';'))
+            .map_err(|error| TuneError::Fen { line: i + 1, error })?;
+        samples.push(Sample::new(&game, result));
+    }
+    Ok(samples)
+}
+
+fn sigmoid(k: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + (-k * eval).exp())
+}
+
+/// Fits the evaluation weights to game results by minimising the mean squared
+/// difference between each result and the win probability the evaluation
+/// predicts, using Adam gradient descent
+pub struct Tuner {
+    samples: Vec<Sample>,
+    weights: Vec<[f64; 2]>,
+    /// Scales centipawns to the logistic curve
+    k: f64,
+    moments: Vec<[f64; 2]>,
+    velocities: Vec<[f64; 2]>,
+    steps: i32,
+}
+
+impl Tuner {
+    pub fn new(samples: Vec<Sample>, weights: &Weights) -> Self {
+        Tuner {
+            samples,
+            weights: weights.iter().map(|w| [w.mg as f64, w.eg as f64]).collect(),
+            k: 1.0 / 150.0,
+            moments: vec![[0.0; 2]; PARAM_COUNT],
+            velocities: vec![[0.0; 2]; PARAM_COUNT],
+            steps: 0,
+        }
+    }
+
+    pub fn k(&self) -> f64 {
+        self.k
+    }
+
+    fn error_with(&self, k: f64) -> f64 {
+        let total: f64 = self
+            .samples
+            .iter()
+            .map(|s| (s.result - sigmoid(k, s.eval(&self.weights))).powi(2))
+            .sum();
+        total / self.samples.len().max(1) as f64
+    }
+
+    pub fn error(&self) -> f64 {
+        self.error_with(self.k)
+    }
+
+    /// Picks the scaling that best fits the current weights, so tuning only
+    /// changes the weights and not the overall scale of the evaluation
+    pub fn find_k(&mut self) {
+        let (mut low, mut high) = (0.0001, 0.05);
+        for _ in 0..60 {
+            let a = low + (high - low) / 3.0;
+            let b = high - (high - low) / 3.0;
+            if self.error_with(a) < self.error_with(b) {
+                high = b;
+            } else {
+                low = a;
+            }
+        }
+        self.k = (low + high) / 2.0;
+    }
+
+    /// One Adam step over the whole dataset, returning the error before it
+    pub fn epoch(&mut self, rate: f64) -> f64 {
+        let mut gradient = vec![[0.0; 2]; PARAM_COUNT];
+        let mut error = 0.0;
+        for sample in &self.samples {
+            let p = sigmoid(self.k, sample.eval(&self.weights));
+            error += (sample.result - p).powi(2);
+            let slope = -2.0 * (sample.result - p) * p * (1.0 - p) * self.k;
+            for &(param, count) in &sample.terms {
+                gradient[param][0] += slope * count as f64 * sample.phase;
+                gradient[param][1] += slope * count as f64 * (1.0 - sample.phase);
+            }
+        }
+
+        let n = self.samples.len().max(1) as f64;
+        self.steps += 1;
+        let params = self
+            .weights
+            .iter_mut()
+            .flatten()
+            .zip(self.moments.iter_mut().flatten())
+            .zip(self.velocities.iter_mut().flatten())
+            .zip(gradient.iter().flatten());
+        for (((weight, m), v), g) in params {
+            let g = g / n;
+            *m = BETA1 * *m + (1.0 - BETA1) * g;
+            *v = BETA2 * *v + (1.0 - BETA2) * g * g;
+            let m = *m / (1.0 - BETA1.powi(self.steps));
+            let v = *v / (1.0 - BETA2.powi(self.steps));
+            *weight -= rate * m / (v.sqrt() + 1e-8);
+        }
+        error / n
+    }
+
+    pub fn weights(&self) -> Weights {
+        let mut weights = [s(0, 0); PARAM_COUNT];
+        for (w, [mg, eg]) in weights.iter_mut().zip(&self.weights) {
+            *w = s(mg.round() as i32, eg.round() as i32);
+        }
+        weights
+    }
+}
+
+/// Writes one `mg eg` pair per line, in parameter order
+pub fn save_weights<W: Write>(weights: &Weights, mut writer: W) -> std::io::Result<()> {
+    for w in weights {
+        writeln!(writer, "{} {}", w.mg, w.eg)?;
+    }
+    Ok(())
+}
+
+pub fn load_weights<R: BufRead>(reader: R) -> Result<Weights, TuneError> {
+    let mut weights = [s(0, 0); PARAM_COUNT];
+    let mut count = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut halves = line.split_whitespace().map(str::parse::<i32>);
+        let (Some(Ok(mg)), Some(Ok(eg)), None) = (halves.next(), halves.next(), halves.next())
+        else {
+            return Err(TuneError::Weights { line: i + 1 });
+        };
+        if count == PARAM_COUNT {
+            return Err(TuneError::Weights { line: i + 1 });
+        }
+        weights[count] = s(mg, eg);
+        count += 1;
+    }
+    if count < PARAM_COUNT {
+        return Err(TuneError::Weights { line: count + 1 });
+    }
+    Ok(weights)
+}
+
+/// The weights as a Rust constant, to paste over `DEFAULT_WEIGHTS`
+pub fn rust_source(weights: &Weights) -> String {
+    let groups = [
+        (MATERIAL, "Material"),
+        (PSQT, "Piece squares"),
+        (MOBILITY, "Mobility"),
+        (DOUBLED, "Doubled, isolated and backward pawns"),
+        (PASSED, "Passed pawns"),
+        (SHIELD, "Pawn shield"),
+        (KING_ATTACK, "King attackers"),
+        (
+            BISHOP_PAIR,
+            "Bishop pair, Rooks on open and semi-open files",
+        ),
+    ];
+    let mut out = String::from("pub const DEFAULT_WEIGHTS: Weights = [\n");
+    for (param, w) in weights.iter().enumerate() {
+        if let Some((_, name)) = groups.iter().find(|(start, _)| *start == param) {
+            writeln!(out, "    // {name}").unwrap();
+        }
+        writeln!(out, "    s({}, {}),", w.mg, w.eg).unwrap();
+    }
+    out.push_str("];\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dataset_and_weights() {
+        let data = "\
+# White is a Pawn up in each
+4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 [1.0]
+4k3/8/8/8/8/4P3/8/4K3 b - - 0 1; 1-0
+4k3/8/8/8/8/8/P7/4K3 w - - 0 1 \"1/2-1/2\"
+4k3/8/8/8/8/8/3P4/4K3 b - - 0 1 | 95 | 0.0
+";
+        let samples = read_dataset(data.as_bytes()).unwrap();
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples[1].result, 1.0);
+        assert_eq!(samples[2].result, 0.5);
+        assert_eq!(samples[3].result, 0.0);
+        assert!(matches!(
+            read_dataset("4k3/8/8/8/8/8/8/4K3 w - - 0 1".as_bytes()),
+            Err(TuneError::Result { line: 1 })
+        ));
+
+        let mut tuner = Tuner::new(samples, &DEFAULT_WEIGHTS);
+        tuner.find_k();
+        let before = tuner.error();
+        for _ in 0..50 {
+            tuner.epoch(1.0);
+        }
+        assert!(tuner.error() < before);
+
+        let mut file = vec![];
+        save_weights(&tuner.weights(), &mut file).unwrap();
+        assert_eq!(load_weights(file.as_slice()).unwrap(), tuner.weights());
+        assert!(rust_source(&DEFAULT_WEIGHTS).contains("    s(100, 120),"));
+    }
+}
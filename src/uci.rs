@@ -0,0 +1,326 @@
+use crate::board::*;
+use crate::engine::{send, Engine, GoMode, Output, MAX_HASH_MB, MAX_MULTIPV, MAX_THREADS};
+use crate::eval::Trace;
+use crate::fen::START_FEN;
+use crate::moves::Move;
+use crate::nnue::Evaluator;
+use crate::search::{mate_in, Limits, SearchResult, DEFAULT_HASH_MB};
+use crate::timeman::TimeControl;
+use std::time::Duration;
+
+/// UCI notation of a line of moves played from `game`
+fn line(game: &GameState, pv: &[Move]) -> String {
+    let mut game = game.clone();
+    let mut moves = vec![];
+    for mov in pv {
+        moves.push(game.uci(mov));
+        game = game.apply(mov.clone());
+    }
+    moves.join(" ")
+}
+
+fn score(score: i32) -> String {
+    match mate_in(score) {
+        Some(moves) => format!("mate {moves}"),
+        None => format!("cp {score}"),
+    }
+}
+
+/// One `info` line for each principal variation of an iteration
+pub fn info(game: &GameState, result: &SearchResult) -> Vec<String> {
+    let ms = result.time.as_millis();
+    let nps = result.nodes as u128 * 1000 / ms.max(1);
+    result
+        .lines
+        .iter()
+        .enumerate()
+        .map(|(i, l)| {
+            format!(
+                "info depth {} multipv {} score {} nodes {} nps {} hashfull {} tbhits {} time {} pv {}",
+                result.depth,
+                i + 1,
+                score(l.score),
+                result.nodes,
+                nps,
+                result.hashfull,
+                result.tbhits,
+                ms,
+                line(game, &l.pv)
+            )
+        })
+        .collect()
+}
+
+fn bestmove(game: &GameState, result: &SearchResult) -> String {
+    match &result.best {
+        Some(best) => {
+            let mut msg = format!("bestmove {}", game.uci(best));
+            if let Some(reply) = result.pv.get(1) {
+                let next = game.apply(best.clone());
+                msg += &format!(" ponder {}", next.uci(reply));
+            }
+            msg
+        }
+        None => String::from("bestmove 0000"),
+    }
+}
+
+/// Universal Chess Interface front-end, reading one command at a time while
+/// the engine searches on its own thread
+pub struct Uci {
+    engine: Engine,
+    out: Output,
+}
+
+impl Uci {
+    pub fn new(out: Output) -> Self {
+        Uci {
+            engine: Engine::new(),
+            out,
+        }
+    }
+
+    fn send(&self, msg: &str) {
+        send(&self.out, msg);
+    }
+
+    /// Handles one line from the GUI, returning false once it asks to quit
+    pub fn handle(&mut self, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("uci") => {
+                self.send(concat!("id name rustle ", env!("CARGO_PKG_VERSION")));
+                self.send("id author the rustle developers");
+                self.send(&format!(
+                    "option name Hash type spin default {DEFAULT_HASH_MB} min 1 max {MAX_HASH_MB}"
+                ));
+                self.send(&format!(
+                    "option name Threads type spin default 1 min 1 max {MAX_THREADS}"
+                ));
+                self.send(&format!(
+                    "option name MultiPV type spin default 1 min 1 max {MAX_MULTIPV}"
+                ));
+                self.send("option name Ponder type check default false");
+                self.send("option name SyzygyPath type string default <empty>");
+                self.send("option name EvalFile type string default <empty>");
+                self.send("uciok");
+            }
+            Some("isready") => self.send("readyok"),
+            Some("ucinewgame") => self.engine.new_game(),
+            Some("setoption") => self.set_option(tokens.collect()),
+            Some("position") => self.position(tokens.collect()),
+            Some("go") => self.go(tokens.collect()),
+            Some("stop") => self.engine.stop(),
+            Some("ponderhit") => self.engine.ponderhit(),
+            // Not part of UCI, but handy for checking the evaluation by hand
+            Some("eval") => {
+                for line in Trace::new(&self.engine.game).to_string().lines() {
+                    self.send(line);
+                }
+            }
+            Some("quit") => {
+                self.engine.stop();
+                return false;
+            }
+            Some(command) => self.send(&format!("info string Unknown command {command}")),
+            None => {}
+        }
+        true
+    }
+
+    fn set_option(&mut self, tokens: Vec<&str>) {
+        let value = tokens.iter().position(|&t| t == "value");
+        let name = tokens[..value.unwrap_or(tokens.len())]
+            .iter()
+            .skip_while(|&&t| t != "name")
+            .skip(1)
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let value = value.map(|i| tokens[i + 1..].join(" "));
+        let number = value.as_deref().and_then(|v| v.parse::<usize>().ok());
+        match (name.to_lowercase().as_str(), number) {
+            ("hash", Some(mb)) => self.engine.set_hash(mb),
+            ("threads", Some(threads)) => self.engine.set_threads(threads),
+            ("multipv", Some(lines)) => self.engine.set_multipv(lines),
+            // The GUI decides when to ponder, so this only tells it that we can
+            ("ponder", _) => {}
+            ("syzygypath", _) => {
+                match self
+                    .engine
+                    .set_tablebases(value.as_deref().unwrap_or_default())
+                {
+                    Ok(found) => self.send(&format!("info string Found {found} tablebases")),
+                    Err(e) => self.send(&format!("info string Tablebases not found: {e}")),
+                }
+            }
+            ("evalfile", _) => {
+                let path = value.as_deref().unwrap_or_default();
+                match self.engine.set_eval_file(path) {
+                    Ok(()) => match self.engine.search().evaluator {
+                        Evaluator::Classical => self.send("info string Classical evaluation"),
+                        Evaluator::Nnue(_) => self.send(&format!("info string Network {path}")),
+                    },
+                    Err(e) => self.send(&format!("info string {e}")),
+                }
+            }
+            _ => self.send(&format!("info string Unknown option {name}")),
+        }
+    }
+
+    fn position(&mut self, tokens: Vec<&str>) {
+        let moves = tokens.iter().position(|&t| t == "moves");
+        let setup = &tokens[..moves.unwrap_or(tokens.len())];
+        let fen = match setup.split_first() {
+            Some((&"startpos", _)) => START_FEN.to_string(),
+            Some((&"fen", fields)) => fields.join(" "),
+            _ => return self.send("info string Expected startpos or fen"),
+        };
+        match GameState::from_fen(&fen) {
+            Ok(game) => self.engine.set_position(game),
+            Err(e) => return self.send(&format!("info string {e}")),
+        }
+        for mov in moves.map_or(&[][..], |i| &tokens[i + 1..]) {
+            if !self.engine.play(mov) {
+                return self.send(&format!("info string Illegal move {mov}"));
+            }
+        }
+    }
+
+    fn go(&mut self, tokens: Vec<&str>) {
+        let mut limits = Limits::default();
+        let mut mode = GoMode::Normal;
+        let (mut time, mut inc) = ([None; 2], [Duration::ZERO; 2]);
+        let mut moves_to_go = None;
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            // Clocks can run below zero when the GUI allows overstepping
+            let mut value = || {
+                tokens
+                    .next()
+                    .and_then(|v| v.parse::<i64>().ok())
+                    .map(|v| v.max(0) as u64)
+            };
+            let ms = |v: Option<u64>| v.map(Duration::from_millis);
+            match token {
+                "depth" => limits.depth = value().map(|d| d.min(u8::MAX as u64) as u8),
+                "nodes" => limits.nodes = value(),
+                "movetime" => limits.clock = ms(value()).map(TimeControl::MoveTime),
+                "wtime" => time[Sides::White as usize] = ms(value()),
+                "btime" => time[Sides::Black as usize] = ms(value()),
+                "winc" => inc[Sides::White as usize] = ms(value()).unwrap_or_default(),
+                "binc" => inc[Sides::Black as usize] = ms(value()).unwrap_or_default(),
+                "movestogo" => moves_to_go = value().map(|m| m as u32),
+                "infinite" => mode = GoMode::Infinite,
+                "ponder" => mode = GoMode::Ponder,
+                _ => {}
+            }
+        }
+        let side = self.engine.game.turn as usize;
+        if let (None, Some(remaining)) = (limits.clock, time[side]) {
+            limits.clock = Some(TimeControl::Clock {
+                remaining,
+                increment: inc[side],
+                moves_to_go,
+            });
+        }
+        // A bare go searches until stopped
+        if limits.depth.is_none() && limits.nodes.is_none() && limits.clock.is_none() {
+            mode = GoMode::Infinite;
+        }
+
+        let game = self.engine.game.clone();
+        let (out, done) = (self.out.clone(), self.out.clone());
+        let report = Box::new(move |result: &SearchResult| {
+            for line in info(&game, result) {
+                send(&out, &line);
+            }
+        });
+        let game = self.engine.game.clone();
+        self.engine.go(limits, mode, report, move |result| {
+            send(&done, &bestmove(&game, &result));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::testing::Buffer;
+
+    fn uci() -> (Uci, Buffer) {
+        let buffer = Buffer::default();
+        (Uci::new(buffer.output()), buffer)
+    }
+
+    #[test]
+    fn handshake_and_options() {
+        let (mut uci, buffer) = uci();
+        uci.handle("uci");
+        let reply = buffer.take();
+        assert!(reply.contains("option name MultiPV type spin default 1"));
+        assert!(reply.ends_with("uciok\n"));
+
+        uci.handle("setoption name SyzygyPath value /nonexistent/syzygy");
+        assert!(buffer
+            .take()
+            .starts_with("info string Tablebases not found"));
+        uci.handle("setoption name SyzygyPath value <empty>");
+        assert!(buffer.take().contains("Found 0 tablebases"));
+        uci.handle("setoption name EvalFile value /nonexistent/net.nnue");
+        assert!(buffer.take().starts_with("info string Network read failed"));
+        uci.handle("setoption name EvalFile value <empty>");
+        assert!(buffer.take().contains("Classical evaluation"));
+        assert!(!uci.handle("quit"));
+    }
+
+    #[test]
+    fn position_and_go() {
+        let (mut uci, buffer) = uci();
+        uci.handle("setoption name MultiPV value 2");
+        uci.handle("setoption name Hash value 4");
+        uci.handle("ucinewgame");
+        uci.handle("position startpos moves e2e4 e7e5 g1f3");
+        uci.handle("go depth 3");
+        uci.engine.wait();
+        let reply = buffer.take();
+        assert!(reply.contains("info depth 3 multipv 2 score cp"));
+        let last = reply.lines().last().unwrap();
+        assert!(last.starts_with("bestmove ") && last.contains(" ponder "));
+
+        uci.handle("position fen 4k3/6p1/8/3P1P1p/8/P7/P7/4K3 w - - 0 1");
+        uci.handle("eval");
+        let reply = buffer.take();
+        assert!(reply.lines().any(|l| l.starts_with("Isolated pawns")));
+        assert!(reply.ends_with("from White's side\n"));
+
+        uci.handle("position startpos moves e2e5");
+        assert!(buffer.take().contains("Illegal move e2e5"));
+        uci.handle("go wtime 2000 btime 2000 winc 10 binc 10");
+        uci.engine.wait();
+        assert!(buffer.take().contains("bestmove"));
+    }
+
+    #[test]
+    fn stop_and_ponderhit() {
+        let (mut uci, buffer) = uci();
+        // Infinite searches only answer when stopped
+        uci.handle("position fen 7k/6pp/8/3q4/8/8/3R4/3RK3 w - - 0 1");
+        uci.handle("go infinite");
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(!buffer.take().contains("bestmove"));
+        uci.handle("stop");
+        assert!(buffer.take().contains("bestmove d2d5"));
+
+        // A ponder search waits for the hit, then only has its own time left
+        uci.handle("position startpos moves e2e4 e7e5");
+        uci.handle("go ponder wtime 100 btime 100");
+        // However long it takes, it keeps pondering until the hit
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(uci.engine.is_searching());
+        assert!(!buffer.take().contains("bestmove"));
+        uci.handle("ponderhit");
+        uci.engine.wait();
+        assert!(buffer.take().contains("bestmove"));
+    }
+}
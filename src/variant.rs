@@ -0,0 +1,519 @@
+use crate::board::*;
+use crate::moves::{Move, KING_DIRS};
+use crate::outcome::Outcome;
+
+/// Rule set a game is played under. Variants share the standard move generator
+/// and hook into it through the `GameState` methods below.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    #[default]
+    Standard,
+    Crazyhouse,
+    Atomic,
+    KingOfTheHill,
+    ThreeCheck,
+    Antichess,
+    Horde,
+    RacingKings,
+}
+
+pub const VARIANTS: [Variant; 8] = [
+    Variant::Standard,
+    Variant::Crazyhouse,
+    Variant::Atomic,
+    Variant::KingOfTheHill,
+    Variant::ThreeCheck,
+    Variant::Antichess,
+    Variant::Horde,
+    Variant::RacingKings,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionError {
+    Overlap,
+    Kings,
+    PawnRank,
+    OppositeCheck,
+    EnPassant,
+    Variant,
+}
+
+impl std::fmt::Display for PositionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            PositionError::Overlap => "Two pieces share a square",
+            PositionError::Kings => "Wrong number of Kings",
+            PositionError::PawnRank => "Pawn on an unreachable rank",
+            PositionError::OppositeCheck => "Side not to move is in check",
+            PositionError::EnPassant => "Invalid en passant square",
+            PositionError::Variant => "Position not allowed by the variant",
+        };
+        write!(f, "{msg}")
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+const HILL: [Square; 4] = [Square::D4, Square::E4, Square::D5, Square::E5];
+
+impl Variant {
+    /// Name used by the PGN `Variant` tag
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::Standard => "Standard",
+            Variant::Crazyhouse => "Crazyhouse",
+            Variant::Atomic => "Atomic",
+            Variant::KingOfTheHill => "King of the Hill",
+            Variant::ThreeCheck => "Three-check",
+            Variant::Antichess => "Antichess",
+            Variant::Horde => "Horde",
+            Variant::RacingKings => "Racing Kings",
+        }
+    }
+
+    pub fn start_fen(&self) -> &'static str {
+        match self {
+            Variant::Crazyhouse => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1",
+            Variant::ThreeCheck => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3+3 0 1",
+            Variant::Antichess => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+            Variant::Horde => {
+                "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1"
+            }
+            Variant::RacingKings => "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1",
+            _ => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        }
+    }
+
+    /// Looks up a PGN `Variant` tag, where "From Position" is standard chess
+    /// from a custom setup
+    pub fn from_name(name: &str) -> Option<Variant> {
+        if name.eq_ignore_ascii_case("From Position") {
+            return Some(Variant::Standard);
+        }
+        VARIANTS
+            .into_iter()
+            .find(|v| v.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl GameState {
+    /// Starting position of `variant`
+    pub fn start(variant: Variant) -> GameState {
+        let mut game = GameState::from(variant.start_fen().to_string());
+        game.variant = variant;
+        game
+    }
+
+    /// Checks that the position could be reached under the variant's rules,
+    /// so it can be used as the start of a game
+    pub fn validate(&self) -> Result<(), PositionError> {
+        let boards: Vec<u64> = self.state.iter().flatten().map(|b| b.0).collect();
+        let union = boards.iter().fold(0, |acc, b| acc | b);
+        if boards.iter().map(|b| b.count_ones()).sum::<u32>() != union.count_ones() {
+            return Err(PositionError::Overlap);
+        }
+
+        for side in [Sides::White, Sides::Black] {
+            let kings = self.board(side, Piece::King).0.count_ones();
+            let expected = match (self.variant, side) {
+                (Variant::Antichess, _) => kings,
+                (Variant::Horde, Sides::White) => 0,
+                _ => 1,
+            };
+            if kings != expected {
+                return Err(PositionError::Kings);
+            }
+        }
+
+        let pawns = |side| self.board(side, Piece::Pawn);
+        for square in SQUARES {
+            let white_ok = match square.rank() {
+                0 => self.variant == Variant::Horde,
+                7 => false,
+                _ => true,
+            };
+            let black_ok = !matches!(square.rank(), 0 | 7);
+            if (pawns(Sides::White).get(square) && !white_ok)
+                || (pawns(Sides::Black).get(square) && !black_ok)
+            {
+                return Err(PositionError::PawnRank);
+            }
+        }
+
+        if self.variant == Variant::RacingKings
+            && (pawns(Sides::White).0 | pawns(Sides::Black).0 != 0 || self.in_check())
+        {
+            return Err(PositionError::Variant);
+        }
+
+        let opp = self.turn.switch();
+        if !self.king_safe(opp) && self.variant_end().is_none() {
+            return Err(PositionError::OppositeCheck);
+        }
+
+        if let Some(ep) = self.en_passant {
+            // The Pawn that just double stepped must stand in front of the square
+            let (rank, pushed) = match self.turn {
+                Sides::White => (5, ep.step(Direction::S)),
+                Sides::Black => (2, ep.step(Direction::N)),
+            };
+            let horde_first_rank = self.variant == Variant::Horde && ep.rank() == 1;
+            let valid = (ep.rank() == rank || horde_first_rank)
+                && !BitBoard(union).get(ep)
+                && pushed.is_some_and(|sq| pawns(opp).get(sq));
+            if !valid {
+                return Err(PositionError::EnPassant);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `side`'s King is safe from capture after a move, which is what
+    /// separates legal from pseudo-legal moves
+    pub fn king_safe(&self, side: Sides) -> bool {
+        let opp = side.switch();
+        match self.variant {
+            // The King is an ordinary piece and may be captured
+            Variant::Antichess => true,
+            Variant::Atomic => {
+                let Some(king) = self.king_square(side) else {
+                    return false;
+                };
+                // Exploding the enemy King wins outright, and Kings that touch
+                // can never capture each other
+                match self.king_square(opp) {
+                    None => true,
+                    Some(theirs) => {
+                        KING_DIRS.into_iter().any(|d| king.step(d) == Some(theirs))
+                            || !self.attacked(king, opp)
+                    }
+                }
+            }
+            _ => match self.king_square(side) {
+                Some(king) => !self.attacked(king, opp),
+                None => true,
+            },
+        }
+    }
+
+    /// Variant rules applied once the standard move has been made on `self`
+    pub(crate) fn after_move(&mut self, before: &GameState, mov: &Move) {
+        match self.variant {
+            Variant::Atomic if mov.capture.is_some() => {
+                // The capture explodes the capturing piece along with every
+                // piece but Pawns on the surrounding squares
+                let center = mov.to;
+                for square in SQUARES {
+                    let near = square == center
+                        || KING_DIRS
+                            .into_iter()
+                            .any(|d| center.step(d) == Some(square));
+                    if !near {
+                        continue;
+                    }
+                    for side in [Sides::White, Sides::Black] {
+                        for piece in PIECES {
+                            let board = self.board_mut(side, piece);
+                            if board.get(square) && (square == center || piece != Piece::Pawn) {
+                                board.flip(square);
+                            }
+                        }
+                    }
+                }
+                for side in [Sides::White, Sides::Black] {
+                    if self.king_square(side).is_none() {
+                        self.castle_rights &= match side {
+                            Sides::White => Castle::BlackKing as u8 | Castle::BlackQueen as u8,
+                            Sides::Black => Castle::WhiteKing as u8 | Castle::WhiteQueen as u8,
+                        };
+                    }
+                }
+                self.clear_lost_castle_rights();
+            }
+            Variant::ThreeCheck if self.in_check() => {
+                self.checks[before.turn as usize] += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Restricts the legal moves further, for variants that forbid moves
+    /// the standard rules allow
+    pub(crate) fn filter_variant_moves(&self, moves: Vec<Move>) -> Vec<Move> {
+        match self.variant {
+            Variant::Atomic => moves
+                .into_iter()
+                .filter(|m| !(m.piece == Piece::King && m.capture.is_some()))
+                .collect(),
+            // Captures are compulsory and there is no castling
+            Variant::Antichess => {
+                let forced = moves.iter().any(|m| m.capture.is_some());
+                moves
+                    .into_iter()
+                    .filter(|m| m.castle.is_none() && (m.capture.is_some() || !forced))
+                    .collect()
+            }
+            // Giving check is not allowed
+            Variant::RacingKings => moves
+                .into_iter()
+                .filter(|m| !self.apply(m.clone()).in_check())
+                .collect(),
+            _ => moves,
+        }
+    }
+
+    /// Game endings decided by the position alone, before looking at the moves
+    pub fn variant_end(&self) -> Option<Outcome> {
+        match self.variant {
+            Variant::Atomic => [Sides::White, Sides::Black]
+                .into_iter()
+                .find(|&side| self.king_square(side).is_none())
+                .map(|side| Outcome::Variant(Some(side.switch()))),
+            Variant::KingOfTheHill => [Sides::White, Sides::Black]
+                .into_iter()
+                .find(|&side| HILL.iter().any(|&sq| self.board(side, Piece::King).get(sq)))
+                .map(|side| Outcome::Variant(Some(side))),
+            Variant::ThreeCheck => [Sides::White, Sides::Black]
+                .into_iter()
+                .find(|&side| self.checks[side as usize] >= 3)
+                .map(|side| Outcome::Variant(Some(side))),
+            Variant::Horde => {
+                let white = PIECES
+                    .into_iter()
+                    .any(|p| self.board(Sides::White, p).0 != 0);
+                (!white).then_some(Outcome::Variant(Some(Sides::Black)))
+            }
+            Variant::RacingKings => {
+                let home = |side| {
+                    self.king_square(side)
+                        .is_some_and(|king: Square| king.rank() == 7)
+                };
+                match (home(Sides::White), home(Sides::Black)) {
+                    (true, true) => Some(Outcome::Variant(None)),
+                    (false, true) => Some(Outcome::Variant(Some(Sides::Black))),
+                    // Black gets one last move to draw by reaching the rank too
+                    (true, false) if self.turn == Sides::Black && self.can_race_home() => None,
+                    (true, false) => Some(Outcome::Variant(Some(Sides::White))),
+                    (false, false) => None,
+                }
+            }
+            Variant::Standard | Variant::Crazyhouse | Variant::Antichess => None,
+        }
+    }
+
+    /// Whether the side to move has a legal King move onto the eighth rank
+    fn can_race_home(&self) -> bool {
+        self.pseudo_moves().into_iter().any(|m| {
+            m.piece == Piece::King
+                && m.to.rank() == 7
+                && self.is_legal(&m)
+                && !self.apply(m.clone()).in_check()
+        })
+    }
+
+    /// Ending when the side to move has no legal moves
+    pub(crate) fn no_moves_outcome(&self) -> Outcome {
+        match self.variant {
+            // Running out of pieces or moves wins
+            Variant::Antichess => Outcome::Variant(Some(self.turn)),
+            _ if self.in_check() => Outcome::Checkmate(self.turn.switch()),
+            _ => Outcome::Stalemate,
+        }
+    }
+
+    /// Whether neither side can possibly win under the variant's rules
+    pub fn insufficient_material(&self) -> bool {
+        let count = |side: Sides, piece: Piece| self.board(side, piece).0.count_ones();
+        let only_kings = |side: Sides| {
+            PIECES
+                .into_iter()
+                .all(|p| p == Piece::King || count(side, p) == 0)
+        };
+        match self.variant {
+            Variant::Crazyhouse | Variant::KingOfTheHill => false,
+            Variant::Antichess | Variant::Horde | Variant::RacingKings => false,
+            Variant::Atomic | Variant::ThreeCheck => {
+                only_kings(Sides::White) && only_kings(Sides::Black)
+            }
+            Variant::Standard => {
+                let heavy = [Piece::Pawn, Piece::Rook, Piece::Queen];
+                if [Sides::White, Sides::Black]
+                    .into_iter()
+                    .any(|side| heavy.into_iter().any(|p| count(side, p) > 0))
+                {
+                    return false;
+                }
+                let knights =
+                    count(Sides::White, Piece::Knight) + count(Sides::Black, Piece::Knight);
+                let bishops = self.board(Sides::White, Piece::Bishop).0
+                    | self.board(Sides::Black, Piece::Bishop).0;
+                let light = SQUARES
+                    .into_iter()
+                    .filter(|sq| (sq.file() + sq.rank()) % 2 == 1)
+                    .fold(0u64, |acc, sq| acc | 1u64 << sq as u8);
+                // Bare Kings, a single minor piece, or Bishops all on one colour
+                match knights {
+                    0 => bishops & light == 0 || bishops & !light == 0,
+                    1 => bishops == 0,
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(fen: &str, variant: Variant, sans: &[&str]) -> GameState {
+        let mut game = GameState::from(fen.to_string());
+        game.variant = variant;
+        for san in sans {
+            let mov = game.parse_san(san).unwrap_or_else(|| panic!("{san}"));
+            game = game.apply(mov);
+        }
+        game
+    }
+
+    #[test]
+    fn standard_outcomes() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let mate = play(start, Variant::Standard, &["f3", "e5", "g4", "Qh4#"]);
+        assert_eq!(mate.outcome(), Some(Outcome::Checkmate(Sides::Black)));
+
+        let stalemate = play("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", Variant::Standard, &[]);
+        assert_eq!(stalemate.outcome(), Some(Outcome::Stalemate));
+
+        let bishops = play("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1", Variant::Standard, &[]);
+        assert_eq!(bishops.outcome(), Some(Outcome::InsufficientMaterial));
+    }
+
+    #[test]
+    fn atomic_explosions() {
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/8/5N2/PPPPPPPP/RNBQKB1R w KQkq - 0 2";
+        let game = play(fen, Variant::Atomic, &["Nxe5"]);
+        assert_eq!(
+            game.fen(),
+            "rnbqkbnr/pppp1ppp/8/8/8/8/PPPPPPPP/RNBQKB1R b KQkq - 0 2"
+        );
+
+        // Capturing next to the enemy King explodes it and wins
+        let fen = "4k3/4p3/8/8/8/8/8/4QK2 w - - 0 1";
+        let game = play(fen, Variant::Atomic, &["Qxe7"]);
+        assert_eq!(game.outcome(), Some(Outcome::Variant(Some(Sides::White))));
+
+        // Touching Kings are never in check
+        let game = play("8/8/8/3kK3/8/8/8/7r w - - 0 1", Variant::Atomic, &[]);
+        assert!(!game.in_check());
+        assert!(game.moves().iter().all(|m| m.piece == Piece::King));
+    }
+
+    #[test]
+    fn king_of_the_hill() {
+        let game = play(
+            "8/8/8/8/8/3K4/8/7k w - - 0 1",
+            Variant::KingOfTheHill,
+            &["Ke4"],
+        );
+        assert_eq!(game.outcome(), Some(Outcome::Variant(Some(Sides::White))));
+        assert!(game.moves().is_empty());
+    }
+
+    #[test]
+    fn antichess() {
+        let game = play(
+            Variant::Antichess.start_fen(),
+            Variant::Antichess,
+            &["e4", "d5"],
+        );
+        assert_eq!(game.moves().len(), 1);
+        assert!(!game.in_check());
+
+        let game = play(
+            "8/1P6/8/8/8/8/8/7k w - - 0 1",
+            Variant::Antichess,
+            &["b8=K"],
+        );
+        assert!(game.board(Sides::White, Piece::King).get(Square::B8));
+
+        let game = play(
+            "8/8/8/8/8/8/1p6/B7 b - - 0 1",
+            Variant::Antichess,
+            &["bxa1=Q"],
+        );
+        assert_eq!(game.outcome(), Some(Outcome::Variant(Some(Sides::White))));
+    }
+
+    #[test]
+    fn horde() {
+        let game = GameState::start(Variant::Horde);
+        assert_eq!(game.board(Sides::White, Piece::Pawn).0.count_ones(), 36);
+
+        let game = play("4k3/8/8/8/8/8/8/P7 w - - 0 1", Variant::Horde, &["a3"]);
+        assert_eq!(game.en_passant, Some(Square::A2));
+
+        let game = play("4k3/8/8/8/8/8/1q6/P7 b - - 0 1", Variant::Horde, &["Qxa1"]);
+        assert_eq!(game.outcome(), Some(Outcome::Variant(Some(Sides::Black))));
+    }
+
+    #[test]
+    fn racing_kings() {
+        let game = GameState::start(Variant::RacingKings);
+        assert_eq!(game.validate(), Ok(()));
+        assert!(game
+            .moves()
+            .iter()
+            .all(|m| !game.apply(m.clone()).in_check()));
+
+        // Black can still draw by reaching the eighth rank on its reply
+        let game = play(
+            "8/k5K1/8/8/8/8/8/8 w - - 0 1",
+            Variant::RacingKings,
+            &["Kg8"],
+        );
+        assert_eq!(game.outcome(), None);
+        let drawn = play(&game.fen(), Variant::RacingKings, &["Kb8"]);
+        assert_eq!(drawn.outcome(), Some(Outcome::Variant(None)));
+        let game = play(
+            "8/6K1/8/k7/8/8/8/8 w - - 0 1",
+            Variant::RacingKings,
+            &["Kg8"],
+        );
+        assert_eq!(game.outcome(), Some(Outcome::Variant(Some(Sides::White))));
+    }
+
+    #[test]
+    fn validation() {
+        let invalid = [
+            ("4k3/8/8/8/8/8/8/8 w - - 0 1", PositionError::Kings),
+            ("4k3/8/8/8/8/8/8/P3K3 w - - 0 1", PositionError::PawnRank),
+            (
+                "4k3/8/8/8/8/8/8/3KR3 w - - 0 1",
+                PositionError::OppositeCheck,
+            ),
+            ("4k3/8/8/8/4P3/8/8/4K3 b - e6 0 1", PositionError::EnPassant),
+        ];
+        for (fen, error) in invalid {
+            assert_eq!(GameState::from(fen.to_string()).validate(), Err(error));
+        }
+        assert_eq!(GameState::start(Variant::Horde).validate(), Ok(()));
+        let ep = GameState::from("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1".to_string());
+        assert_eq!(ep.validate(), Ok(()));
+    }
+
+    #[test]
+    fn three_check() {
+        let fen = "rnbqkbnr/ppp2ppp/8/3pp3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1+3 0 3";
+        let game = GameState::from(fen.to_string());
+        assert_eq!(game.variant, Variant::ThreeCheck);
+        assert_eq!(game.checks, [2, 0]);
+        let game = game.apply(game.parse_san("Bb5+").unwrap());
+        assert_eq!(game.outcome(), Some(Outcome::Variant(Some(Sides::White))));
+        assert_eq!(
+            game.fen(),
+            "rnbqkbnr/ppp2ppp/8/1B1pp3/4P3/8/PPPP1PPP/RNBQK1NR b KQkq - 0+3 1 3"
+        );
+    }
+}
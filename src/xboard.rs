@@ -0,0 +1,384 @@
+use crate::board::*;
+use crate::engine::{send, Engine, GoMode, Output};
+use crate::outcome::Outcome;
+use crate::search::{mate_in, Limits, SearchResult};
+use crate::timeman::TimeControl;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Scores are shown in centipawns, with mates as this plus the moves to mate
+const MATE_SCORE: i32 = 100000;
+
+/// Result command for a finished game, with the reason as the comment
+fn result(outcome: Outcome) -> String {
+    let reason = match outcome {
+        Outcome::Checkmate(Sides::White) => "White mates",
+        Outcome::Checkmate(Sides::Black) => "Black mates",
+        Outcome::Stalemate => "Stalemate",
+        Outcome::FiftyMoves => "Draw by fifty move rule",
+        Outcome::InsufficientMaterial => "Insufficient material",
+        Outcome::Variant(Some(Sides::White)) => "White wins",
+        Outcome::Variant(Some(Sides::Black)) => "Black wins",
+        Outcome::Variant(None) => "Draw",
+    };
+    format!("{} {{{reason}}}", outcome.result())
+}
+
+/// Thinking output: depth, score, time in centiseconds, nodes and the PV
+fn thinking(game: &GameState, result: &SearchResult) -> String {
+    let score = match mate_in(result.score) {
+        Some(moves) if moves > 0 => MATE_SCORE + moves,
+        Some(moves) => -MATE_SCORE + moves,
+        None => result.score,
+    };
+    let mut game = game.clone();
+    let mut pv = vec![];
+    for mov in &result.pv {
+        pv.push(game.san(mov));
+        game = game.apply(mov.clone());
+    }
+    format!(
+        "{} {} {} {} {}",
+        result.depth,
+        score,
+        result.time.as_millis() / 10,
+        result.nodes,
+        pv.join(" ")
+    )
+}
+
+/// Parses a `level` time such as `5` minutes or `2:30`
+fn parse_minutes(time: &str) -> Option<Duration> {
+    let (minutes, seconds) = time.split_once(':').unwrap_or((time, "0"));
+    let secs = minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Chess Engine Communication Protocol front-end, as spoken by XBoard and
+/// WinBoard. Unlike UCI the engine keeps track of the game itself and
+/// answers the opponent's moves whenever it plays the side to move.
+pub struct XBoard {
+    engine: Engine,
+    out: Output,
+    /// Side the engine plays, none in force mode where it only records moves
+    engine_side: Option<Sides>,
+    post: bool,
+    /// Moves per time control, increment and fixed time per move, from
+    /// `level` and `st`
+    moves_per_session: u32,
+    increment: Duration,
+    move_time: Option<Duration>,
+    depth: Option<u8>,
+    /// The engine's clock, as last given by `time`
+    clock: Duration,
+    /// Moves the search thread played, applied before the next command
+    played: Receiver<String>,
+    sender: Sender<String>,
+    /// Makes a stopped search drop its move instead of playing it
+    cancel: Arc<AtomicBool>,
+}
+
+impl XBoard {
+    pub fn new(out: Output) -> Self {
+        let (sender, played) = mpsc::channel();
+        XBoard {
+            engine: Engine::new(),
+            out,
+            engine_side: Some(Sides::Black),
+            post: false,
+            moves_per_session: 0,
+            increment: Duration::ZERO,
+            move_time: None,
+            depth: None,
+            clock: Duration::from_secs(300),
+            played,
+            sender,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn send(&self, msg: &str) {
+        send(&self.out, msg);
+    }
+
+    /// Records the moves the engine has played since the last command
+    fn sync(&mut self) {
+        while let Ok(mov) = self.played.try_recv() {
+            self.engine.play(&mov);
+        }
+    }
+
+    /// Stops thinking without playing the move found
+    fn abort(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.engine.stop();
+        self.cancel.store(false, Ordering::Relaxed);
+        self.sync();
+    }
+
+    /// Handles one line from the GUI, returning false once it asks to quit
+    pub fn handle(&mut self, line: &str) -> bool {
+        self.sync();
+        let mut tokens = line.split_whitespace();
+        let Some(command) = tokens.next() else {
+            return true;
+        };
+        let args: Vec<&str> = tokens.collect();
+        match command {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer"
+            | "otim" => {}
+            "protover" => {
+                self.send(concat!(
+                    "feature myname=\"rustle ",
+                    env!("CARGO_PKG_VERSION"),
+                    "\" ping=1 setboard=1 usermove=1 san=0 time=1 colors=0 sigint=0 \
+                     sigterm=0 reuse=1 analyze=0 variants=\"normal\" egt=\"syzygy\""
+                ));
+                self.send("feature option=\"EvalFile -file \"");
+                self.send("feature done=1");
+            }
+            "ping" => {
+                // Replies only once everything before it has been dealt with
+                self.engine.wait();
+                self.sync();
+                self.send(&format!("pong {}", args.first().unwrap_or(&"")));
+            }
+            "new" => {
+                self.abort();
+                self.engine.new_game();
+                self.engine_side = Some(Sides::Black);
+                self.depth = None;
+            }
+            "setboard" => {
+                self.abort();
+                match GameState::from_fen(&args.join(" ")) {
+                    Ok(game) => self.engine.set_position(game),
+                    Err(e) => self.send(&format!("tellusererror Illegal position: {e}")),
+                }
+            }
+            "force" | "result" => {
+                self.abort();
+                self.engine_side = None;
+            }
+            "go" => {
+                self.abort();
+                self.engine_side = Some(self.engine.game.turn);
+                self.think();
+            }
+            "?" => {
+                self.engine.stop();
+                self.sync();
+            }
+            "usermove" => {
+                self.abort();
+                let mov = args.first().copied().unwrap_or_default();
+                if !self.engine.play(mov) {
+                    self.send(&format!("Illegal move: {mov}"));
+                } else if self.engine_side == Some(self.engine.game.turn) {
+                    self.think();
+                }
+            }
+            "undo" | "remove" => {
+                self.abort();
+                let plies = if command == "undo" { 1 } else { 2 };
+                for _ in 0..plies {
+                    self.engine.undo();
+                }
+            }
+            "level" => match args.as_slice() {
+                [moves, base, inc] => {
+                    let mps = moves.parse().ok();
+                    let base = parse_minutes(base);
+                    let inc = inc.parse::<f64>().ok().filter(|i| *i >= 0.0);
+                    match (mps, base, inc) {
+                        (Some(mps), Some(base), Some(inc)) => {
+                            self.moves_per_session = mps;
+                            self.clock = base;
+                            self.increment = Duration::from_secs_f64(inc);
+                            self.move_time = None;
+                        }
+                        _ => self.send(&format!("Error (bad level): {line}")),
+                    }
+                }
+                _ => self.send(&format!("Error (bad level): {line}")),
+            },
+            "st" => match args.first().and_then(|s| s.parse::<f64>().ok()) {
+                Some(secs) if secs > 0.0 => self.move_time = Some(Duration::from_secs_f64(secs)),
+                _ => self.send(&format!("Error (bad time): {line}")),
+            },
+            "sd" => match args.first().and_then(|d| d.parse().ok()) {
+                Some(depth) => self.depth = Some(depth),
+                None => self.send(&format!("Error (bad depth): {line}")),
+            },
+            "time" => match args.first().and_then(|t| t.parse::<i64>().ok()) {
+                // Centiseconds, which can be negative once the flag has fallen
+                Some(cs) => self.clock = Duration::from_millis(cs.max(0) as u64 * 10),
+                None => self.send(&format!("Error (bad time): {line}")),
+            },
+            "egtpath" => match args.split_first() {
+                Some((&"syzygy", paths)) => {
+                    self.abort();
+                    if let Err(e) = self.engine.set_tablebases(&paths.join(" ")) {
+                        self.send(&format!("tellusererror Tablebases not found: {e}"));
+                    }
+                }
+                _ => self.send(&format!("Error (unsupported tablebases): {line}")),
+            },
+            "option" => match args.join(" ").split_once('=') {
+                Some(("EvalFile", path)) => {
+                    self.abort();
+                    if let Err(e) = self.engine.set_eval_file(path) {
+                        self.send(&format!("tellusererror {e}"));
+                    }
+                }
+                _ => self.send(&format!("Error (unknown option): {line}")),
+            },
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "quit" => {
+                self.abort();
+                return false;
+            }
+            _ => self.send(&format!("Error (unknown command): {command}")),
+        }
+        true
+    }
+
+    /// Searches for the engine's move, which is sent and played when found
+    fn think(&mut self) {
+        if let Some(outcome) = self.engine.game.outcome() {
+            return self.send(&result(outcome));
+        }
+        let game = &self.engine.game;
+        let clock = match self.move_time {
+            Some(time) => TimeControl::MoveTime(time),
+            None => TimeControl::Clock {
+                remaining: self.clock,
+                increment: self.increment,
+                moves_to_go: (self.moves_per_session > 0).then(|| {
+                    self.moves_per_session - (game.fullmoves - 1) % self.moves_per_session
+                }),
+            },
+        };
+        let limits = Limits {
+            depth: self.depth,
+            clock: Some(clock),
+            ..Default::default()
+        };
+
+        let (out, post, root) = (self.out.clone(), self.post, game.clone());
+        let report = Box::new(move |result: &SearchResult| {
+            if post {
+                send(&out, &thinking(&root, result));
+            }
+        });
+        let (out, root) = (self.out.clone(), game.clone());
+        let (sender, cancel) = (self.sender.clone(), self.cancel.clone());
+        self.engine
+            .go(limits, GoMode::Normal, report, move |found| {
+                let Some(best) = found.best.filter(|_| !cancel.load(Ordering::Relaxed)) else {
+                    return;
+                };
+                // Recorded before it is sent, so it is in place for the reply
+                let _ = sender.send(root.uci(&best));
+                send(&out, &format!("move {}", root.uci(&best)));
+                if let Some(outcome) = root.apply(best).outcome() {
+                    send(&out, &result(outcome));
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::testing::Buffer;
+
+    /// A session past the handshake, searching to depth 3 with thinking
+    /// output
+    fn xboard() -> (XBoard, Buffer) {
+        let buffer = Buffer::default();
+        let mut xboard = XBoard::new(buffer.output());
+        for command in [
+            "xboard",
+            "protover 2",
+            "new",
+            "level 40 5 0",
+            "post",
+            "sd 3",
+        ] {
+            assert!(xboard.handle(command));
+        }
+        (xboard, buffer)
+    }
+
+    #[test]
+    fn handshake_and_options() {
+        let (mut xboard, buffer) = xboard();
+        let reply = buffer.take();
+        assert!(reply.contains("usermove=1"));
+        assert!(reply.ends_with("feature done=1\n"));
+        xboard.handle("option EvalFile=/nonexistent/net.nnue");
+        assert!(buffer
+            .take()
+            .starts_with("tellusererror Network read failed"));
+        xboard.handle("option EvalFile=");
+        assert!(buffer.take().is_empty());
+        assert!(!xboard.handle("quit"));
+    }
+
+    #[test]
+    fn moves_and_go() {
+        let (mut xboard, buffer) = xboard();
+        buffer.take();
+        // The engine plays Black and answers each move
+        xboard.handle("time 30000");
+        xboard.handle("usermove e2e4");
+        xboard.handle("ping 1");
+        let reply = buffer.take();
+        assert!(reply.lines().any(|l| l.starts_with("3 ")));
+        assert!(reply.contains("move "));
+        assert!(reply.ends_with("pong 1\n"));
+        assert_eq!(xboard.engine.history.len(), 2);
+
+        xboard.handle("usermove e2e4");
+        assert!(buffer.take().starts_with("Illegal move: e2e4"));
+
+        // Force mode only records moves, and undo takes them back
+        xboard.handle("force");
+        xboard.handle("usermove d2d4");
+        assert_eq!(xboard.engine.history.len(), 3);
+        xboard.handle("remove");
+        xboard.handle("undo");
+        assert_eq!(xboard.engine.history.len(), 0);
+
+        xboard.handle("setboard 7k/6pp/8/3q4/8/8/3R4/3RK3 w - - 0 1");
+        xboard.handle("go");
+        xboard.handle("ping 2");
+        assert!(buffer.take().contains("move d2d5\n"));
+
+        // A mate ends the game with its result
+        xboard.handle("setboard 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        xboard.handle("go");
+        xboard.handle("ping 3");
+        assert!(buffer.take().contains("move a1a8\n1-0 {White mates}"));
+    }
+
+    #[test]
+    fn move_now() {
+        let (mut xboard, buffer) = xboard();
+        buffer.take();
+        // With no limit in sight, the engine moves only when told to
+        xboard.handle("sd 100");
+        xboard.handle("st 1000");
+        xboard.handle("go");
+        assert!(xboard.engine.is_searching());
+        xboard.handle("?");
+        xboard.handle("ping 1");
+        let reply = buffer.take();
+        assert!(reply.contains("move "));
+        assert!(reply.ends_with("pong 1\n"));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::board::*;
+
+/// Most pieces of one kind a Crazyhouse pocket can hold
+const MAX_POCKET: usize = 16;
+
+struct Keys {
+    pieces: [[[u64; 64]; 6]; 2],
+    turn: u64,
+    castle: [u64; 16],
+    en_passant: [u64; 8],
+    pockets: [[[u64; MAX_POCKET + 1]; 6]; 2],
+    checks: [[u64; 4]; 2],
+    /// Crazyhouse promoted pieces, by square
+    promoted: [u64; 64],
+}
+
+const fn splitmix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const KEYS: Keys = {
+    let mut state = 0x5275_7374_6C65u64;
+    let mut keys = Keys {
+        pieces: [[[0; 64]; 6]; 2],
+        turn: 0,
+        castle: [0; 16],
+        en_passant: [0; 8],
+        pockets: [[[0; MAX_POCKET + 1]; 6]; 2],
+        checks: [[0; 4]; 2],
+        promoted: [0; 64],
+    };
+    let mut side = 0;
+    while side < 2 {
+        let mut piece = 0;
+        while piece < 6 {
+            let mut sq = 0;
+            while sq < 64 {
+                keys.pieces[side][piece][sq] = splitmix(&mut state);
+                sq += 1;
+            }
+            // A count of zero keeps the key of an empty pocket at zero
+            let mut count = 1;
+            while count <= MAX_POCKET {
+                keys.pockets[side][piece][count] = splitmix(&mut state);
+                count += 1;
+            }
+            piece += 1;
+        }
+        let mut checks = 1;
+        while checks < 4 {
+            keys.checks[side][checks] = splitmix(&mut state);
+            checks += 1;
+        }
+        side += 1;
+    }
+    keys.turn = splitmix(&mut state);
+    let mut i = 1;
+    while i < 16 {
+        keys.castle[i] = splitmix(&mut state);
+        i += 1;
+    }
+    let mut file = 0;
+    while file < 8 {
+        keys.en_passant[file] = splitmix(&mut state);
+        file += 1;
+    }
+    let mut sq = 0;
+    while sq < 64 {
+        keys.promoted[sq] = splitmix(&mut state);
+        sq += 1;
+    }
+    keys
+};
+
+impl GameState {
+    /// Zobrist hash of everything that makes two positions the same for
+    /// repetitions and the transposition table. It is computed from scratch
+    /// rather than updated in `apply`, where explosions, drops and captures
+    /// going to the pockets would each need their keys kept in step: a few
+    /// dozen XORs cost little next to the move generation at every node.
+    pub fn hash(&self) -> u64 {
+        let mut hash = 0;
+        for side in [Sides::White, Sides::Black] {
+            for piece in PIECES {
+                let mut board = self.board(side, piece).0;
+                while board != 0 {
+                    let sq = board.trailing_zeros() as usize;
+                    hash ^= KEYS.pieces[side as usize][piece as usize][sq];
+                    board &= board - 1;
+                }
+                let count = (self.pocket(side, piece) as usize).min(MAX_POCKET);
+                hash ^= KEYS.pockets[side as usize][piece as usize][count];
+            }
+            let checks = (self.checks[side as usize] as usize).min(3);
+            hash ^= KEYS.checks[side as usize][checks];
+        }
+        let mut promoted = self.promoted.0;
+        while promoted != 0 {
+            hash ^= KEYS.promoted[promoted.trailing_zeros() as usize];
+            promoted &= promoted - 1;
+        }
+        if self.turn == Sides::Black {
+            hash ^= KEYS.turn;
+        }
+        hash ^= KEYS.castle[self.castle_rights as usize & 0b1111];
+        if let Some(sq) = self.en_passant {
+            hash ^= KEYS.en_passant[sq.file() as usize];
+        }
+        hash
+    }
+}